@@ -0,0 +1,410 @@
+//! The open/close lifecycle of a PayTube channel.
+//!
+//! ```text
+//!
+//!   open(participants, deposits)    deposits are verified on-chain
+//!             |
+//!   process_paytube_transfers(..)   executed in the channel, limited to
+//!             |                     what each participant has deposited
+//!   close()                         net balances settled to the base chain,
+//!                                   delegations released
+//! ```
+//!
+//! Participants lock up their deposits before the channel is opened, using
+//! the same escrow as game stakes: SOL is moved into a deposit account
+//! controlled by the channel's authority, SPL tokens are delegated to it.
+
+use {
+    crate::{
+        escrow::StakeEscrow,
+        fees::{split_cost, ChannelFees},
+        storage::StoredSession,
+        token::MintInfo,
+        transaction::PayTubeTransaction,
+    },
+    solana_sdk::{
+        account::AccountSharedData,
+        instruction::{Instruction as SolanaInstruction, InstructionError},
+        pubkey::Pubkey,
+        rent::Rent,
+        system_program,
+        transaction::{self, TransactionError},
+    },
+    std::collections::{HashMap, HashSet},
+};
+
+/// `InstructionError::Custom` code of a transfer from or to an account that
+/// isn't a participant of the open channel. It's outside the codes of the
+/// system and token programs, which execute transfers.
+pub const NOT_A_PARTICIPANT: u32 = 0x1000;
+
+/// Funds a participant brings into a channel.
+///
+/// A `None` value for `mint` represents native SOL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelDeposit {
+    pub owner: Pubkey,
+    pub mint: Option<Pubkey>,
+    pub amount: u64,
+}
+
+/// An open channel: its participants, their deposits and their balances
+/// within the channel.
+#[derive(Clone)]
+pub(crate) struct ChannelSession {
+    authority: Pubkey,
+    participants: HashSet<Pubkey>,
+    deposits: HashMap<(Pubkey, Option<Pubkey>), u64>,
+    balances: HashMap<(Pubkey, Option<Pubkey>), u64>,
+    /// The mints of the token deposits.
+    mints: HashMap<Pubkey, MintInfo>,
+    fees: Option<ChannelFees>,
+}
+
+impl ChannelSession {
+    /// The escrow holding the channel's deposits in `mint`.
+    pub fn escrow(authority: &Pubkey, mint: Option<&MintInfo>) -> StakeEscrow {
+        match mint {
+            Some(mint) => StakeEscrow::token(*authority, mint.clone()),
+            None => StakeEscrow::sol(*authority),
+        }
+    }
+
+    /// The escrow holding this session's deposits in `mint`.
+    fn deposit_escrow(&self, mint: Option<Pubkey>) -> Result<StakeEscrow, String> {
+        let mint = match mint {
+            Some(mint) => Some(
+                self.mints
+                    .get(&mint)
+                    .ok_or_else(|| format!("No deposits in {}", mint))?,
+            ),
+            None => None,
+        };
+        Ok(Self::escrow(&self.authority, mint))
+    }
+
+    /// Open a session, verifying every deposit with `load_account`.
+    pub fn open(
+        authority: Pubkey,
+        participants: &[Pubkey],
+        deposits: &[ChannelDeposit],
+        fees: Option<ChannelFees>,
+        load_account: impl Fn(&Pubkey) -> Option<AccountSharedData>,
+    ) -> Result<Self, String> {
+        let participants: HashSet<Pubkey> = participants.iter().cloned().collect();
+
+        let mut totals: HashMap<(Pubkey, Option<Pubkey>), u64> = HashMap::new();
+        for deposit in deposits {
+            if !participants.contains(&deposit.owner) {
+                return Err(format!("{} is not a participant", deposit.owner));
+            }
+            *totals.entry((deposit.owner, deposit.mint)).or_default() += deposit.amount;
+        }
+        let mints = MintInfo::load_all(
+            totals
+                .keys()
+                .filter_map(|(_, mint)| *mint)
+                .collect::<HashSet<_>>(),
+            &load_account,
+        )?;
+        for ((owner, mint), amount) in &totals {
+            let escrow = Self::escrow(&authority, mint.map(|mint| &mints[&mint]));
+            let address = escrow.deposit_address(&authority, owner);
+            let account =
+                load_account(&address).ok_or_else(|| format!("No deposit found for {}", owner))?;
+            escrow.verify_deposit(owner, &account, *amount)?;
+        }
+
+        Ok(Self {
+            authority,
+            participants,
+            balances: totals.clone(),
+            deposits: totals,
+            mints,
+            fees,
+        })
+    }
+
+    /// The session as persisted by the channel.
+    pub fn to_stored(&self) -> StoredSession {
+        let mut participants: Vec<Pubkey> = self.participants.iter().copied().collect();
+        participants.sort();
+        let entries = |amounts: &HashMap<(Pubkey, Option<Pubkey>), u64>| {
+            let mut entries: Vec<(Pubkey, Option<Pubkey>, u64)> = amounts
+                .iter()
+                .map(|((owner, mint), amount)| (*owner, *mint, *amount))
+                .collect();
+            entries.sort();
+            entries
+        };
+        StoredSession {
+            authority: self.authority,
+            participants,
+            deposits: entries(&self.deposits),
+            balances: entries(&self.balances),
+            fees: self.fees.clone(),
+        }
+    }
+
+    /// Restore a persisted session, loading the mints of its deposits with
+    /// `load_account`.
+    pub fn restore(
+        stored: StoredSession,
+        load_account: impl Fn(&Pubkey) -> Option<AccountSharedData>,
+    ) -> Result<Self, String> {
+        let amounts = |entries: Vec<(Pubkey, Option<Pubkey>, u64)>| {
+            entries
+                .into_iter()
+                .map(|(owner, mint, amount)| ((owner, mint), amount))
+                .collect::<HashMap<_, _>>()
+        };
+        let deposits = amounts(stored.deposits);
+        let mints = MintInfo::load_all(
+            deposits
+                .keys()
+                .filter_map(|(_, mint)| *mint)
+                .collect::<HashSet<_>>(),
+            &load_account,
+        )?;
+        Ok(Self {
+            authority: stored.authority,
+            participants: stored.participants.into_iter().collect(),
+            deposits,
+            balances: amounts(stored.balances),
+            mints,
+            fees: stored.fees,
+        })
+    }
+
+    /// Check a batch of transfers against the participants' balances, on
+    /// top of the `check_results` of the batch's pre-checks.
+    ///
+    /// Transfers are checked in order, so a transfer may spend funds received
+    /// earlier in the same batch.
+    pub fn check_transfers<T>(
+        &self,
+        transactions: &[PayTubeTransaction],
+        check_results: &mut [transaction::Result<T>],
+    ) {
+        let mut balances = self.balances.clone();
+        for (transaction, check_result) in transactions.iter().zip(check_results.iter_mut()) {
+            if check_result.is_err() {
+                continue;
+            }
+            match self.check_transfer(&balances, transaction) {
+                Ok(()) => Self::transfer(&mut balances, transaction, self.fee(transaction)),
+                Err(e) => *check_result = Err(e),
+            }
+        }
+    }
+
+    fn check_transfer(
+        &self,
+        balances: &HashMap<(Pubkey, Option<Pubkey>), u64>,
+        transaction: &PayTubeTransaction,
+    ) -> transaction::Result<()> {
+        if !self.participants.contains(&transaction.from)
+            || !self.participants.contains(&transaction.to)
+        {
+            return Err(TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(NOT_A_PARTICIPANT),
+            ));
+        }
+        let balance = balances
+            .get(&(transaction.from, transaction.mint))
+            .copied()
+            .unwrap_or_default();
+        let fee = self.fee(transaction).map_or(0, |(_, fee)| fee);
+        if balance < transaction.amount.saturating_add(fee) {
+            return Err(TransactionError::InstructionError(
+                0,
+                InstructionError::InsufficientFunds,
+            ));
+        }
+        Ok(())
+    }
+
+    /// The fee charged on a transfer, and the account it's paid to.
+    fn fee(&self, transaction: &PayTubeTransaction) -> Option<(Pubkey, u64)> {
+        let fees = self.fees.as_ref()?;
+        let fee = fees.fee(&transaction.from, transaction.amount);
+        (fee > 0).then_some((fees.operator, fee))
+    }
+
+    fn transfer(
+        balances: &mut HashMap<(Pubkey, Option<Pubkey>), u64>,
+        transaction: &PayTubeTransaction,
+        fee: Option<(Pubkey, u64)>,
+    ) {
+        let (operator, fee) = fee.unwrap_or_default();
+        *balances
+            .get_mut(&(transaction.from, transaction.mint))
+            .unwrap() -= transaction.amount + fee;
+        *balances
+            .entry((transaction.to, transaction.mint))
+            .or_default() += transaction.amount;
+        if fee > 0 {
+            *balances.entry((operator, transaction.mint)).or_default() += fee;
+        }
+    }
+
+    /// Record a transfer that was executed in the channel.
+    pub fn apply_transfer(&mut self, transaction: &PayTubeTransaction) -> transaction::Result<()> {
+        self.check_transfer(&self.balances, transaction)?;
+        let fee = self.fee(transaction);
+        Self::transfer(&mut self.balances, transaction, fee);
+        Ok(())
+    }
+
+    /// Whether the participants share the base chain cost of settling.
+    pub fn shares_settlement_costs(&self) -> bool {
+        self.fees
+            .as_ref()
+            .is_some_and(|fees| fees.share_settlement_costs)
+    }
+
+    /// Charge `cost` lamports of base chain fees to the participants' SOL
+    /// balances, split evenly, reimbursing the authority that pays them.
+    pub fn charge_settlement_cost(&mut self, cost: u64) {
+        let mut participants: Vec<(Pubkey, u64)> = self
+            .participants
+            .iter()
+            .map(|participant| (*participant, self.balance(participant, None)))
+            .collect();
+        participants.sort();
+        let mut charged = 0;
+        for (participant, share) in split_cost(cost, &participants) {
+            if share > 0 {
+                *self.balances.get_mut(&(participant, None)).unwrap() -= share;
+                charged += share;
+            }
+        }
+        if charged > 0 {
+            *self.balances.entry((self.authority, None)).or_default() += charged;
+        }
+    }
+
+    pub fn balance(&self, owner: &Pubkey, mint: Option<Pubkey>) -> u64 {
+        self.balances
+            .get(&(*owner, mint))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The accounts `transactions` are executed against, in place of the
+    /// base chain's: a participant's wallet holds their SOL balance in the
+    /// channel, on top of its rent exemption, and their token accounts hold
+    /// their token balances. Token accounts are loaded with `load_account`,
+    /// or made up if they don't exist yet.
+    ///
+    /// Accounts that aren't the participant's token accounts are left as
+    /// they are, for the pre-checks to reject.
+    pub fn virtual_accounts(
+        &self,
+        transactions: &[PayTubeTransaction],
+        mints: &HashMap<Pubkey, MintInfo>,
+        load_account: impl Fn(&Pubkey) -> Option<AccountSharedData>,
+    ) -> Result<Vec<(Pubkey, AccountSharedData)>, String> {
+        let rent = Rent::default();
+        let mut accounts = HashMap::new();
+        for transaction in transactions {
+            let parties = [&transaction.from, &transaction.to]
+                .into_iter()
+                .filter(|owner| self.participants.contains(owner));
+            let Some(mint) = transaction.mint else {
+                for owner in parties {
+                    let lamports = self.balance(owner, None) + rent.minimum_balance(0);
+                    accounts.insert(
+                        *owner,
+                        AccountSharedData::new(lamports, 0, &system_program::id()),
+                    );
+                }
+                continue;
+            };
+            let Some(mint_info) = mints.get(&mint) else {
+                continue;
+            };
+            let (source, destination) = transaction.token_accounts(mint_info);
+            for owner in parties {
+                let address = if *owner == transaction.from {
+                    source
+                } else {
+                    destination
+                };
+                let account = load_account(&address);
+                if account
+                    .as_ref()
+                    .is_some_and(|account| mint_info.check_token_account(owner, account).is_err())
+                {
+                    continue;
+                }
+                let account = mint_info.token_account_holding(
+                    owner,
+                    account,
+                    self.balance(owner, Some(mint)),
+                )?;
+                accounts.insert(address, account);
+            }
+        }
+        Ok(accounts.into_iter().collect())
+    }
+
+    /// Instructions paying out the final balances from the deposits.
+    ///
+    /// `held` returns the amount actually held in a SOL deposit account, so
+    /// anything deposited beyond the declared amount is returned as well.
+    pub fn close_instructions(
+        &self,
+        held: impl Fn(&Pubkey) -> Result<u64, String>,
+    ) -> Result<Vec<SolanaInstruction>, String> {
+        let mints: HashSet<Option<Pubkey>> = self
+            .balances
+            .keys()
+            .chain(self.deposits.keys())
+            .map(|(_, mint)| *mint)
+            .collect();
+
+        let mut instructions = Vec::new();
+        for mint in mints {
+            let escrow = self.deposit_escrow(mint)?;
+            let mut deposits = Vec::new();
+            let mut payouts: HashMap<Pubkey, u64> = HashMap::new();
+            for ((owner, _), amount) in self.deposits.iter().filter(|((_, m), _)| *m == mint) {
+                let amount = match mint {
+                    Some(_) => *amount,
+                    None => {
+                        let held = held(&escrow.deposit_address(&self.authority, owner))?;
+                        *payouts.entry(*owner).or_default() += held.saturating_sub(*amount);
+                        held
+                    }
+                };
+                deposits.push((*owner, amount));
+            }
+            for ((owner, _), amount) in self.balances.iter().filter(|((_, m), _)| *m == mint) {
+                *payouts.entry(*owner).or_default() += amount;
+            }
+            let payouts: Vec<(Pubkey, u64)> = payouts.into_iter().collect();
+            instructions.extend(escrow.release_instructions(
+                &self.authority,
+                &deposits,
+                &payouts,
+            )?);
+        }
+        Ok(instructions)
+    }
+
+    /// Instructions revoking the channel's token delegations. These must be
+    /// signed by each delegating owner.
+    pub fn revoke_instructions(&self) -> Result<Vec<SolanaInstruction>, String> {
+        self.deposits
+            .keys()
+            .filter_map(|(owner, mint)| mint.map(|mint| (owner, mint)))
+            .map(|(owner, mint)| {
+                let escrow = self.deposit_escrow(Some(mint))?;
+                let mint = &self.mints[&mint];
+                mint.revoke_instruction(&escrow.deposit_address(&self.authority, owner), owner)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,149 @@
+//! A channel transaction for any program.
+//!
+//! A `PayTubeTransaction` can only move SOL or tokens, and programs such as
+//! Rock-Paper-Scissors would otherwise need their own transaction format and
+//! settler. A `ChannelTransaction` wraps arbitrary Solana instructions
+//! instead: they're executed by the SVM in the channel, in order and
+//! atomically, then settled by replaying them on the base chain.
+//!
+//! Unlike PayTube transactions, channel transactions carry signatures: every
+//! account an instruction marks as a signer must sign the transaction's
+//! `message_data` before the channel executes it. The channel only signs the
+//! settlement for keys that signed the transaction itself, and the settlement
+//! is a new message on a real blockhash, so signers whose keys the channel
+//! doesn't hold sign it again.
+
+use {
+    crate::transaction_two::RpsTransaction,
+    solana_sdk::{
+        instruction::Instruction as SolanaInstruction,
+        message::Message,
+        pubkey::Pubkey,
+        signature::Signature,
+        signer::Signer,
+        transaction::{
+            SanitizedTransaction as SolanaSanitizedTransaction, Transaction as SolanaTransaction,
+            TransactionError,
+        },
+    },
+    std::collections::HashSet,
+};
+
+/// Instructions for any programs, executed together and paid for by
+/// `fee_payer`.
+#[derive(Debug, Clone)]
+pub struct ChannelTransaction {
+    pub instructions: Vec<SolanaInstruction>,
+    pub fee_payer: Pubkey,
+    /// The signature of each of `signers`, in order, once given.
+    signatures: Vec<Option<Signature>>,
+}
+
+impl ChannelTransaction {
+    pub fn new(instructions: Vec<SolanaInstruction>, fee_payer: Pubkey) -> Self {
+        Self {
+            instructions,
+            fee_payer,
+            signatures: Vec::new(),
+        }
+    }
+
+    fn message(&self) -> Message {
+        Message::new(&self.instructions, Some(&self.fee_payer))
+    }
+
+    /// The bytes each signer signs.
+    pub fn message_data(&self) -> Vec<u8> {
+        self.message().serialize()
+    }
+
+    /// Every key that must sign the transaction, fee payer first.
+    pub fn signers(&self) -> Vec<Pubkey> {
+        let message = self.message();
+        message.account_keys[..message.header.num_required_signatures as usize].to_vec()
+    }
+
+    /// The signers without a valid signature, e.g. because the instructions
+    /// changed after they signed.
+    pub fn missing_signers(&self) -> Vec<Pubkey> {
+        let message_data = self.message_data();
+        self.signers()
+            .into_iter()
+            .enumerate()
+            .filter(|(index, signer)| {
+                !matches!(
+                    self.signatures.get(*index),
+                    Some(Some(signature)) if signature.verify(signer.as_ref(), &message_data)
+                )
+            })
+            .map(|(_, signer)| signer)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing_signers().is_empty()
+    }
+
+    /// Add a signature produced elsewhere, after checking it's valid.
+    pub fn add_signature(&mut self, signer: &Pubkey, signature: Signature) -> Result<(), String> {
+        let signers = self.signers();
+        let position = signers
+            .iter()
+            .position(|key| key == signer)
+            .ok_or_else(|| format!("{} does not sign this transaction", signer))?;
+        if !signature.verify(signer.as_ref(), &self.message_data()) {
+            return Err(format!("Invalid signature from {}", signer));
+        }
+        self.signatures.resize(signers.len(), None);
+        self.signatures[position] = Some(signature);
+        Ok(())
+    }
+
+    /// Sign with `signer`, if the transaction needs it.
+    pub fn sign(&mut self, signer: &dyn Signer) -> Result<bool, String> {
+        let pubkey = signer.try_pubkey().map_err(|e| e.to_string())?;
+        if !self.signers().contains(&pubkey) {
+            return Ok(false);
+        }
+        let signature = signer
+            .try_sign_message(&self.message_data())
+            .map_err(|e| e.to_string())?;
+        self.add_signature(&pubkey, signature)?;
+        Ok(true)
+    }
+}
+
+impl From<&RpsTransaction> for ChannelTransaction {
+    fn from(value: &RpsTransaction) -> Self {
+        Self::new(vec![SolanaInstruction::from(value)], value.player)
+    }
+}
+
+impl From<&ChannelTransaction> for SolanaTransaction {
+    fn from(value: &ChannelTransaction) -> Self {
+        SolanaTransaction::new_with_payer(&value.instructions, Some(&value.fee_payer))
+    }
+}
+
+impl TryFrom<&ChannelTransaction> for SolanaSanitizedTransaction {
+    type Error = TransactionError;
+
+    fn try_from(value: &ChannelTransaction) -> Result<Self, Self::Error> {
+        SolanaSanitizedTransaction::try_from_legacy_transaction(
+            SolanaTransaction::from(value),
+            &HashSet::new(),
+        )
+    }
+}
+
+/// Create a batch of Solana transactions, for the Solana SVM's transaction
+/// processor, from a batch of channel transactions. Malformed transactions
+/// are replaced by the error they were rejected with.
+pub fn create_svm_transactions(
+    channel_transactions: &[ChannelTransaction],
+) -> Vec<Result<SolanaSanitizedTransaction, TransactionError>> {
+    channel_transactions
+        .iter()
+        .map(SolanaSanitizedTransaction::try_from)
+        .collect()
+}
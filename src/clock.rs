@@ -0,0 +1,104 @@
+//! Time sources for game and channel timestamps.
+//!
+//! Game creation, moves, stakes and timeouts all need the current time.
+//! Rather than reading the system time directly, the `GameStateManager` and
+//! game engines are given a `Clock`, so tests can control time and channels
+//! can follow the base chain's clock - the same clock on-chain adjudication
+//! would use.
+
+use {
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        account::from_account,
+        clock::{Clock as ClockSysvar, Slot},
+        sysvar,
+    },
+    std::sync::atomic::{AtomicU64, Ordering},
+};
+
+/// A source of the current time.
+pub trait Clock: Send + Sync {
+    /// Seconds since the unix epoch.
+    fn unix_timestamp(&self) -> Result<u64, String>;
+
+    /// The current slot, for clocks that follow a chain.
+    fn slot(&self) -> Result<Option<Slot>, String> {
+        Ok(None)
+    }
+}
+
+/// The local system time.
+#[derive(Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn unix_timestamp(&self) -> Result<u64, String> {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// A clock that only moves when told to. Useful for tests.
+#[derive(Default)]
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(now: u64) -> Self {
+        Self {
+            now: AtomicU64::new(now),
+        }
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn unix_timestamp(&self) -> Result<u64, String> {
+        Ok(self.now.load(Ordering::SeqCst))
+    }
+}
+
+/// The base chain's `Clock` sysvar, read over RPC.
+///
+/// Fails if the sysvar can't be fetched, rather than mixing in the local
+/// time, so timestamps always agree with the chain.
+pub struct ChainClock {
+    rpc_client: RpcClient,
+}
+
+impl ChainClock {
+    pub fn new(rpc_client: RpcClient) -> Self {
+        Self { rpc_client }
+    }
+
+    /// Fetch the `Clock` sysvar from the base chain.
+    pub fn sysvar(&self) -> Result<ClockSysvar, String> {
+        let account = self
+            .rpc_client
+            .get_account(&sysvar::clock::id())
+            .map_err(|e| e.to_string())?;
+        from_account::<ClockSysvar, _>(&account)
+            .ok_or_else(|| "Invalid clock sysvar account".to_string())
+    }
+}
+
+impl Clock for ChainClock {
+    fn unix_timestamp(&self) -> Result<u64, String> {
+        self.sysvar()
+            .map(|clock| clock.unix_timestamp.max(0) as u64)
+    }
+
+    fn slot(&self) -> Result<Option<Slot>, String> {
+        self.sysvar().map(|clock| Some(clock.slot))
+    }
+}
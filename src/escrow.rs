@@ -0,0 +1,260 @@
+//! On-chain escrow backing the stakes committed to a game.
+//!
+//! A stake recorded in a `GameState` is only worth something if the player
+//! can't spend the funds on the base chain before the game is settled. Before
+//! committing a stake, a player locks it up in one of two ways:
+//!
+//! * SOL: the stake is deposited into an account derived from the channel's
+//!   escrow authority (`create_with_seed`), one per game and player. Only the
+//!   authority can move funds out of it.
+//! * SPL tokens: the player delegates the stake on their token account to the
+//!   escrow authority, in the spirit of delegating a token account to the
+//!   PayTube on-chain program. The token account, delegation and transfers
+//!   go through the mint's own program, SPL Token or Token-2022.
+//!
+//! The channel verifies the escrow through its account loader when the stake
+//! is committed, and distributes the escrowed funds according to the game's
+//! payouts at settlement.
+//!
+//! `PayTubeChannel` deposits are escrowed the same way, scoped to the channel
+//! rather than to a game.
+
+use {
+    crate::{game_traits::PlayerId, token::MintInfo},
+    solana_sdk::{
+        account::{AccountSharedData, ReadableAccount},
+        hash::hashv,
+        instruction::Instruction,
+        program_option::COption,
+        pubkey::Pubkey,
+        system_instruction, system_program,
+    },
+    spl_token_2022::{
+        extension::StateWithExtensions,
+        state::{Account as TokenAccount, AccountState},
+    },
+    std::collections::HashMap,
+};
+
+/// Where the stakes of a channel's games are held.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StakeEscrow {
+    /// Controls the escrowed funds. Its keypair must be one of the channel's
+    /// keys for games to be settled.
+    pub authority: Pubkey,
+    /// Stakes are in this mint's tokens instead of SOL.
+    pub mint: Option<MintInfo>,
+}
+
+impl StakeEscrow {
+    pub fn sol(authority: Pubkey) -> Self {
+        Self {
+            authority,
+            mint: None,
+        }
+    }
+
+    pub fn token(authority: Pubkey, mint: MintInfo) -> Self {
+        Self {
+            authority,
+            mint: Some(mint),
+        }
+    }
+
+    /// Seed of a player's SOL deposit account for a game or channel
+    /// (`scope`). Seeds are limited to 32 bytes, so the scope and player are
+    /// hashed down to fit.
+    pub fn deposit_seed(scope: &Pubkey, player_id: &PlayerId) -> String {
+        let hash = hashv(&[b"stake", scope.as_ref(), player_id.as_ref()]);
+        hash.as_ref()[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// The account holding a player's stake for a game or channel.
+    pub fn deposit_address(&self, scope: &Pubkey, player_id: &PlayerId) -> Pubkey {
+        match &self.mint {
+            Some(mint) => mint.token_account(player_id),
+            None => Pubkey::create_with_seed(
+                &self.authority,
+                &Self::deposit_seed(scope, player_id),
+                &system_program::id(),
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Instructions, signed by the player, that lock up their stake.
+    ///
+    /// Token delegations replace any previous delegation, so `amount` must
+    /// cover all of the player's open stakes in this mint.
+    pub fn deposit_instructions(
+        &self,
+        scope: &Pubkey,
+        player_id: &PlayerId,
+        amount: u64,
+    ) -> Result<Vec<Instruction>, String> {
+        let deposit = self.deposit_address(scope, player_id);
+        match &self.mint {
+            Some(mint) => Ok(vec![mint.approve_instruction(
+                &deposit,
+                &self.authority,
+                player_id,
+                amount,
+            )?]),
+            None => Ok(vec![system_instruction::transfer(
+                player_id, &deposit, amount,
+            )]),
+        }
+    }
+
+    /// Check that `account`, the player's deposit account, backs `amount`.
+    pub fn verify_deposit(
+        &self,
+        player_id: &PlayerId,
+        account: &AccountSharedData,
+        amount: u64,
+    ) -> Result<(), String> {
+        match &self.mint {
+            Some(mint) => {
+                if account.owner() != &mint.program_id {
+                    return Err("Stake account is not a token account".to_string());
+                }
+                mint.check_token_account(player_id, account)
+                    .map_err(|_| "Stake account does not belong to the player".to_string())?;
+                let token_account = StateWithExtensions::<TokenAccount>::unpack(account.data())
+                    .map_err(|e| e.to_string())?
+                    .base;
+                if token_account.state != AccountState::Initialized {
+                    return Err("Stake account is frozen".to_string());
+                }
+                if token_account.delegate != COption::Some(self.authority) {
+                    return Err("Stake is not delegated to the escrow".to_string());
+                }
+                if token_account.delegated_amount < amount || token_account.amount < amount {
+                    return Err(format!("Escrowed stake is less than {}", amount));
+                }
+                Ok(())
+            }
+            None => {
+                if account.owner() != &system_program::id() {
+                    return Err("Stake account is not a system account".to_string());
+                }
+                if account.lamports() < amount {
+                    return Err(format!("Escrowed stake is less than {}", amount));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// How each of `payouts`, the amounts owed to each player, is funded from
+    /// `deposits`, the amounts held for each player: the `(from, amount)`
+    /// draws on the deposits for every payout, in order. Payouts are funded
+    /// from the winner's own deposit first, then from the others.
+    pub fn fund_payouts(
+        deposits: &[(PlayerId, u64)],
+        payouts: &[(PlayerId, u64)],
+    ) -> Result<Vec<Vec<(PlayerId, u64)>>, String> {
+        let total_deposits: u64 = deposits.iter().map(|(_, amount)| amount).sum();
+        let total_payouts: u64 = payouts.iter().map(|(_, amount)| amount).sum();
+        if total_payouts > total_deposits {
+            return Err(format!(
+                "Payouts of {} exceed the escrowed {}",
+                total_payouts, total_deposits
+            ));
+        }
+
+        let mut remaining: HashMap<PlayerId, u64> = deposits.iter().cloned().collect();
+        let mut funding = Vec::new();
+        for (to, amount) in payouts {
+            let mut draws = Vec::new();
+            let mut owed = *amount;
+            let own = std::iter::once(to);
+            let others = deposits
+                .iter()
+                .map(|(player_id, _)| player_id)
+                .filter(|player_id| *player_id != to);
+            for from in own.chain(others) {
+                let Some(available) = remaining.get_mut(from) else {
+                    continue;
+                };
+                let amount = owed.min(*available);
+                if amount > 0 {
+                    *available -= amount;
+                    owed -= amount;
+                    draws.push((*from, amount));
+                }
+                if owed == 0 {
+                    break;
+                }
+            }
+            funding.push(draws);
+        }
+        Ok(funding)
+    }
+
+    /// Instruction, signed by the authority, that moves `amount` out of
+    /// `from`'s deposit to `to`.
+    pub fn transfer_instruction(
+        &self,
+        scope: &Pubkey,
+        from: &PlayerId,
+        to: &PlayerId,
+        amount: u64,
+    ) -> Result<Instruction, String> {
+        match &self.mint {
+            Some(mint) => mint.transfer_instruction(
+                &mint.token_account(from),
+                &mint.token_account(to),
+                &self.authority,
+                amount,
+            ),
+            None => Ok(system_instruction::transfer_with_seed(
+                &self.deposit_address(scope, from),
+                &self.authority,
+                Self::deposit_seed(scope, from),
+                &system_program::id(),
+                to,
+                amount,
+            )),
+        }
+    }
+
+    /// Instructions, signed by the authority, that distribute the escrowed
+    /// stakes according to `payouts`, the amounts owed to each player.
+    ///
+    /// `deposits` are the amounts held for each player. Payouts are funded
+    /// as in `fund_payouts`. Whatever isn't paid out is released back to the
+    /// depositor; for delegated token accounts this needs no transfer.
+    pub fn release_instructions(
+        &self,
+        scope: &Pubkey,
+        deposits: &[(PlayerId, u64)],
+        payouts: &[(PlayerId, u64)],
+    ) -> Result<Vec<Instruction>, String> {
+        let funding = Self::fund_payouts(deposits, payouts)?;
+
+        let mut remaining: HashMap<PlayerId, u64> = deposits.iter().cloned().collect();
+        let mut transfers: Vec<(PlayerId, PlayerId, u64)> = Vec::new();
+        for ((to, _), draws) in payouts.iter().zip(funding) {
+            for (from, amount) in draws {
+                *remaining.get_mut(&from).unwrap() -= amount;
+                transfers.push((from, *to, amount));
+            }
+        }
+        for (player_id, _) in deposits {
+            let amount = remaining[player_id];
+            if amount > 0 {
+                transfers.push((*player_id, *player_id, amount));
+            }
+        }
+
+        transfers
+            .into_iter()
+            .filter(|(from, to, _)| self.mint.is_none() || from != to)
+            .map(|(from, to, amount)| self.transfer_instruction(scope, &from, &to, amount))
+            .collect()
+    }
+}
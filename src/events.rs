@@ -0,0 +1,97 @@
+//! Lifecycle events emitted by a game channel.
+//!
+//! Frontends and analytics services subscribe to a channel to be notified of
+//! games being created, played, finished and settled, instead of polling the
+//! `GameStateManager`.
+
+use {
+    crate::game_traits::{GameInstanceId, GameMove, GameTypeId, Payout, PlayerId},
+    serde::{Deserialize, Serialize},
+    solana_sdk::signature::Signature,
+    std::sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+};
+
+/// A game or channel lifecycle event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ChannelEvent {
+    GameCreated {
+        game_instance_id: GameInstanceId,
+        game_type_id: GameTypeId,
+        players: Vec<PlayerId>,
+    },
+    StakeCommitted {
+        game_instance_id: GameInstanceId,
+        player_id: PlayerId,
+        amount: u64,
+    },
+    MoveApplied {
+        game_move: GameMove,
+    },
+    MoveRejected {
+        game_move: GameMove,
+        reason: String,
+    },
+    GameEnded {
+        game_instance_id: GameInstanceId,
+        winner: Option<PlayerId>,
+    },
+    PayoutsComputed {
+        game_instance_id: GameInstanceId,
+        payouts: Vec<Payout>,
+    },
+    /// A settlement transaction was sent to the base chain.
+    SettlementSubmitted {
+        game_instance_id: GameInstanceId,
+        signature: Signature,
+    },
+    /// A settlement transaction was confirmed on the base chain.
+    SettlementConfirmed {
+        game_instance_id: GameInstanceId,
+        signature: Signature,
+    },
+    /// Settling a game failed. Payouts confirmed before the failure are kept,
+    /// and `PlayChannel::settle_game` pays out the rest.
+    SettlementFailed {
+        game_instance_id: GameInstanceId,
+        reason: String,
+    },
+    /// Sending a settlement transaction failed transiently and is retried.
+    SettlementRetried {
+        game_instance_id: GameInstanceId,
+        signature: Signature,
+        attempt: u32,
+        reason: String,
+    },
+}
+
+/// Fans events out to every subscriber.
+///
+/// Subscribers that have dropped their receiver are removed on the next
+/// emitted event.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<Sender<ChannelEvent>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe to all events emitted from now on.
+    pub fn subscribe(&self) -> Receiver<ChannelEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn emit(&self, event: ChannelEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,149 @@
+//! Fees for using a channel.
+//!
+//! A channel's operator can charge a fee on every transfer made in the
+//! channel. Fees are charged in the transferred asset, on top of the amount
+//! transferred, and accrue to the operator's fee account: they're settled to
+//! the base chain along with the transfers themselves.
+//!
+//! The base chain fees of settling a channel are paid by the channel's
+//! authority. When a channel is closed, they can instead be shared among the
+//! participants, out of their SOL balances in the channel.
+
+use {
+    crate::transaction::PayTubeTransaction,
+    borsh::{BorshDeserialize, BorshSerialize},
+    serde::{Deserialize, Serialize},
+    solana_sdk::{
+        instruction::InstructionError,
+        pubkey::Pubkey,
+        transaction::{self, TransactionError},
+    },
+    std::collections::HashMap,
+};
+
+/// How much a transfer in the channel costs.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    BorshSerialize,
+    BorshDeserialize,
+    Serialize,
+    Deserialize,
+)]
+pub enum FeePolicy {
+    #[default]
+    Free,
+    /// A flat fee for every transfer.
+    PerTransaction(u64),
+    /// A share of the amount transferred, in basis points.
+    Percentage { basis_points: u64 },
+}
+
+impl FeePolicy {
+    /// The fee for transferring `amount`.
+    pub fn fee(&self, amount: u64) -> u64 {
+        match self {
+            Self::Free => 0,
+            Self::PerTransaction(fee) => *fee,
+            Self::Percentage { basis_points } => {
+                (amount as u128 * *basis_points as u128 / 10_000) as u64
+            }
+        }
+    }
+}
+
+/// The fees a channel charges, and who they're paid to.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+pub struct ChannelFees {
+    pub policy: FeePolicy,
+    /// The operator's fee account.
+    pub operator: Pubkey,
+    /// Whether participants share the base chain fees of settling the
+    /// channel when it's closed, rather than the authority bearing them.
+    pub share_settlement_costs: bool,
+}
+
+impl ChannelFees {
+    pub fn new(policy: FeePolicy, operator: Pubkey) -> Self {
+        Self {
+            policy,
+            operator,
+            share_settlement_costs: false,
+        }
+    }
+
+    pub fn with_shared_settlement_costs(mut self) -> Self {
+        self.share_settlement_costs = true;
+        self
+    }
+
+    /// The fee charged to the sender of a transfer of `amount` from `from`.
+    /// The operator doesn't pay fees to itself.
+    pub fn fee(&self, from: &Pubkey, amount: u64) -> u64 {
+        if *from == self.operator {
+            return 0;
+        }
+        self.policy.fee(amount)
+    }
+
+    /// Check that the senders of a batch of transfers, made outside an open
+    /// channel, can pay both the amounts and their fees out of their
+    /// `balance`, on top of the `check_results` of the batch's pre-checks.
+    ///
+    /// Transfers are checked in order, each spending what the sender's
+    /// earlier transfers in the batch left.
+    pub fn check_transfers<T>(
+        &self,
+        transactions: &[PayTubeTransaction],
+        check_results: &mut [transaction::Result<T>],
+        balance: impl Fn(&PayTubeTransaction) -> u64,
+    ) {
+        let mut spent: HashMap<(Pubkey, Option<Pubkey>), u64> = HashMap::new();
+        for (transaction, check_result) in transactions.iter().zip(check_results.iter_mut()) {
+            if check_result.is_err() {
+                continue;
+            }
+            let fee = self.fee(&transaction.from, transaction.amount);
+            let spent = spent
+                .entry((transaction.from, transaction.mint))
+                .or_default();
+            let total = spent.saturating_add(transaction.amount).saturating_add(fee);
+            if balance(transaction) < total {
+                *check_result = Err(TransactionError::InstructionError(
+                    0,
+                    InstructionError::InsufficientFunds,
+                ));
+            } else {
+                *spent = total;
+            }
+        }
+    }
+}
+
+/// Split `cost` evenly between `parties`, each paying at most what they can
+/// afford. Any remainder is taken from the parties in order.
+pub fn split_cost(cost: u64, parties: &[(Pubkey, u64)]) -> Vec<(Pubkey, u64)> {
+    let mut shares: Vec<(Pubkey, u64)> = parties.iter().map(|(party, _)| (*party, 0)).collect();
+    let mut remaining = cost;
+    // Whoever can't afford an even share pays what they can, and the rest is
+    // split between the others.
+    loop {
+        let able: Vec<usize> = (0..parties.len())
+            .filter(|index| shares[*index].1 < parties[*index].1)
+            .collect();
+        if remaining == 0 || able.is_empty() {
+            break;
+        }
+        let share = (remaining / able.len() as u64).max(1);
+        for index in able {
+            let amount = share.min(parties[index].1 - shares[index].1).min(remaining);
+            shares[index].1 += amount;
+            remaining -= amount;
+        }
+    }
+    shares
+}
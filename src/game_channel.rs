@@ -1,12 +1,26 @@
 //Game Channel
 //Game Session
 
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::mpsc::Receiver};
 
-use solana_client::rpc_client::RpcClient;
-use solana_sdk::signature::Keypair;
+use solana_client::{client_error::ClientError, rpc_client::RpcClient};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    transaction::VersionedTransaction,
+};
+use solana_svm::transaction_processing_callback::TransactionProcessingCallback;
 
-use crate::game_traits::*;
+pub use crate::settler_two::SettlementConfig;
+use crate::{
+    escrow::StakeEscrow,
+    events::{ChannelEvent, EventBus},
+    game_traits::*,
+    loader::PayTubeAccountLoader,
+    settler_two::PlayChannelSettler,
+    simulation::SimulationReport,
+    storage::ChannelStorage,
+};
 
 /// Game state channel that extends Paytube for gaming
 pub struct PlayChannel {
@@ -16,6 +30,12 @@ pub struct PlayChannel {
     pub rpc_client: RpcClient,
     /// Signers for settlement
     pub keys: Vec<Keypair>,
+    /// Lifecycle event subscribers
+    events: EventBus,
+    /// Where stakes are locked up on the base chain, if they're backed
+    escrow: Option<StakeEscrow>,
+    /// Settle wagered games as soon as they end, if set
+    auto_settlement: Option<SettlementConfig>,
 }
 
 impl PlayChannel {
@@ -28,6 +48,9 @@ impl PlayChannel {
             keys,
             game_manager,
             rpc_client,
+            events: EventBus::new(),
+            escrow: None,
+            auto_settlement: None,
         }
     }
 
@@ -43,9 +66,49 @@ impl PlayChannel {
             game_manager,
             rpc_client,
             keys,
+            events: EventBus::new(),
+            escrow: None,
+            auto_settlement: None,
         }
     }
 
+    /// Rebuild a game channel from durable storage after a restart
+    pub fn recover(
+        keys: Vec<Keypair>,
+        rpc_client: RpcClient,
+        registry: GameEngineRegistry,
+        retention_policy: RetentionPolicy,
+        storage: Box<dyn ChannelStorage>,
+    ) -> Result<Self, String> {
+        let game_manager = GameStateManager::recover(registry, retention_policy, storage)?;
+
+        Ok(Self {
+            game_manager,
+            rpc_client,
+            keys,
+            events: EventBus::new(),
+            escrow: None,
+            auto_settlement: None,
+        })
+    }
+
+    /// Require stakes to be backed by `escrow` on the base chain
+    pub fn with_escrow(mut self, escrow: StakeEscrow) -> Self {
+        self.escrow = Some(escrow);
+        self
+    }
+
+    /// Settle the payouts of wagered games automatically when they end
+    pub fn with_auto_settlement(mut self, config: SettlementConfig) -> Self {
+        self.auto_settlement = Some(config);
+        self
+    }
+
+    /// Subscribe to game and channel lifecycle events
+    pub fn subscribe(&self) -> Receiver<ChannelEvent> {
+        self.events.subscribe()
+    }
+
     // register game engine?
 
     /// Create a new game and return the game instance ID
@@ -62,12 +125,13 @@ impl PlayChannel {
             .get_engine(game_type)
             .ok_or_else(|| format!("Game type '{}' not found", game_type))?;
 
+        // Games are only wagered by default once there's something at stake
         let final_wagering_config = if let Some(config) = wagering_config {
             if !engine.supports_wagering() {
                 return Err("This game does not support wagering".to_string());
             }
             Some(config)
-        } else if engine.supports_wagering() {
+        } else if stake_amount > 0 && engine.supports_wagering() {
             engine.default_wagering_config()
         } else {
             None
@@ -81,36 +145,132 @@ impl PlayChannel {
             timeout_seconds: 300, //5 minutes
             stake_amount,         //to be investigated
             custom_config: HashMap::new(),
+            game_id: None, // assigned by the game manager
             wagering_config: final_wagering_config,
         };
 
         //Create game instance
         let game_instance_id = self.game_manager.create_game(&config, players)?;
 
+        let game_type_id = self
+            .game_manager
+            .get_game_state(&game_instance_id)
+            .map(|game_state| game_state.game_type_id.clone())
+            .unwrap_or_else(|| game_type.clone());
+        self.events.emit(ChannelEvent::GameCreated {
+            game_instance_id,
+            game_type_id,
+            players: players.to_vec(),
+        });
+
         Ok(game_instance_id)
     }
 
-    // commit player stake???
+    /// Commit a player's stake to a wagered game
+    ///
+    /// If the channel has an escrow, the stake must already be locked up in
+    /// it on the base chain.
+    pub fn commit_stake(
+        &mut self,
+        game_instance_id: &GameInstanceId,
+        player_id: PlayerId,
+        amount: u64,
+    ) -> Result<(), String> {
+        let token_mint = match &self.escrow {
+            Some(escrow) => {
+                // A token delegation backs all of the player's open stakes
+                // in that mint at once.
+                let token_mint = escrow.mint.as_ref().map(|mint| mint.mint);
+                let required = match token_mint {
+                    Some(mint) => self.open_token_stakes(&player_id, &mint) + amount,
+                    None => amount,
+                };
+                let deposit = escrow.deposit_address(game_instance_id, &player_id);
+                let account = PayTubeAccountLoader::new(&self.rpc_client)
+                    .get_account_shared_data(&deposit)
+                    .ok_or_else(|| "Stake has not been deposited".to_string())?;
+                escrow.verify_deposit(&player_id, &account, required)?;
+                token_mint
+            }
+            None => None,
+        };
+
+        self.game_manager
+            .commit_stake(game_instance_id, player_id, amount, token_mint)?;
+
+        self.events.emit(ChannelEvent::StakeCommitted {
+            game_instance_id: *game_instance_id,
+            player_id,
+            amount,
+        });
+        Ok(())
+    }
+
+    /// Total stakes `player_id` has committed in `mint` to games that
+    /// haven't been paid out in full: active games, finished or not, and
+    /// archived games with unsettled payouts.
+    fn open_token_stakes(&self, player_id: &PlayerId, mint: &Pubkey) -> u64 {
+        let filter = GameFilter {
+            player: Some(*player_id),
+            ..Default::default()
+        };
+        let unsettled = self
+            .game_manager
+            .list_archived_games(&filter)
+            .into_iter()
+            .filter(|archived| !archived.is_settled())
+            .map(|archived| &archived.final_state);
+        self.game_manager
+            .list_games(&filter)
+            .into_iter()
+            .chain(unsettled)
+            .filter_map(|game_state| game_state.wagering_state.as_ref())
+            .filter_map(|wagering_state| wagering_state.player_stakes.get(player_id))
+            .filter(|stake| stake.token_mint.as_ref() == Some(mint))
+            .map(|stake| stake.amount)
+            .sum()
+    }
 
     /// Process a game move
+    ///
+    /// Once applied, a move isn't undone by what follows it: if the game
+    /// ends and its automatic settlement fails, a `SettlementFailed` event
+    /// is emitted instead.
     pub fn process_game_move(&mut self, game_move: &GameMove) -> Result<GameActionResult, String> {
+        let now = self.game_manager.clock().unix_timestamp()?;
         if let Some(game_state) = self
             .game_manager
             .get_game_state_mut(&game_move.game_instance_id)
         {
-            game_state.last_activity = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
+            game_state.last_activity = now;
         }
 
         // Process the move
-        let result = self.game_manager.process_move(game_move)?;
+        let result = match self.game_manager.process_move(game_move) {
+            Ok(GameActionResult::Failure(reason)) => {
+                self.events.emit(ChannelEvent::MoveRejected {
+                    game_move: game_move.clone(),
+                    reason: reason.clone(),
+                });
+                return Ok(GameActionResult::Failure(reason));
+            }
+            Ok(result) => result,
+            Err(reason) => {
+                self.events.emit(ChannelEvent::MoveRejected {
+                    game_move: game_move.clone(),
+                    reason: reason.clone(),
+                });
+                return Err(reason);
+            }
+        };
+        self.events.emit(ChannelEvent::MoveApplied {
+            game_move: game_move.clone(),
+        });
 
         // Check if game ended
         if let GameActionResult::GameEnded { winner } = &result {
             //Handle game completion
-            self.handle_game_completion(&game_move.game_instance_id, *winner)?;
+            self.handle_game_completion(&game_move.game_instance_id, *winner);
         }
 
         Ok(result)
@@ -121,20 +281,157 @@ impl PlayChannel {
     /// Handle game completion
     fn handle_game_completion(
         &mut self,
-        game_instance_id: &PlayerId,
+        game_instance_id: &GameInstanceId,
         winner: Option<PlayerId>,
-    ) -> Result<(), String> {
+    ) {
         // Log game completion
         println!(
             "Game {} completed with winner: {:?}",
             game_instance_id, winner
         );
 
+        self.events.emit(ChannelEvent::GameEnded {
+            game_instance_id: *game_instance_id,
+            winner,
+        });
+
+        // The game may already have been archived by the retention policy
+        let payouts = match self.game_manager.get_game_state(game_instance_id) {
+            Some(game_state) => game_state
+                .wagering_state
+                .as_ref()
+                .and_then(|wagering_state| wagering_state.payouts.clone()),
+            None => self
+                .game_manager
+                .get_archived_game(game_instance_id)
+                .map(|archived| archived.payouts.clone())
+                .filter(|payouts| !payouts.is_empty()),
+        };
+        if let Some(payouts) = payouts {
+            self.events.emit(ChannelEvent::PayoutsComputed {
+                game_instance_id: *game_instance_id,
+                payouts,
+            });
+
+            if let Some(config) = self.auto_settlement.clone() {
+                if let Err(reason) = self.settle_game(game_instance_id, &config) {
+                    self.events.emit(ChannelEvent::SettlementFailed {
+                        game_instance_id: *game_instance_id,
+                        reason,
+                    });
+                }
+            }
+        }
+
         // Additional completion logic could go here
-        // - Notify players
         // - Update statistics
-        // - Trigger automatic settlement
+    }
 
-        Ok(())
+    /// The transactions paying out an archived game's unsettled payouts from
+    /// the escrowed stakes, without sending them.
+    pub fn plan_settlement(
+        &self,
+        game_instance_id: &GameInstanceId,
+    ) -> Result<Vec<VersionedTransaction>, String> {
+        let archived = self
+            .game_manager
+            .get_archived_game(game_instance_id)
+            .ok_or_else(|| "Game not found".to_string())?;
+        let settler = PlayChannelSettler::new(&self.rpc_client);
+        Ok(self
+            .payout_transactions(&settler, archived)?
+            .into_iter()
+            .map(|(_, transaction)| transaction)
+            .collect())
+    }
+
+    /// Simulate an archived game's settlement on the base chain, without
+    /// sending anything.
+    pub fn simulate_settlement(
+        &self,
+        game_instance_id: &GameInstanceId,
+    ) -> Result<Vec<SimulationReport>, String> {
+        let transactions = self.plan_settlement(game_instance_id)?;
+        PlayChannelSettler::new(&self.rpc_client).simulate(&transactions)
+    }
+
+    fn payout_transactions(
+        &self,
+        settler: &PlayChannelSettler,
+        archived: &ArchivedGame,
+    ) -> Result<Vec<(Option<usize>, VersionedTransaction)>, String> {
+        let escrow = self
+            .escrow
+            .as_ref()
+            .ok_or_else(|| "Channel has no escrow to settle from".to_string())?;
+        settler.plan(escrow, archived, &self.keys)
+    }
+
+    /// Pay out a finished game's payouts from the escrowed stakes on the
+    /// base chain
+    ///
+    /// The game is archived if it isn't already, and each payout's
+    /// settlement signature is recorded in the archived game as soon as it's
+    /// confirmed. If a transaction fails the other payouts are still sent,
+    /// and settling the game again only pays out the ones that are missing.
+    pub fn settle_game(
+        &mut self,
+        game_instance_id: &GameInstanceId,
+        config: &SettlementConfig,
+    ) -> Result<Vec<Signature>, String> {
+        if self.game_manager.get_game_state(game_instance_id).is_some() {
+            self.game_manager.archive_game(game_instance_id)?;
+        }
+        let archived = self
+            .game_manager
+            .get_archived_game(game_instance_id)
+            .ok_or_else(|| "Game not found".to_string())?;
+        let settler = PlayChannelSettler::new(&self.rpc_client);
+        let transactions = self.payout_transactions(&settler, archived)?;
+        if transactions.is_empty() && !archived.settlement_signatures.is_empty() {
+            return Err("Game is already settled".to_string());
+        }
+
+        let mut signatures = Vec::new();
+        let mut result = Ok(());
+        for (payout, transaction) in &transactions {
+            self.events.emit(ChannelEvent::SettlementSubmitted {
+                game_instance_id: *game_instance_id,
+                signature: transaction.signatures[0],
+            });
+            let retried = |attempt, error: &ClientError| {
+                self.events.emit(ChannelEvent::SettlementRetried {
+                    game_instance_id: *game_instance_id,
+                    signature: transaction.signatures[0],
+                    attempt,
+                    reason: error.to_string(),
+                })
+            };
+            match settler.send_with_retry(transaction, config, retried) {
+                Ok(signature) => {
+                    self.events.emit(ChannelEvent::SettlementConfirmed {
+                        game_instance_id: *game_instance_id,
+                        signature,
+                    });
+                    match payout {
+                        Some(index) => self.game_manager.record_payout_settlement(
+                            game_instance_id,
+                            *index,
+                            signature,
+                        )?,
+                        None => self
+                            .game_manager
+                            .record_settlement(game_instance_id, &[signature])?,
+                    }
+                    signatures.push(signature);
+                }
+                Err(e) => {
+                    if result.is_ok() {
+                        result = Err(format!("Settlement failed: {}", e));
+                    }
+                }
+            }
+        }
+        result.map(|_| signatures)
     }
 }
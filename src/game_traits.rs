@@ -9,8 +9,18 @@
 //Game Engine Registry
 //Game State Manager
 
-use solana_sdk::{pubkey::Pubkey, signature::Signature};
-use std::collections::HashMap;
+use crate::{
+    clock::{Clock, SystemClock},
+    storage::{ChannelSnapshot, ChannelStorage, StorageRecord, StoredAccount},
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use solana_sdk::{account::AccountSharedData, pubkey::Pubkey, signature::Signature};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    sync::Arc,
+};
 
 /// Unique identifier for a game instance
 pub type GameInstanceId = Pubkey;
@@ -24,7 +34,24 @@ pub type GameTypeId = String;
 /// Move identifier within a game
 pub type MoveId = u64;
 
-#[derive(Debug, Clone)]
+/// Derive a game instance id the same way the on-chain game programs derive
+/// the game account, i.e. the PDA for `["game", creator, game_id]`.
+///
+/// Off-chain `GameState`s and on-chain game accounts therefore refer to the
+/// same game and can be reconciled at settlement.
+pub fn derive_game_instance_id(
+    program_id: &Pubkey,
+    creator: &PlayerId,
+    game_id: u64,
+) -> GameInstanceId {
+    Pubkey::find_program_address(
+        &[b"game", &creator.to_bytes(), &game_id.to_le_bytes()],
+        program_id,
+    )
+    .0
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 pub enum GameActionResult {
     /// Action was successful
     Success,
@@ -37,7 +64,7 @@ pub enum GameActionResult {
 }
 
 /// A generic game move that can represent any action in any game
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GameMove {
     pub game_instance_id: GameInstanceId,
     pub player_id: PlayerId,
@@ -47,8 +74,187 @@ pub struct GameMove {
     pub timestamp: u64,
 }
 
+// `Signature` has no borsh impl, so its raw bytes are written by hand.
+impl BorshSerialize for GameMove {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        BorshSerialize::serialize(&self.game_instance_id, writer)?;
+        BorshSerialize::serialize(&self.player_id, writer)?;
+        BorshSerialize::serialize(&self.move_id, writer)?;
+        BorshSerialize::serialize(&self.move_data, writer)?;
+        writer.write_all(self.signature.as_ref())?;
+        BorshSerialize::serialize(&self.timestamp, writer)
+    }
+}
+
+impl BorshDeserialize for GameMove {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let game_instance_id = Pubkey::deserialize_reader(reader)?;
+        let player_id = Pubkey::deserialize_reader(reader)?;
+        let move_id = MoveId::deserialize_reader(reader)?;
+        let move_data = Vec::<u8>::deserialize_reader(reader)?;
+        let mut signature = [0u8; 64];
+        reader.read_exact(&mut signature)?;
+        let timestamp = u64::deserialize_reader(reader)?;
+        Ok(Self {
+            game_instance_id,
+            player_id,
+            move_id,
+            move_data,
+            signature: Signature::from(signature),
+            timestamp,
+        })
+    }
+}
+
+/// Version of the binary and JSON encodings of the game types. Bump this
+/// whenever a field is added, removed or reordered, and keep the older
+/// versions decodable: added fields get a serde default, and the changed
+/// types override `GameEncoding::from_legacy_bytes`.
+///
+/// 1. Initial encoding
+/// 2. Added `GameConfig::game_id`
+/// 3. Added `ArchivedGame::settlement_signatures`
+/// 4. Added `ArchivedGame::payout_signatures`
+/// 5. Added `ChannelSnapshot::game_counters` and
+///    `StorageRecord::GameCounterUpdated`
+/// 6. Added `ChannelSnapshot::session` and `ChannelSnapshot::transfer_ids`,
+///    with `StorageRecord::SessionUpdated` and
+///    `StorageRecord::TransfersProcessed`
+pub const GAME_ENCODING_VERSION: u8 = 6;
+
+/// Stable, versioned encodings for values that are signed, persisted, sent to
+/// peers or posted on-chain.
+///
+/// The binary form is the version byte followed by the borsh encoding, the
+/// JSON form is `{"version": .., "data": ..}`.
+pub trait GameEncoding: BorshSerialize + BorshDeserialize + Serialize + DeserializeOwned {
+    fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = vec![GAME_ENCODING_VERSION];
+        BorshSerialize::serialize(self, &mut bytes).map_err(|e| e.to_string())?;
+        Ok(bytes)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        match bytes.split_first() {
+            Some((&GAME_ENCODING_VERSION, data)) => {
+                Self::try_from_slice(data).map_err(|e| e.to_string())
+            }
+            Some((&version, data)) if (1..GAME_ENCODING_VERSION).contains(&version) => {
+                Self::from_legacy_bytes(version, data)
+            }
+            Some((version, _)) => Err(format!("Unsupported encoding version {}", version)),
+            None => Err("Empty input".to_string()),
+        }
+    }
+
+    /// Decode the binary form written by an older encoding `version`. Types
+    /// whose layout changed since override this, for the others it's the
+    /// same as the current one.
+    fn from_legacy_bytes(version: u8, data: &[u8]) -> Result<Self, String> {
+        let _ = version;
+        Self::try_from_slice(data).map_err(|e| e.to_string())
+    }
+
+    fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string(&Versioned {
+            version: GAME_ENCODING_VERSION,
+            data: self,
+        })
+        .map_err(|e| e.to_string())
+    }
+
+    fn from_json(json: &str) -> Result<Self, String> {
+        // Fields added since an older version fall back to their defaults.
+        let versioned: Versioned<Self> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        if versioned.version == 0 || versioned.version > GAME_ENCODING_VERSION {
+            return Err(format!(
+                "Unsupported encoding version {}",
+                versioned.version
+            ));
+        }
+        Ok(versioned.data)
+    }
+}
+
+impl GameEncoding for GameActionResult {}
+impl GameEncoding for GameMove {}
+impl GameEncoding for WageringType {}
+impl GameEncoding for WageringState {}
+impl GameEncoding for Payout {}
+impl GameEncoding for WageringConfig {}
+impl GameEncoding for GameState {}
+impl GameEncoding for PlayerStake {}
+
+impl GameEncoding for GameConfig {
+    fn from_legacy_bytes(version: u8, data: &[u8]) -> Result<Self, String> {
+        if version >= 2 {
+            return Self::try_from_slice(data).map_err(|e| e.to_string());
+        }
+        // Version 1 had no `game_id`.
+        decode_legacy(data, |reader| {
+            Ok(Self {
+                game_type_id: GameTypeId::deserialize_reader(reader)?,
+                max_players: u8::deserialize_reader(reader)?,
+                min_players: u8::deserialize_reader(reader)?,
+                timeout_seconds: u64::deserialize_reader(reader)?,
+                stake_amount: u64::deserialize_reader(reader)?,
+                custom_config: HashMap::<String, String>::deserialize_reader(reader)?,
+                game_id: None,
+                wagering_config: Option::<WageringConfig>::deserialize_reader(reader)?,
+            })
+        })
+    }
+}
+
+impl GameEncoding for ArchivedGame {
+    fn from_legacy_bytes(version: u8, data: &[u8]) -> Result<Self, String> {
+        decode_legacy(data, |reader| Self::deserialize_versioned(reader, version))
+    }
+}
+
+/// Decode all of `data` with `decode`, for `GameEncoding::from_legacy_bytes`
+pub(crate) fn decode_legacy<T>(
+    data: &[u8],
+    decode: impl FnOnce(&mut &[u8]) -> std::io::Result<T>,
+) -> Result<T, String> {
+    let mut reader = data;
+    let value = decode(&mut reader).map_err(|e| e.to_string())?;
+    if !reader.is_empty() {
+        return Err("Unexpected trailing bytes".to_string());
+    }
+    Ok(value)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Versioned<T> {
+    version: u8,
+    data: T,
+}
+
+/// JSON object keys must be strings, so maps keyed by pubkey are encoded as
+/// a list of `[key, value]` pairs.
+mod pubkey_map {
+    use super::*;
+
+    pub fn serialize<V: Serialize, S: Serializer>(
+        map: &HashMap<Pubkey, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut entries: Vec<(&Pubkey, &V)> = map.iter().collect();
+        entries.sort_by_key(|(key, _)| **key);
+        serializer.collect_seq(entries)
+    }
+
+    pub fn deserialize<'de, V: Deserialize<'de>, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<Pubkey, V>, D::Error> {
+        let entries: Vec<(Pubkey, V)> = Deserialize::deserialize(deserializer)?;
+        Ok(entries.into_iter().collect())
+    }
+}
+
 /// Types of wagering systems
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 pub enum WageringType {
     WinnerTakesAll,
     SplitPot {
@@ -62,9 +268,10 @@ pub enum WageringType {
 }
 
 /// Wagering state for a game
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 pub struct WageringState {
     pub config: WageringConfig,
+    #[serde(with = "pubkey_map")]
     pub player_stakes: HashMap<PlayerId, PlayerStake>,
     pub total_pot: u64,
     pub stakes_committed: bool,
@@ -72,7 +279,7 @@ pub struct WageringState {
 }
 
 /// Payout information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 pub struct Payout {
     pub player_id: PlayerId,
     pub amount: u64,
@@ -81,7 +288,7 @@ pub struct Payout {
 }
 
 /// Wagering configuration for a game
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 pub struct WageringConfig {
     pub wagering_type: WageringType,
     pub min_stake: u64,
@@ -91,7 +298,7 @@ pub struct WageringConfig {
 }
 
 /// A generic game state that can represent any game's state
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 pub struct GameState {
     pub game_instance_id: GameInstanceId,
     pub game_type_id: GameTypeId,
@@ -109,7 +316,7 @@ pub struct GameState {
 }
 
 /// Player stake information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 pub struct PlayerStake {
     pub player_id: PlayerId,
     pub amount: u64,
@@ -119,7 +326,7 @@ pub struct PlayerStake {
 }
 
 /// Configuration for a game instance
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 pub struct GameConfig {
     pub game_type_id: GameTypeId,
     pub max_players: u8,
@@ -127,6 +334,9 @@ pub struct GameConfig {
     pub timeout_seconds: u64,
     pub stake_amount: u64,
     pub custom_config: HashMap<String, String>, // Game-specific configuration
+    /// Per-creator game counter used to derive the game instance id. `None`
+    /// lets the `GameStateManager` assign the creator's next game id.
+    pub game_id: Option<u64>,
     /// Wagering configuration (optional)
     pub wagering_config: Option<WageringConfig>,
 }
@@ -134,6 +344,14 @@ pub struct GameConfig {
 pub trait GameEngine: Send + Sync {
     //unique identifier
     fn game_type_id(&self) -> GameTypeId;
+    //engine version, bumped when rules or state layout change
+    fn version(&self) -> u32 {
+        1
+    }
+    //versioned identifier, e.g. "rock_paper_scissors@1"
+    fn versioned_game_type_id(&self) -> GameTypeId {
+        format!("{}@{}", self.game_type_id(), self.version())
+    }
     //display name
     fn display_name(&self) -> String;
     //description
@@ -260,43 +478,478 @@ pub trait GameEngine: Send + Sync {
         }
     }
 
+    //channel accounts
+    /// Accounts loaded or modified in the channel since the last call, e.g.
+    /// by SVM execution, so they can be persisted
+    fn take_modified_accounts(&self) -> Vec<(Pubkey, AccountSharedData)> {
+        Vec::new()
+    }
+    /// Seed the engine with accounts recovered from storage
+    fn restore_accounts(&self, _accounts: &HashMap<Pubkey, AccountSharedData>) {}
+
+    //time
+    /// Use `clock` for game timestamps, so the engine agrees with the
+    /// `GameStateManager` running it
+    fn set_clock(&mut self, _clock: Arc<dyn Clock>) {}
+
     //serialization, deserialization and formatting
 }
 
+/// Metadata describing a registered game engine, used to build lobbies
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GameEngineInfo {
+    pub game_type_id: GameTypeId,
+    pub version: u32,
+    pub versioned_game_type_id: GameTypeId,
+    pub display_name: String,
+    pub description: String,
+    pub min_players: u8,
+    pub max_players: u8,
+    pub supports_wagering: bool,
+    pub default_wagering_config: Option<WageringConfig>,
+}
+
+impl GameEngineInfo {
+    fn from_engine(engine: &dyn GameEngine) -> Self {
+        Self {
+            game_type_id: engine.game_type_id(),
+            version: engine.version(),
+            versioned_game_type_id: engine.versioned_game_type_id(),
+            display_name: engine.display_name(),
+            description: engine.description(),
+            min_players: engine.min_players(),
+            max_players: engine.max_players(),
+            supports_wagering: engine.supports_wagering(),
+            default_wagering_config: engine.default_wagering_config(),
+        }
+    }
+}
+
 pub struct GameEngineRegistry {
+    /// Engines keyed by their versioned game type id
     engines: HashMap<GameTypeId, Box<dyn GameEngine>>,
+    /// Clock given to every engine, including ones registered later
+    clock: Option<Arc<dyn Clock>>,
 }
 
 impl GameEngineRegistry {
     pub fn new() -> Self {
         Self {
             engines: HashMap::new(),
+            clock: None,
+        }
+    }
+
+    /// Use `clock` for the timestamps of every engine
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        for engine in self.engines.values_mut() {
+            engine.set_clock(clock.clone());
+        }
+        self.clock = Some(clock);
+    }
+
+    /// Register an engine. Fails if the same game type and version is
+    /// already registered.
+    pub fn register_engine(&mut self, mut engine: Box<dyn GameEngine>) -> Result<(), String> {
+        let versioned_game_type_id = engine.versioned_game_type_id();
+        if self.engines.contains_key(&versioned_game_type_id) {
+            return Err(format!(
+                "Game type '{}' already registered",
+                versioned_game_type_id
+            ));
+        }
+        if let Some(clock) = &self.clock {
+            engine.set_clock(clock.clone());
         }
+        self.engines.insert(versioned_game_type_id, engine);
+        Ok(())
     }
 
-    pub fn register_engine(&mut self, engine: Box<dyn GameEngine>) {
-        let game_type_id = engine.game_type_id();
-        self.engines.insert(game_type_id, engine);
+    /// Remove an engine by versioned id, or every version of an unversioned id
+    pub fn unregister_engine(&mut self, game_type_id: &GameTypeId) -> Vec<Box<dyn GameEngine>> {
+        if let Some(engine) = self.engines.remove(game_type_id) {
+            return vec![engine];
+        }
+        let versioned_ids: Vec<GameTypeId> = self
+            .engines
+            .iter()
+            .filter(|(_, engine)| engine.game_type_id() == *game_type_id)
+            .map(|(id, _)| id.clone())
+            .collect();
+        versioned_ids
+            .iter()
+            .filter_map(|id| self.engines.remove(id))
+            .collect()
     }
 
+    /// Look up an engine by versioned id, or the latest version of an
+    /// unversioned id
     pub fn get_engine(&self, game_type_id: &GameTypeId) -> Option<&dyn GameEngine> {
-        self.engines.get(game_type_id).map(|e| e.as_ref())
+        if let Some(engine) = self.engines.get(game_type_id) {
+            return Some(engine.as_ref());
+        }
+        self.engines
+            .values()
+            .filter(|engine| engine.game_type_id() == *game_type_id)
+            .max_by_key(|engine| engine.version())
+            .map(|e| e.as_ref())
+    }
+
+    /// List all registered engines, sorted by versioned game type id
+    pub fn list_engines(&self) -> Vec<GameEngineInfo> {
+        let mut engines: Vec<GameEngineInfo> = self
+            .engines
+            .values()
+            .map(|engine| GameEngineInfo::from_engine(engine.as_ref()))
+            .collect();
+        engines.sort_by(|a, b| a.versioned_game_type_id.cmp(&b.versioned_game_type_id));
+        engines
+    }
+
+    /// Metadata for a single engine
+    pub fn engine_info(&self, game_type_id: &GameTypeId) -> Option<GameEngineInfo> {
+        self.get_engine(game_type_id)
+            .map(GameEngineInfo::from_engine)
+    }
+}
+
+/// Lifecycle status of a game, derived from its state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameStatus {
+    /// Wagered game still waiting for every player's stake
+    AwaitingStakes,
+    InProgress,
+    Finished,
+}
+
+impl GameState {
+    pub fn status(&self) -> GameStatus {
+        if self.is_finished {
+            GameStatus::Finished
+        } else if matches!(&self.wagering_state, Some(w) if !w.stakes_committed) {
+            GameStatus::AwaitingStakes
+        } else {
+            GameStatus::InProgress
+        }
+    }
+
+    /// Whether any player has committed a stake to the game
+    pub fn has_committed_stakes(&self) -> bool {
+        self.wagering_state
+            .as_ref()
+            .is_some_and(|wagering_state| !wagering_state.player_stakes.is_empty())
+    }
+}
+
+/// Filter for listing games. `None` fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct GameFilter {
+    pub player: Option<PlayerId>,
+    /// Versioned or unversioned game type id
+    pub game_type_id: Option<GameTypeId>,
+    pub status: Option<GameStatus>,
+}
+
+impl GameFilter {
+    pub fn matches(&self, game_state: &GameState) -> bool {
+        if let Some(player) = &self.player {
+            if !game_state.players.contains(player) {
+                return false;
+            }
+        }
+        if let Some(game_type_id) = &self.game_type_id {
+            let unversioned = game_state.game_type_id.split('@').next();
+            if game_state.game_type_id != *game_type_id
+                && unversioned != Some(game_type_id.as_str())
+            {
+                return false;
+            }
+        }
+        if let Some(status) = self.status {
+            if game_state.status() != status {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// A finished game moved out of the active set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchivedGame {
+    pub final_state: GameState,
+    pub payouts: Vec<Payout>,
+    pub archived_at: u64,
+    /// Base-chain transactions that paid out `payouts`
+    #[serde(default)]
+    pub settlement_signatures: Vec<Signature>,
+    /// The transaction that paid out each of `payouts`, once confirmed
+    #[serde(default)]
+    pub payout_signatures: Vec<Option<Signature>>,
+}
+
+impl BorshSerialize for ArchivedGame {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        BorshSerialize::serialize(&self.final_state, writer)?;
+        BorshSerialize::serialize(&self.payouts, writer)?;
+        BorshSerialize::serialize(&self.archived_at, writer)?;
+        BorshSerialize::serialize(&(self.settlement_signatures.len() as u32), writer)?;
+        for signature in &self.settlement_signatures {
+            writer.write_all(signature.as_ref())?;
+        }
+        BorshSerialize::serialize(&(self.payout_signatures.len() as u32), writer)?;
+        for signature in &self.payout_signatures {
+            match signature {
+                Some(signature) => {
+                    writer.write_all(&[1])?;
+                    writer.write_all(signature.as_ref())?;
+                }
+                None => writer.write_all(&[0])?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BorshDeserialize for ArchivedGame {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        Self::deserialize_versioned(reader, GAME_ENCODING_VERSION)
+    }
+}
+
+impl ArchivedGame {
+    /// Decode the binary layout of encoding `version`
+    pub(crate) fn deserialize_versioned<R: Read>(
+        reader: &mut R,
+        version: u8,
+    ) -> std::io::Result<Self> {
+        let final_state = GameState::deserialize_reader(reader)?;
+        let payouts = Vec::<Payout>::deserialize_reader(reader)?;
+        let archived_at = u64::deserialize_reader(reader)?;
+        let mut settlement_signatures = Vec::new();
+        // Settlement signatures were added in version 3
+        if version >= 3 {
+            let len = u32::deserialize_reader(reader)?;
+            for _ in 0..len {
+                let mut signature = [0u8; 64];
+                reader.read_exact(&mut signature)?;
+                settlement_signatures.push(Signature::from(signature));
+            }
+        }
+        let mut payout_signatures = Vec::new();
+        // Payout signatures were added in version 4
+        if version >= 4 {
+            let len = u32::deserialize_reader(reader)?;
+            for _ in 0..len {
+                if bool::deserialize_reader(reader)? {
+                    let mut signature = [0u8; 64];
+                    reader.read_exact(&mut signature)?;
+                    payout_signatures.push(Some(Signature::from(signature)));
+                } else {
+                    payout_signatures.push(None);
+                }
+            }
+        }
+        Ok(Self {
+            final_state,
+            payouts,
+            archived_at,
+            settlement_signatures,
+            payout_signatures,
+        })
+    }
+
+    /// Whether the payout at `index` has been paid out on the base chain
+    pub fn is_payout_settled(&self, index: usize) -> bool {
+        match self.payout_signatures.get(index) {
+            Some(signature) => signature.is_some(),
+            // Games archived before payouts were tracked individually were
+            // settled all at once.
+            None => self.payout_signatures.is_empty() && !self.settlement_signatures.is_empty(),
+        }
+    }
+
+    /// Whether every payout has been paid out on the base chain
+    pub fn is_settled(&self) -> bool {
+        (0..self.payouts.len()).all(|index| self.is_payout_settled(index))
     }
+}
 
-    //List all game types
+/// Controls how long games are kept in memory
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Move games to the history store as soon as they finish
+    pub archive_on_finish: bool,
+    /// Maximum number of archived games kept, oldest are dropped first.
+    /// Games with unsettled payouts are never dropped
+    pub max_history: Option<usize>,
+    /// Unfinished games idle for longer than this are evicted, unless
+    /// stakes were committed to them
+    pub idle_timeout_seconds: Option<u64>,
 }
 
 pub struct GameStateManager {
     registry: GameEngineRegistry,
     active_games: HashMap<GameInstanceId, GameState>,
+    game_history: HashMap<GameInstanceId, ArchivedGame>,
+    retention_policy: RetentionPolicy,
+    /// Next game id for each creator
+    game_counters: HashMap<PlayerId, u64>,
+    /// Accounts loaded or modified in the channel
+    accounts: HashMap<Pubkey, AccountSharedData>,
+    /// Durable storage (optional - state is memory only without it)
+    storage: Option<Box<dyn ChannelStorage>>,
+    clock: Arc<dyn Clock>,
 }
 
 impl GameStateManager {
     pub fn new(registry: GameEngineRegistry) -> Self {
+        Self::with_retention_policy(registry, RetentionPolicy::default())
+    }
+
+    pub fn with_retention_policy(
+        registry: GameEngineRegistry,
+        retention_policy: RetentionPolicy,
+    ) -> Self {
         Self {
             registry,
             active_games: HashMap::new(),
+            game_history: HashMap::new(),
+            retention_policy,
+            game_counters: HashMap::new(),
+            accounts: HashMap::new(),
+            storage: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Use `clock` for timestamps instead of the system time, in the manager
+    /// and every engine it runs.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.registry.set_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Rebuild a manager from durable storage after a restart
+    pub fn recover(
+        registry: GameEngineRegistry,
+        retention_policy: RetentionPolicy,
+        storage: Box<dyn ChannelStorage>,
+    ) -> Result<Self, String> {
+        let snapshot = storage
+            .load()
+            .map_err(|e| format!("Failed to load channel state: {}", e))?;
+
+        let mut manager = Self::with_retention_policy(registry, retention_policy);
+        manager.active_games = snapshot
+            .active_games
+            .into_iter()
+            .map(|game_state| (game_state.game_instance_id, game_state))
+            .collect();
+        manager.game_history = snapshot
+            .game_history
+            .into_iter()
+            .map(|archived| (archived.final_state.game_instance_id, archived))
+            .collect();
+        manager.accounts = snapshot
+            .accounts
+            .into_iter()
+            .map(StoredAccount::into_account)
+            .collect();
+        manager.game_counters = snapshot.game_counters.into_iter().collect();
+        for engine in manager.registry.engines.values() {
+            engine.restore_accounts(&manager.accounts);
+        }
+        manager.storage = Some(storage);
+        Ok(manager)
+    }
+
+    /// Persist all further changes to `storage`, starting from the current
+    /// state
+    pub fn set_storage(&mut self, storage: Box<dyn ChannelStorage>) -> Result<(), String> {
+        self.storage = Some(storage);
+        self.checkpoint()
+    }
+
+    /// Write a snapshot of the whole channel state and compact the log
+    pub fn checkpoint(&mut self) -> Result<(), String> {
+        let snapshot = ChannelSnapshot {
+            active_games: self.active_games.values().cloned().collect(),
+            game_history: self.game_history.values().cloned().collect(),
+            accounts: self
+                .accounts
+                .iter()
+                .map(|(pubkey, account)| StoredAccount::new(*pubkey, account.clone()))
+                .collect(),
+            game_counters: self
+                .game_counters
+                .iter()
+                .map(|(creator, next_game_id)| (*creator, *next_game_id))
+                .collect(),
+            ..ChannelSnapshot::default()
+        };
+        if let Some(storage) = &mut self.storage {
+            storage
+                .checkpoint(&snapshot)
+                .map_err(|e| format!("Failed to checkpoint channel state: {}", e))?;
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self, record: StorageRecord) -> Result<(), String> {
+        if let Some(storage) = &mut self.storage {
+            storage
+                .append(&record)
+                .map_err(|e| format!("Failed to persist channel state: {}", e))?;
         }
+        Ok(())
+    }
+
+    /// Persist the latest state of a game, or its removal
+    fn persist_game(&mut self, game_instance_id: &GameInstanceId) -> Result<(), String> {
+        if self.storage.is_none() {
+            return Ok(());
+        }
+        let record = if let Some(game_state) = self.active_games.get(game_instance_id) {
+            StorageRecord::GameUpdated(game_state.clone())
+        } else if let Some(archived) = self.game_history.get(game_instance_id) {
+            StorageRecord::GameArchived(archived.clone())
+        } else {
+            StorageRecord::GameRemoved(*game_instance_id)
+        };
+        self.persist(record)
+    }
+
+    /// Record accounts loaded or modified in the channel, e.g. the output of
+    /// an SVM batch. Accounts modified by engines are recorded automatically.
+    pub fn record_accounts(
+        &mut self,
+        accounts: &[(Pubkey, AccountSharedData)],
+    ) -> Result<(), String> {
+        if accounts.is_empty() {
+            return Ok(());
+        }
+        for (pubkey, account) in accounts {
+            self.accounts.insert(*pubkey, account.clone());
+        }
+        if self.storage.is_some() {
+            let stored = accounts
+                .iter()
+                .map(|(pubkey, account)| StoredAccount::new(*pubkey, account.clone()))
+                .collect();
+            self.persist(StorageRecord::AccountsUpdated(stored))?;
+        }
+        Ok(())
+    }
+
+    /// Accounts known to the channel, used to seed account loaders
+    pub fn accounts(&self) -> &HashMap<Pubkey, AccountSharedData> {
+        &self.accounts
     }
 
     //Create a new game instance
@@ -310,13 +963,69 @@ impl GameStateManager {
             .get_engine(&config.game_type_id)
             .ok_or_else(|| format!("Game type '{}' not found", config.game_type_id))?;
 
-        let game_state = engine.create_game(config, players)?;
+        let creator = players
+            .first()
+            .ok_or_else(|| "At least one player is required".to_string())?;
+
+        let (mut game_state, next_game_id) = if config.game_id.is_some() {
+            let game_state = engine.create_game(config, players)?;
+            if self.game_exists(&game_state.game_instance_id) {
+                return Err("Game already exists".to_string());
+            }
+            (game_state, None)
+        } else {
+            // Assign the creator's next unused game id. Counters are
+            // persisted, so ids aren't reused once their games are evicted or
+            // trimmed, but ids taken by games created with an explicit one
+            // are skipped here. The counter only moves on once the game is
+            // created, so a failed creation doesn't use up an id.
+            let mut config = config.clone();
+            let mut game_id = self.game_counters.get(creator).copied().unwrap_or_default();
+            loop {
+                config.game_id = Some(game_id);
+                let game_state = engine.create_game(&config, players)?;
+                game_id += 1;
+                if !self.game_exists(&game_state.game_instance_id) {
+                    break (game_state, Some(game_id));
+                }
+            }
+        };
         let game_instance_id = game_state.game_instance_id;
 
+        // Engines that don't set up wagering themselves get the configured one
+        if game_state.wagering_state.is_none() {
+            game_state.wagering_state =
+                config
+                    .wagering_config
+                    .clone()
+                    .map(|wagering_config| WageringState {
+                        config: wagering_config,
+                        player_stakes: HashMap::new(),
+                        total_pot: 0,
+                        stakes_committed: false,
+                        payouts: None,
+                    });
+        }
+
+        // Pin the game to the engine version it was created with
+        game_state.game_type_id = engine.versioned_game_type_id();
+        let accounts = engine.take_modified_accounts();
+
+        if let Some(next_game_id) = next_game_id {
+            self.persist(StorageRecord::GameCounterUpdated(*creator, next_game_id))?;
+            self.game_counters.insert(*creator, next_game_id);
+        }
+        self.record_accounts(&accounts)?;
         self.active_games.insert(game_instance_id, game_state);
+        self.persist_game(&game_instance_id)?;
         Ok(game_instance_id)
     }
 
+    fn game_exists(&self, game_instance_id: &GameInstanceId) -> bool {
+        self.active_games.contains_key(game_instance_id)
+            || self.game_history.contains_key(game_instance_id)
+    }
+
     /// Process a game move
     pub fn process_move(&mut self, game_move: &GameMove) -> Result<GameActionResult, String> {
         let game_state = self
@@ -338,9 +1047,13 @@ impl GameStateManager {
         // Apply the move
         let mut new_state = engine.apply_move(game_state, game_move)?;
 
-        if let Some(winner) = engine.check_game_end(&game_state) {
+        // A draw ends the game without a winner, so `is_finished` is checked
+        // alongside the engine's verdict.
+        let winner = engine.check_game_end(&new_state);
+        let game_ended = winner.is_some() || new_state.is_finished;
+        if game_ended {
             new_state.is_finished = true;
-            new_state.winner = Some(winner);
+            new_state.winner = winner;
 
             // Calculate payouts if wagering is enabled
             if let Some(wagering_state) = &new_state.wagering_state {
@@ -350,10 +1063,20 @@ impl GameStateManager {
                 }
             }
         }
+        let accounts = engine.take_modified_accounts();
+
         // Update the game state
+        self.record_accounts(&accounts)?;
         self.active_games
             .insert(game_move.game_instance_id, new_state);
+        self.persist_game(&game_move.game_instance_id)?;
 
+        if game_ended {
+            if self.retention_policy.archive_on_finish {
+                self.archive_game(&game_move.game_instance_id)?;
+            }
+            return Ok(GameActionResult::GameEnded { winner });
+        }
         Ok(validation_result)
     }
 
@@ -362,11 +1085,13 @@ impl GameStateManager {
     // receive peer move
     // pub fn make_my_move(&mut self, game_instance_id: &GameInstanceId, move_data: )
 
+    /// Record a player's stake; `token_mint` is `None` for SOL stakes
     pub fn commit_stake(
         &mut self,
         game_instance_id: &GameInstanceId,
         player_id: PlayerId,
         amount: u64,
+        token_mint: Option<Pubkey>,
     ) -> Result<(), String> {
         let game_state = self
             .active_games
@@ -386,12 +1111,9 @@ impl GameStateManager {
             let player_stake = PlayerStake {
                 player_id,
                 amount,
-                token_mint: None, // Default to SOL
+                token_mint,
                 committed: true,
-                committed_at: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs(),
+                committed_at: self.clock.unix_timestamp()?,
             };
 
             wagering_state.player_stakes.insert(player_id, player_stake);
@@ -405,7 +1127,7 @@ impl GameStateManager {
             return Err("Game does not support wagering".to_string());
         }
 
-        Ok(())
+        self.persist_game(game_instance_id)
     }
 
     // current game state
@@ -415,6 +1137,9 @@ impl GameStateManager {
     }
 
     /// Get the current state of a game (mutable)
+    ///
+    /// Changes made through this reference are persisted with the game's
+    /// next update or checkpoint.
     pub fn get_game_state_mut(
         &mut self,
         game_instance_id: &GameInstanceId,
@@ -422,8 +1147,188 @@ impl GameStateManager {
         self.active_games.get_mut(game_instance_id)
     }
 
-    //get active games
-    //remove finished game
+    /// List active games matching `filter`
+    pub fn list_games(&self, filter: &GameFilter) -> Vec<&GameState> {
+        self.active_games
+            .values()
+            .filter(|game_state| filter.matches(game_state))
+            .collect()
+    }
+
+    /// List every game that has not finished yet
+    pub fn get_active_games(&self) -> Vec<&GameState> {
+        self.active_games
+            .values()
+            .filter(|game_state| !game_state.is_finished)
+            .collect()
+    }
+
+    /// Move a finished game, with its final payouts, into the history store
+    pub fn archive_game(&mut self, game_instance_id: &GameInstanceId) -> Result<(), String> {
+        let game_state = self
+            .active_games
+            .get(game_instance_id)
+            .ok_or_else(|| "Game not found".to_string())?;
+        if !game_state.is_finished {
+            return Err("Only finished games can be archived".to_string());
+        }
+        let archived_at = self.clock.unix_timestamp()?;
+
+        let final_state = self.active_games.remove(game_instance_id).unwrap();
+        let payouts = final_state
+            .wagering_state
+            .as_ref()
+            .and_then(|wagering_state| wagering_state.payouts.clone())
+            .unwrap_or_default();
+
+        self.game_history.insert(
+            *game_instance_id,
+            ArchivedGame {
+                final_state,
+                payout_signatures: vec![None; payouts.len()],
+                payouts,
+                archived_at,
+                settlement_signatures: Vec::new(),
+            },
+        );
+        self.persist_game(game_instance_id)?;
+        self.trim_history()
+    }
+
+    /// Record the base-chain transactions that settled an archived game
+    pub fn record_settlement(
+        &mut self,
+        game_instance_id: &GameInstanceId,
+        signatures: &[Signature],
+    ) -> Result<(), String> {
+        let archived = self
+            .game_history
+            .get_mut(game_instance_id)
+            .ok_or_else(|| "Archived game not found".to_string())?;
+        archived.settlement_signatures.extend_from_slice(signatures);
+        self.persist_game(game_instance_id)
+    }
+
+    /// Record the base-chain transaction that paid out the payout at `index`
+    /// of an archived game
+    pub fn record_payout_settlement(
+        &mut self,
+        game_instance_id: &GameInstanceId,
+        index: usize,
+        signature: Signature,
+    ) -> Result<(), String> {
+        let archived = self
+            .game_history
+            .get_mut(game_instance_id)
+            .ok_or_else(|| "Archived game not found".to_string())?;
+        if index >= archived.payouts.len() {
+            return Err("Payout not found".to_string());
+        }
+        archived
+            .payout_signatures
+            .resize(archived.payouts.len(), None);
+        archived.payout_signatures[index] = Some(signature);
+        archived.settlement_signatures.push(signature);
+        self.persist_game(game_instance_id)
+    }
+
+    /// Remove a game from the active set without archiving it. Games with
+    /// committed stakes can't be removed, as that would orphan the stakes.
+    pub fn remove_game(
+        &mut self,
+        game_instance_id: &GameInstanceId,
+    ) -> Result<Option<GameState>, String> {
+        if self
+            .active_games
+            .get(game_instance_id)
+            .is_some_and(GameState::has_committed_stakes)
+        {
+            return Err("Game has committed stakes".to_string());
+        }
+        let removed = self.active_games.remove(game_instance_id);
+        if removed.is_some() {
+            self.persist(StorageRecord::GameRemoved(*game_instance_id))?;
+        }
+        Ok(removed)
+    }
+
+    /// Get an archived game
+    pub fn get_archived_game(&self, game_instance_id: &GameInstanceId) -> Option<&ArchivedGame> {
+        self.game_history.get(game_instance_id)
+    }
+
+    /// List archived games matching `filter`, most recently archived first
+    pub fn list_archived_games(&self, filter: &GameFilter) -> Vec<&ArchivedGame> {
+        let mut games: Vec<&ArchivedGame> = self
+            .game_history
+            .values()
+            .filter(|archived| filter.matches(&archived.final_state))
+            .collect();
+        games.sort_by(|a, b| b.archived_at.cmp(&a.archived_at));
+        games
+    }
+
+    /// Apply the retention policy: archive finished games, evict idle ones
+    /// and trim the history store. Returns the ids of evicted games.
+    ///
+    /// Idle games holding committed stakes are kept, as evicting them would
+    /// orphan the escrowed stakes.
+    pub fn apply_retention_policy(&mut self) -> Result<Vec<GameInstanceId>, String> {
+        let now = self.clock.unix_timestamp()?;
+        let finished: Vec<GameInstanceId> = self
+            .active_games
+            .values()
+            .filter(|game_state| game_state.is_finished)
+            .map(|game_state| game_state.game_instance_id)
+            .collect();
+        for game_instance_id in &finished {
+            self.archive_game(game_instance_id)?;
+        }
+
+        let mut evicted = Vec::new();
+        if let Some(idle_timeout) = self.retention_policy.idle_timeout_seconds {
+            self.active_games.retain(|game_instance_id, game_state| {
+                let last_seen = game_state.last_activity.max(game_state.last_updated);
+                let idle = now.saturating_sub(last_seen) > idle_timeout
+                    && !game_state.has_committed_stakes();
+                if idle {
+                    evicted.push(*game_instance_id);
+                }
+                !idle
+            });
+        }
+        for game_instance_id in &evicted {
+            self.persist(StorageRecord::GameRemoved(*game_instance_id))?;
+        }
+
+        self.trim_history()?;
+        Ok(evicted)
+    }
+
+    fn trim_history(&mut self) -> Result<(), String> {
+        let Some(max_history) = self.retention_policy.max_history else {
+            return Ok(());
+        };
+        if self.game_history.len() <= max_history {
+            return Ok(());
+        }
+
+        // Games with unsettled payouts are kept until they're paid out.
+        let mut by_age: Vec<(u64, GameInstanceId)> = self
+            .game_history
+            .iter()
+            .filter(|(_, archived)| archived.is_settled())
+            .map(|(id, archived)| (archived.archived_at, *id))
+            .collect();
+        by_age.sort();
+        let excess = self.game_history.len() - max_history;
+        for (_, id) in by_age.into_iter().take(excess) {
+            self.game_history.remove(&id);
+            self.persist(StorageRecord::GameRemoved(id))?;
+        }
+        Ok(())
+    }
+
     //get registry
     /// Get registry for external access
     pub fn get_registry(&self) -> &GameEngineRegistry {
@@ -0,0 +1,271 @@
+// Best-of-N match mode
+//
+// Wraps any `GameEngine` and plays rounds of it until one player reaches the
+// required number of round wins. Drawn rounds are replayed, up to a cap, after
+// which the player with the most round wins takes the match (or it is drawn).
+// The wager is only settled once, when the whole match ends.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{clock::Clock, game_traits::*};
+
+/// Result of a single completed round within a match
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct RoundSummary {
+    pub winner: Option<PlayerId>,
+    /// Number of entries in the match `move_history` that belong to this round
+    pub move_count: u32,
+}
+
+/// Match state, stored in `GameState.state_data`
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct MatchState {
+    pub wins: HashMap<PlayerId, u8>,
+    pub draws: u8,
+    pub rounds: Vec<RoundSummary>,
+    /// Serialized state of the inner engine for the round in progress
+    pub round_state_data: Vec<u8>,
+}
+
+impl MatchState {
+    /// Index into the match `move_history` where the current round starts
+    fn round_start(&self) -> usize {
+        self.rounds.iter().map(|r| r.move_count as usize).sum()
+    }
+
+    /// Player with strictly the most round wins, if any
+    fn leader(&self) -> Option<PlayerId> {
+        let best = self.wins.values().copied().max()?;
+        let mut leaders = self.wins.iter().filter(|(_, wins)| **wins == best);
+        match (leaders.next(), leaders.next()) {
+            (Some((player, _)), None) if best > 0 => Some(*player),
+            _ => None,
+        }
+    }
+}
+
+/// Adapter turning any single-round `GameEngine` into a best-of-N match
+pub struct MatchEngine {
+    inner: Box<dyn GameEngine>,
+    wins_required: u8,
+    max_draws: u8,
+}
+
+impl MatchEngine {
+    /// Best-of-`best_of` match (e.g. 3 or 5), replaying up to `max_draws`
+    /// drawn rounds before the match is decided on round wins. `best_of`
+    /// must be odd, so a match can't be tied on round wins.
+    pub fn new(inner: Box<dyn GameEngine>, best_of: u8, max_draws: u8) -> Result<Self, String> {
+        if best_of % 2 == 0 {
+            return Err(format!(
+                "Matches must be best of an odd number, not {}",
+                best_of
+            ));
+        }
+        Ok(Self {
+            inner,
+            wins_required: best_of / 2 + 1,
+            max_draws,
+        })
+    }
+
+    pub fn wins_required(&self) -> u8 {
+        self.wins_required
+    }
+
+    fn best_of(&self) -> u8 {
+        self.wins_required * 2 - 1
+    }
+
+    fn decode(&self, game_state: &GameState) -> Result<MatchState, String> {
+        MatchState::try_from_slice(&game_state.state_data)
+            .map_err(|e| format!("Invalid match state: {}", e))
+    }
+
+    /// Config used to start a fresh round of the inner game. Rounds are only
+    /// used for their state, so the round number serves as the game id.
+    fn round_config(&self, round: u64) -> GameConfig {
+        GameConfig {
+            game_type_id: self.inner.game_type_id(),
+            max_players: self.inner.max_players(),
+            min_players: self.inner.min_players(),
+            timeout_seconds: 0,
+            stake_amount: 0,
+            custom_config: HashMap::new(),
+            game_id: Some(round),
+            wagering_config: None,
+        }
+    }
+
+    /// View of the current round as the inner engine expects it.
+    ///
+    /// Wagering is stripped so the inner engine never pays out per round.
+    fn round_view(&self, game_state: &GameState, match_state: &MatchState) -> GameState {
+        let mut round = game_state.clone();
+        round.game_type_id = self.inner.game_type_id();
+        round.state_data = match_state.round_state_data.clone();
+        round.move_history = game_state.move_history[match_state.round_start()..].to_vec();
+        round.is_finished = false;
+        round.winner = None;
+        round.wagering_state = None;
+        round
+    }
+}
+
+impl GameEngine for MatchEngine {
+    fn game_type_id(&self) -> GameTypeId {
+        format!("best_of_{}_{}", self.best_of(), self.inner.game_type_id())
+    }
+
+    fn display_name(&self) -> String {
+        format!("{} (Best of {})", self.inner.display_name(), self.best_of())
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "First to {} round wins. {}",
+            self.wins_required,
+            self.inner.description()
+        )
+    }
+
+    fn max_players(&self) -> u8 {
+        self.inner.max_players()
+    }
+
+    fn min_players(&self) -> u8 {
+        self.inner.min_players()
+    }
+
+    fn supports_wagering(&self) -> bool {
+        self.inner.supports_wagering()
+    }
+
+    fn default_wagering_config(&self) -> Option<WageringConfig> {
+        self.inner.default_wagering_config()
+    }
+
+    fn create_game(&self, config: &GameConfig, players: &[PlayerId]) -> Result<GameState, String> {
+        // The first round carries the match's game id, so the match has the
+        // same instance id the inner game would have had.
+        let mut round_config = self.round_config(0);
+        round_config.custom_config = config.custom_config.clone();
+        round_config.game_id = config.game_id;
+        let mut game_state = self.inner.create_game(&round_config, players)?;
+
+        let match_state = MatchState {
+            wins: players.iter().map(|player| (*player, 0)).collect(),
+            draws: 0,
+            rounds: Vec::new(),
+            round_state_data: game_state.state_data,
+        };
+
+        game_state.game_type_id = self.game_type_id();
+        game_state.state_data = match_state.try_to_vec().map_err(|e| e.to_string())?;
+        game_state.wagering_state =
+            config
+                .wagering_config
+                .clone()
+                .map(|wagering_config| WageringState {
+                    config: wagering_config,
+                    player_stakes: HashMap::new(),
+                    total_pot: 0,
+                    stakes_committed: false,
+                    payouts: None,
+                });
+
+        Ok(game_state)
+    }
+
+    fn validate_move(&self, game_state: &GameState, game_move: &GameMove) -> GameActionResult {
+        if game_state.is_finished {
+            return GameActionResult::Failure("Match is already finished".to_string());
+        }
+
+        match self.decode(game_state) {
+            Ok(match_state) => self
+                .inner
+                .validate_move(&self.round_view(game_state, &match_state), game_move),
+            Err(e) => GameActionResult::Failure(e),
+        }
+    }
+
+    fn apply_move(
+        &self,
+        game_state: &GameState,
+        game_move: &GameMove,
+    ) -> Result<GameState, String> {
+        let mut match_state = self.decode(game_state)?;
+        let round = self
+            .inner
+            .apply_move(&self.round_view(game_state, &match_state), game_move)?;
+
+        let mut new_state = game_state.clone();
+        new_state.move_history.push(game_move.clone());
+        new_state.last_updated = round.last_updated;
+        new_state.current_player = round.current_player;
+
+        let round_winner = self.inner.check_game_end(&round);
+        if round_winner.is_none() && !round.is_finished {
+            // Round still in progress
+            match_state.round_state_data = round.state_data;
+            new_state.state_data = match_state.try_to_vec().map_err(|e| e.to_string())?;
+            return Ok(new_state);
+        }
+
+        let move_count = (new_state.move_history.len() - match_state.round_start()) as u32;
+        match_state.rounds.push(RoundSummary {
+            winner: round_winner,
+            move_count,
+        });
+        match round_winner {
+            Some(winner) => *match_state.wins.entry(winner).or_default() += 1,
+            None => match_state.draws += 1,
+        }
+
+        let match_winner = match_state
+            .wins
+            .iter()
+            .find(|(_, wins)| **wins >= self.wins_required)
+            .map(|(player, _)| *player);
+
+        if match_winner.is_some() {
+            new_state.is_finished = true;
+            new_state.winner = match_winner;
+        } else if match_state.draws > self.max_draws {
+            // Out of replays, decide on round wins so far
+            new_state.is_finished = true;
+            new_state.winner = match_state.leader();
+        } else {
+            // Start the next round
+            let next_round = self.inner.create_game(
+                &self.round_config(match_state.rounds.len() as u64),
+                &game_state.players,
+            )?;
+            match_state.round_state_data = next_round.state_data;
+            new_state.current_player = next_round.current_player;
+        }
+
+        new_state.state_data = match_state.try_to_vec().map_err(|e| e.to_string())?;
+        Ok(new_state)
+    }
+
+    fn check_game_end(&self, game_state: &GameState) -> Option<PlayerId> {
+        if game_state.is_finished {
+            game_state.winner
+        } else {
+            None
+        }
+    }
+
+    /// Payouts are only ever computed on the finished match, so the inner
+    /// engine's payout rules apply once to the whole pot.
+    fn calculate_payouts(&self, game_state: &GameState) -> Result<Vec<Payout>, String> {
+        self.inner.calculate_payouts(game_state)
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.inner.set_clock(clock);
+    }
+}
@@ -1,10 +1,19 @@
+pub mod match_engine;
 pub mod rock_paper_scissors;
+pub mod svm_engine;
+pub mod wasm_engine;
 use crate::game_traits::GameEngineRegistry;
-pub use rock_paper_scissors::RPSEngine;
+pub use match_engine::MatchEngine;
+pub use rock_paper_scissors::{RPSEngine, RPSProgram};
+pub use svm_engine::{SvmGameEngine, SvmGameProgram};
+pub use wasm_engine::{load_wasm_games, WasmGameEngine, WasmGameManifest};
 
 // Register builtin games here
-pub fn register_builtin_games(registry: &mut GameEngineRegistry) {
+pub fn register_builtin_games(registry: &mut GameEngineRegistry) -> Result<(), String> {
     // register game here
-    // registry.register_engine(engine);
-    registry.register_engine(Box::new(RPSEngine::new()));
+    // registry.register_engine(engine)?;
+    registry.register_engine(Box::new(RPSEngine::new()))?;
+    let best_of_three = MatchEngine::new(Box::new(RPSEngine::new()), 3, 5)?;
+    registry.register_engine(Box::new(best_of_three))?;
+    Ok(())
 }
@@ -14,11 +14,20 @@
 // Scissors loses to Rock
 
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_sdk::pubkey::Pubkey;
-use std::collections::HashMap;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction as SolanaInstruction},
+    pubkey,
+    pubkey::Pubkey,
+    system_program,
+};
+use std::{collections::HashMap, sync::Arc};
 // use solana_sdk::borsh
 
-use crate::game_traits::*;
+use crate::{
+    clock::{Clock, SystemClock},
+    game_traits::*,
+    games::svm_engine::SvmGameProgram,
+};
 
 /// RPS choice enumeration(should serve as move )
 #[derive(Debug, Clone, BorshSerialize, BorshDeserialize, PartialEq)]
@@ -47,11 +56,31 @@ pub enum RPSPhase {
     Finished,   // Game is complete
 }
 
-pub struct RPSEngine;
+/// The on-chain Rock-Paper-Scissors program (`rock_paper_scissors.so`)
+pub const RPS_PROGRAM_ID: Pubkey = pubkey!("B6iwgaDVFX7LXDMokCYT8Ya21gr2FbsUTBPFh2mcfxNa");
+
+pub struct RPSEngine {
+    /// Program whose game PDAs are used as game instance ids
+    program_id: Pubkey,
+    clock: Arc<dyn Clock>,
+}
 
 impl RPSEngine {
     pub fn new() -> Self {
-        Self
+        Self::with_program_id(RPS_PROGRAM_ID)
+    }
+
+    pub fn with_program_id(program_id: Pubkey) -> Self {
+        Self {
+            program_id,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Use `clock` for game timestamps instead of the system time
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
     }
 
     fn determine_winner(&self, rps_state: &RPSGameState) -> Option<PlayerId> {
@@ -92,6 +121,21 @@ impl GameEngine for RPSEngine {
         2
     }
 
+    fn supports_wagering(&self) -> bool {
+        true
+    }
+
+    /// Both players stake the same amount and the winner takes the pot, or
+    /// both get their stake back on a draw
+    fn default_wagering_config(&self) -> Option<WageringConfig> {
+        Some(WageringConfig {
+            wagering_type: WageringType::WinnerTakesAll,
+            min_stake: 1,
+            max_stake: None,
+            equal_stakes: true,
+        })
+    }
+
     fn create_game(&self, config: &GameConfig, players: &[PlayerId]) -> Result<GameState, String> {
         if players.len() != 2 {
             return Err("Rock-Paper-Scissors requires exactly 2 players".to_string());
@@ -109,8 +153,13 @@ impl GameEngine for RPSEngine {
         // let state = game_state.down
         let state_data = game_state.try_to_vec().unwrap();
 
+        let game_id = config
+            .game_id
+            .ok_or_else(|| "Game id not assigned".to_string())?;
+        let now = self.clock.unix_timestamp()?;
+
         Ok(GameState {
-            game_instance_id: Pubkey::new_unique(),
+            game_instance_id: derive_game_instance_id(&self.program_id, &players[0], game_id),
             game_type_id: self.game_type_id(),
             players: players.to_vec(),
             current_player: None,
@@ -118,14 +167,8 @@ impl GameEngine for RPSEngine {
             move_history: Vec::new(),
             is_finished: false,
             winner: None,
-            created_at: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-            last_updated: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+            created_at: now,
+            last_updated: now,
             wagering_state: None,
             last_activity: 0,
         })
@@ -172,11 +215,8 @@ impl GameEngine for RPSEngine {
         let mut new_state = game_state.clone();
         new_state.state_data = rps_state.try_to_vec().unwrap();
         new_state.move_history.push(game_move.clone());
-        new_state.last_updated = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        new_state.is_finished = true;
+        new_state.last_updated = self.clock.unix_timestamp()?;
+        new_state.is_finished = rps_state.choices.len() == 2;
         new_state.winner = rps_state.winner;
 
         Ok(new_state)
@@ -189,4 +229,126 @@ impl GameEngine for RPSEngine {
             None
         }
     }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+}
+
+/// Anchor discriminators of the RPS program's instructions and game account
+const INITIALIZE_GAME_DISCRIMINATOR: [u8; 8] = [44, 62, 102, 247, 126, 208, 130, 215];
+const MAKE_CHOICE_DISCRIMINATOR: [u8; 8] = [207, 18, 251, 32, 135, 122, 160, 77];
+const GAME_ACCOUNT_DISCRIMINATOR: [u8; 8] = [27, 90, 166, 125, 74, 100, 121, 18];
+
+/// The RPS program's game account, following its discriminator
+#[derive(BorshDeserialize)]
+struct RPSProgramGame {
+    _game_id: u64,
+    _player_one: Pubkey,
+    _player_two: Pubkey,
+    _player_one_choice: Option<RPSChoice>,
+    _player_two_choice: Option<RPSChoice>,
+    result: RPSProgramResult,
+}
+
+#[derive(BorshDeserialize)]
+enum RPSProgramResult {
+    InProgress,
+    PlayerOneWins,
+    PlayerTwoWins,
+    Draw,
+}
+
+/// Drives the on-chain RPS program through `SvmGameEngine`. Moves carry a
+/// borsh `RPSChoice`, like `RPSEngine` moves, and the first player creates
+/// the game.
+pub struct RPSProgram {
+    program_id: Pubkey,
+}
+
+impl RPSProgram {
+    pub fn new() -> Self {
+        Self::with_program_id(RPS_PROGRAM_ID)
+    }
+
+    pub fn with_program_id(program_id: Pubkey) -> Self {
+        Self { program_id }
+    }
+}
+
+impl SvmGameProgram for RPSProgram {
+    fn program_id(&self) -> Pubkey {
+        self.program_id
+    }
+
+    fn game_type_id(&self) -> GameTypeId {
+        "rock_paper_scissors_svm".to_string()
+    }
+
+    fn display_name(&self) -> String {
+        "Rock-Paper-Scissors (on-chain)".to_string()
+    }
+
+    fn description(&self) -> String {
+        "Rock-Paper-Scissors played by the on-chain program".to_string()
+    }
+
+    fn min_players(&self) -> u8 {
+        2
+    }
+
+    fn max_players(&self) -> u8 {
+        2
+    }
+
+    fn game_address(&self, players: &[PlayerId], game_id: u64) -> Pubkey {
+        derive_game_instance_id(&self.program_id, &players[0], game_id)
+    }
+
+    fn create_instruction(
+        &self,
+        game: &Pubkey,
+        players: &[PlayerId],
+        game_id: u64,
+    ) -> SolanaInstruction {
+        let mut data = INITIALIZE_GAME_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&players[1].to_bytes());
+        data.extend_from_slice(&game_id.to_le_bytes());
+
+        SolanaInstruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new(*game, false),
+                AccountMeta::new(players[0], true),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            data,
+        }
+    }
+
+    fn move_instruction(&self, game: &Pubkey, game_move: &GameMove) -> SolanaInstruction {
+        // The program rejects anything that isn't a choice.
+        let mut data = MAKE_CHOICE_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&game_move.move_data);
+
+        SolanaInstruction {
+            program_id: self.program_id,
+            accounts: vec![
+                AccountMeta::new(*game, false),
+                AccountMeta::new(game_move.player_id, true),
+            ],
+            data,
+        }
+    }
+
+    fn game_result(&self, state_data: &[u8], players: &[PlayerId]) -> Option<Option<PlayerId>> {
+        let data = state_data.strip_prefix(&GAME_ACCOUNT_DISCRIMINATOR)?;
+        let game = RPSProgramGame::deserialize(&mut &data[..]).ok()?;
+        match game.result {
+            RPSProgramResult::InProgress => None,
+            RPSProgramResult::PlayerOneWins => Some(Some(players[0])),
+            RPSProgramResult::PlayerTwoWins => Some(Some(players[1])),
+            RPSProgramResult::Draw => Some(None),
+        }
+    }
 }
@@ -0,0 +1,327 @@
+// SVM-backed game engine
+//
+// Bridges the native `GameEngine` path and on-chain game programs such as
+// `rock_paper_scissors.so`. Creating a game and applying moves are executed by
+// invoking a configured SBF program through the SVM's
+// `TransactionBatchProcessor`, and `GameState.state_data` mirrors the data of
+// the game's account. The game account address doubles as the
+// `game_instance_id`, so off-chain and on-chain state refer to the same game.
+
+use {
+    crate::{
+        clock::{Clock, SystemClock},
+        game_traits::*,
+        loader::PayTubeAccountLoader,
+        processor::PayTubeRuntime,
+    },
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        account::{AccountSharedData, ReadableAccount},
+        instruction::Instruction as SolanaInstruction,
+        pubkey::Pubkey,
+        transaction::{
+            SanitizedTransaction as SolanaSanitizedTransaction, Transaction as SolanaTransaction,
+        },
+    },
+    std::{
+        collections::{HashMap, HashSet},
+        sync::{Arc, Mutex, RwLock},
+    },
+};
+
+/// Describes how to drive a particular on-chain game program
+pub trait SvmGameProgram: Send + Sync {
+    fn program_id(&self) -> Pubkey;
+    fn game_type_id(&self) -> GameTypeId;
+    fn display_name(&self) -> String;
+    fn description(&self) -> String;
+    fn min_players(&self) -> u8;
+    fn max_players(&self) -> u8;
+
+    /// Address of the game account (usually a PDA, see
+    /// `derive_game_instance_id`) for the creator's `game_id`-th game
+    fn game_address(&self, players: &[PlayerId], game_id: u64) -> Pubkey;
+
+    /// Instruction initializing the game account for the creator's
+    /// `game_id`-th game
+    fn create_instruction(
+        &self,
+        game: &Pubkey,
+        players: &[PlayerId],
+        game_id: u64,
+    ) -> SolanaInstruction;
+
+    /// Instruction applying `game_move` to the game account
+    fn move_instruction(&self, game: &Pubkey, game_move: &GameMove) -> SolanaInstruction;
+
+    /// Decode the game account data. `None` while the game is running,
+    /// `Some(winner)` once it has ended (`Some(None)` for a draw).
+    fn game_result(&self, state_data: &[u8], players: &[PlayerId]) -> Option<Option<PlayerId>>;
+}
+
+/// Accounts modified in the channel, layered over the base chain
+#[derive(Default)]
+struct ChannelAccounts {
+    accounts: HashMap<Pubkey, AccountSharedData>,
+    /// Bumped whenever `accounts` change, so an execution is only committed
+    /// on top of the accounts it ran against
+    revision: u64,
+}
+
+/// The outcome of executing an instruction, not yet kept by the engine
+struct Execution {
+    revision: u64,
+    state_data: Vec<u8>,
+    accounts: HashMap<Pubkey, AccountSharedData>,
+    modified: Vec<(Pubkey, AccountSharedData)>,
+}
+
+/// A move executed by `validate_move`, kept so `apply_move` doesn't execute
+/// it a second time
+struct ValidatedMove {
+    game_move: GameMove,
+    state_data: Vec<u8>,
+    execution: Execution,
+}
+
+/// A `GameEngine` whose rules are enforced by an SBF program run in the SVM
+pub struct SvmGameEngine {
+    program: Box<dyn SvmGameProgram>,
+    /// The SVM runtime, with the game program loaded, shared by every
+    /// execution
+    runtime: PayTubeRuntime,
+    rpc_client: RpcClient,
+    accounts: RwLock<ChannelAccounts>,
+    /// Accounts modified since they were last taken for persisting
+    modified: RwLock<HashMap<Pubkey, AccountSharedData>>,
+    validated: Mutex<Option<ValidatedMove>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SvmGameEngine {
+    /// Fails if `program_elf` isn't a valid SBF program
+    pub fn new(
+        program: Box<dyn SvmGameProgram>,
+        program_elf: Vec<u8>,
+        rpc_client: RpcClient,
+    ) -> Result<Self, String> {
+        let runtime = PayTubeRuntime::new(&PayTubeAccountLoader::new(&rpc_client))?
+            .with_program(program.program_id(), &program_elf)?;
+        Ok(Self {
+            program,
+            runtime,
+            rpc_client,
+            accounts: RwLock::new(ChannelAccounts::default()),
+            modified: RwLock::new(HashMap::new()),
+            validated: Mutex::new(None),
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    /// Use `clock` for game timestamps instead of the system time
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Execute `instruction` in the SVM against the channel's accounts. The
+    /// resulting account changes are only kept once the execution is
+    /// committed, which allows moves to be validated by simulation.
+    fn execute(
+        &self,
+        instruction: SolanaInstruction,
+        payer: &Pubkey,
+        game: &Pubkey,
+    ) -> Result<Execution, String> {
+        let (cache, revision) = {
+            let accounts = self.accounts.read().unwrap();
+            (accounts.accounts.clone(), accounts.revision)
+        };
+        let account_loader = PayTubeAccountLoader::with_cache(&self.rpc_client, cache);
+
+        let svm_transaction = SolanaSanitizedTransaction::try_from_legacy_transaction(
+            SolanaTransaction::new_with_payer(&[instruction], Some(payer)),
+            &HashSet::new(),
+        )
+        .map_err(|e| e.to_string())?;
+
+        let results = self
+            .runtime
+            .check_and_execute(&account_loader, &[svm_transaction]);
+
+        let execution_result = &results.execution_results[0];
+        if !execution_result.was_executed_successfully() {
+            return Err(format!("Game program failed: {:?}", execution_result));
+        }
+        let loaded_transaction = results.loaded_transactions[0]
+            .as_ref()
+            .map_err(|e| e.to_string())?;
+        account_loader.store_accounts(&loaded_transaction.accounts);
+
+        let state_data = loaded_transaction
+            .accounts
+            .iter()
+            .find(|(pubkey, _)| pubkey == game)
+            .map(|(_, account)| account.data().to_vec())
+            .ok_or_else(|| format!("Game account {} not found", game))?;
+
+        Ok(Execution {
+            revision,
+            state_data,
+            accounts: account_loader.into_cache(),
+            modified: loaded_transaction.accounts.clone(),
+        })
+    }
+
+    /// Keep the account changes of `execution`, returning the game account's
+    /// data. Fails if the channel's accounts changed since it was executed.
+    fn commit(&self, execution: Execution) -> Result<Vec<u8>, String> {
+        let mut accounts = self.accounts.write().unwrap();
+        if accounts.revision != execution.revision {
+            return Err("Game accounts changed during execution".to_string());
+        }
+        accounts.accounts = execution.accounts;
+        accounts.revision += 1;
+        self.modified.write().unwrap().extend(execution.modified);
+        Ok(execution.state_data)
+    }
+
+    /// Take the execution of `game_move` made when it was validated, if it
+    /// was the last move validated and ran against the current state
+    fn take_validated(&self, game_state: &GameState, game_move: &GameMove) -> Option<Execution> {
+        let validated = self.validated.lock().unwrap().take()?;
+        let current = validated.game_move == *game_move
+            && validated.state_data == game_state.state_data
+            && validated.execution.revision == self.accounts.read().unwrap().revision;
+        current.then_some(validated.execution)
+    }
+}
+
+impl GameEngine for SvmGameEngine {
+    fn game_type_id(&self) -> GameTypeId {
+        self.program.game_type_id()
+    }
+
+    fn display_name(&self) -> String {
+        self.program.display_name()
+    }
+
+    fn description(&self) -> String {
+        self.program.description()
+    }
+
+    fn max_players(&self) -> u8 {
+        self.program.max_players()
+    }
+
+    fn min_players(&self) -> u8 {
+        self.program.min_players()
+    }
+
+    fn create_game(&self, config: &GameConfig, players: &[PlayerId]) -> Result<GameState, String> {
+        let payer = players
+            .first()
+            .ok_or_else(|| "At least one player is required".to_string())?;
+        let game_id = config
+            .game_id
+            .ok_or_else(|| "Game id not assigned".to_string())?;
+        let now = self.clock.unix_timestamp()?;
+        let game = self.program.game_address(players, game_id);
+        let instruction = self.program.create_instruction(&game, players, game_id);
+        let state_data = self.commit(self.execute(instruction, payer, &game)?)?;
+
+        Ok(GameState {
+            game_instance_id: game,
+            game_type_id: self.game_type_id(),
+            players: players.to_vec(),
+            current_player: None,
+            state_data,
+            move_history: Vec::new(),
+            is_finished: false,
+            winner: None,
+            created_at: now,
+            last_updated: now,
+            wagering_state: None,
+            last_activity: 0,
+        })
+    }
+
+    fn validate_move(&self, game_state: &GameState, game_move: &GameMove) -> GameActionResult {
+        if game_state.is_finished {
+            return GameActionResult::Failure("Game is already finished".to_string());
+        }
+        if !game_state.players.contains(&game_move.player_id) {
+            return GameActionResult::Failure("Player not in game".to_string());
+        }
+
+        // Let the program decide, keeping the execution for `apply_move`
+        // rather than the resulting state.
+        let game = game_state.game_instance_id;
+        let instruction = self.program.move_instruction(&game, game_move);
+        match self.execute(instruction, &game_move.player_id, &game) {
+            Ok(execution) => {
+                *self.validated.lock().unwrap() = Some(ValidatedMove {
+                    game_move: game_move.clone(),
+                    state_data: game_state.state_data.clone(),
+                    execution,
+                });
+                GameActionResult::Success
+            }
+            Err(e) => GameActionResult::Failure(e),
+        }
+    }
+
+    fn apply_move(
+        &self,
+        game_state: &GameState,
+        game_move: &GameMove,
+    ) -> Result<GameState, String> {
+        let now = self.clock.unix_timestamp()?;
+        let execution = match self.take_validated(game_state, game_move) {
+            Some(execution) => execution,
+            None => {
+                let game = game_state.game_instance_id;
+                let instruction = self.program.move_instruction(&game, game_move);
+                self.execute(instruction, &game_move.player_id, &game)?
+            }
+        };
+        let state_data = self.commit(execution)?;
+
+        let mut new_state = game_state.clone();
+        if let Some(winner) = self.program.game_result(&state_data, &game_state.players) {
+            new_state.is_finished = true;
+            new_state.winner = winner;
+        }
+        new_state.state_data = state_data;
+        new_state.move_history.push(game_move.clone());
+        new_state.last_updated = now;
+
+        Ok(new_state)
+    }
+
+    fn check_game_end(&self, game_state: &GameState) -> Option<PlayerId> {
+        if game_state.is_finished {
+            game_state.winner
+        } else {
+            None
+        }
+    }
+
+    fn take_modified_accounts(&self) -> Vec<(Pubkey, AccountSharedData)> {
+        self.modified.write().unwrap().drain().collect()
+    }
+
+    fn restore_accounts(&self, accounts: &HashMap<Pubkey, AccountSharedData>) {
+        let mut channel_accounts = self.accounts.write().unwrap();
+        channel_accounts.accounts.extend(
+            accounts
+                .iter()
+                .map(|(pubkey, account)| (*pubkey, account.clone())),
+        );
+        channel_accounts.revision += 1;
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+}
@@ -0,0 +1,375 @@
+// Dynamically loaded game engines
+//
+// Games can be shipped as WASM modules instead of being compiled into the
+// crate. Each module runs in a sandboxed `wasmi` interpreter with no imports,
+// and a fuel and memory limit per call, and must export:
+//
+// memory                                              linear memory
+// alloc(len: i32) -> i32                              buffer for host input
+// create_game(players_ptr, players_len) -> i64        initial state_data
+// validate_move(state_ptr, state_len,
+//               move_ptr, move_len) -> i32            0 = legal
+// apply_move(state_ptr, state_len,
+//            move_ptr, move_len) -> i64               new state_data
+// check_game_end(state_ptr, state_len) -> i32         see below
+//
+// `players` is the concatenation of each player's 32 byte pubkey and a move is
+// the player's pubkey followed by the game-specific move data. Buffers are
+// returned packed as `(ptr << 32) | len`; a negative value is an error.
+// `check_game_end` returns -1 while the game is running, -2 for a draw, or the
+// index of the winner in `players`.
+
+use solana_sdk::{hash::hashv, pubkey::Pubkey};
+use std::{collections::HashMap, path::Path, sync::Arc};
+use wasmi::{
+    Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    game_traits::*,
+};
+
+/// Fuel available to a single call into a game module
+const DEFAULT_FUEL: u64 = 10_000_000;
+/// Bytes of linear memory available to a single call into a game module
+const DEFAULT_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+const GAME_RUNNING: i32 = -1;
+const GAME_DRAW: i32 = -2;
+
+/// Metadata for a WASM game, supplied alongside the module
+#[derive(Debug, Clone)]
+pub struct WasmGameManifest {
+    pub game_type_id: GameTypeId,
+    pub version: u32,
+    pub display_name: String,
+    pub description: String,
+    pub min_players: u8,
+    pub max_players: u8,
+}
+
+/// A `GameEngine` backed by a sandboxed WASM module
+pub struct WasmGameEngine {
+    manifest: WasmGameManifest,
+    engine: Engine,
+    module: Module,
+    fuel: u64,
+    memory_limit: usize,
+    clock: Arc<dyn Clock>,
+}
+
+/// A single instantiation of the module, used for one engine call
+struct WasmCall {
+    store: Store<StoreLimits>,
+    instance: Instance,
+    memory: Memory,
+}
+
+impl WasmGameEngine {
+    pub fn new(manifest: WasmGameManifest, wasm_bytes: &[u8]) -> Result<Self, String> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm_bytes)
+            .map_err(|e| format!("Invalid game module '{}': {}", manifest.game_type_id, e))?;
+
+        if module.imports().next().is_some() {
+            return Err(format!(
+                "Game module '{}' must not import host functions",
+                manifest.game_type_id
+            ));
+        }
+
+        Ok(Self {
+            manifest,
+            engine,
+            module,
+            fuel: DEFAULT_FUEL,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(manifest: WasmGameManifest, path: P) -> Result<Self, String> {
+        let wasm_bytes = std::fs::read(path.as_ref())
+            .map_err(|e| format!("Failed to read {}: {}", path.as_ref().display(), e))?;
+        Self::new(manifest, &wasm_bytes)
+    }
+
+    /// Override the fuel available to each call into the module
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    /// Override the bytes of linear memory available to each call into the
+    /// module
+    pub fn with_memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = memory_limit;
+        self
+    }
+
+    /// Use `clock` for game timestamps instead of the system time
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// WASM games have no on-chain program, so game instance ids are derived
+    /// under an address standing in for one, unique to the game type
+    fn program_id(&self) -> Pubkey {
+        Pubkey::new_from_array(
+            hashv(&[b"wasm_game", self.manifest.game_type_id.as_bytes()]).to_bytes(),
+        )
+    }
+
+    /// Instantiate a fresh sandbox, so no state leaks between calls
+    fn instantiate(&self) -> Result<WasmCall, String> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.memory_limit)
+            .trap_on_grow_failure(true)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.add_fuel(self.fuel).map_err(|e| e.to_string())?;
+        let instance = Linker::<StoreLimits>::new(&self.engine)
+            .instantiate(&mut store, &self.module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|e| e.to_string())?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| "Game module does not export memory".to_string())?;
+
+        Ok(WasmCall {
+            store,
+            instance,
+            memory,
+        })
+    }
+}
+
+impl WasmCall {
+    /// Copy `data` into module memory, returning `(ptr, len)`
+    fn write(&mut self, data: &[u8]) -> Result<(i32, i32), String> {
+        let alloc = self
+            .instance
+            .get_typed_func::<i32, i32>(&self.store, "alloc")
+            .map_err(|e| format!("alloc: {}", e))?;
+        let len = data.len() as i32;
+        let ptr = alloc
+            .call(&mut self.store, len)
+            .map_err(|e| format!("alloc: {}", e))?;
+        self.memory
+            .write(&mut self.store, ptr as usize, data)
+            .map_err(|e| e.to_string())?;
+        Ok((ptr, len))
+    }
+
+    /// Read a packed `(ptr << 32) | len` buffer out of module memory
+    fn read(&self, packed: i64) -> Result<Vec<u8>, String> {
+        if packed < 0 {
+            return Err(format!("Game module returned error {}", packed));
+        }
+        let ptr = (packed >> 32) as usize;
+        let len = (packed & 0xffff_ffff) as usize;
+        self.memory
+            .data(&self.store)
+            .get(ptr..ptr + len)
+            .map(|bytes| bytes.to_vec())
+            .ok_or_else(|| "Game module returned out of bounds buffer".to_string())
+    }
+
+    fn call<Params: wasmi::WasmParams, Results: wasmi::WasmResults>(
+        &mut self,
+        name: &str,
+        params: Params,
+    ) -> Result<Results, String> {
+        self.instance
+            .get_typed_func::<Params, Results>(&self.store, name)
+            .map_err(|e| format!("{}: {}", name, e))?
+            .call(&mut self.store, params)
+            .map_err(|e| format!("{}: {}", name, e))
+    }
+}
+
+fn encode_move(game_move: &GameMove) -> Vec<u8> {
+    let mut data = game_move.player_id.to_bytes().to_vec();
+    data.extend_from_slice(&game_move.move_data);
+    data
+}
+
+impl GameEngine for WasmGameEngine {
+    fn game_type_id(&self) -> GameTypeId {
+        self.manifest.game_type_id.clone()
+    }
+
+    fn version(&self) -> u32 {
+        self.manifest.version
+    }
+
+    fn display_name(&self) -> String {
+        self.manifest.display_name.clone()
+    }
+
+    fn description(&self) -> String {
+        self.manifest.description.clone()
+    }
+
+    fn max_players(&self) -> u8 {
+        self.manifest.max_players
+    }
+
+    fn min_players(&self) -> u8 {
+        self.manifest.min_players
+    }
+
+    fn create_game(&self, config: &GameConfig, players: &[PlayerId]) -> Result<GameState, String> {
+        if players.len() < self.min_players() as usize
+            || players.len() > self.max_players() as usize
+        {
+            return Err(format!(
+                "{} requires between {} and {} players",
+                self.display_name(),
+                self.min_players(),
+                self.max_players()
+            ));
+        }
+
+        let mut call = self.instantiate()?;
+        let player_bytes: Vec<u8> = players.iter().flat_map(|p| p.to_bytes()).collect();
+        let (ptr, len) = call.write(&player_bytes)?;
+        let packed: i64 = call.call("create_game", (ptr, len))?;
+        let state_data = call.read(packed)?;
+
+        let game_id = config
+            .game_id
+            .ok_or_else(|| "Game id not assigned".to_string())?;
+        let now = self.clock.unix_timestamp()?;
+
+        Ok(GameState {
+            game_instance_id: derive_game_instance_id(&self.program_id(), &players[0], game_id),
+            game_type_id: self.game_type_id(),
+            players: players.to_vec(),
+            current_player: None,
+            state_data,
+            move_history: Vec::new(),
+            is_finished: false,
+            winner: None,
+            created_at: now,
+            last_updated: now,
+            wagering_state: None,
+            last_activity: 0,
+        })
+    }
+
+    fn validate_move(&self, game_state: &GameState, game_move: &GameMove) -> GameActionResult {
+        if game_state.is_finished {
+            return GameActionResult::Failure("Game is already finished".to_string());
+        }
+        if !game_state.players.contains(&game_move.player_id) {
+            return GameActionResult::Failure("Player not in game".to_string());
+        }
+
+        let result = self.instantiate().and_then(|mut call| {
+            let (state_ptr, state_len) = call.write(&game_state.state_data)?;
+            let (move_ptr, move_len) = call.write(&encode_move(game_move))?;
+            call.call::<_, i32>("validate_move", (state_ptr, state_len, move_ptr, move_len))
+        });
+
+        match result {
+            Ok(0) => GameActionResult::Success,
+            Ok(code) => GameActionResult::Failure(format!("Illegal move (code {})", code)),
+            Err(e) => GameActionResult::Failure(e),
+        }
+    }
+
+    fn apply_move(
+        &self,
+        game_state: &GameState,
+        game_move: &GameMove,
+    ) -> Result<GameState, String> {
+        let mut call = self.instantiate()?;
+        let (state_ptr, state_len) = call.write(&game_state.state_data)?;
+        let (move_ptr, move_len) = call.write(&encode_move(game_move))?;
+        let packed: i64 = call.call("apply_move", (state_ptr, state_len, move_ptr, move_len))?;
+        let state_data = call.read(packed)?;
+
+        let (end_ptr, end_len) = call.write(&state_data)?;
+        let end: i32 = call.call("check_game_end", (end_ptr, end_len))?;
+
+        let mut new_state = game_state.clone();
+        new_state.state_data = state_data;
+        new_state.move_history.push(game_move.clone());
+        new_state.last_updated = self.clock.unix_timestamp()?;
+        match end {
+            GAME_RUNNING => {}
+            GAME_DRAW => new_state.is_finished = true,
+            index => {
+                let winner = game_state
+                    .players
+                    .get(index as usize)
+                    .ok_or_else(|| format!("Game module returned unknown winner {}", index))?;
+                new_state.is_finished = true;
+                new_state.winner = Some(*winner);
+            }
+        }
+
+        Ok(new_state)
+    }
+
+    fn check_game_end(&self, game_state: &GameState) -> Option<PlayerId> {
+        if game_state.is_finished {
+            game_state.winner
+        } else {
+            None
+        }
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+}
+
+/// Load every `<name>.wasm` in `dir` that has a matching manifest entry.
+///
+/// Lets game designers drop new games into a directory without rebuilding
+/// the channel binary. Either every game is registered, or none are.
+pub fn load_wasm_games<P: AsRef<Path>>(
+    registry: &mut GameEngineRegistry,
+    dir: P,
+    manifests: &HashMap<String, WasmGameManifest>,
+) -> Result<Vec<GameTypeId>, String> {
+    let entries = std::fs::read_dir(dir.as_ref())
+        .map_err(|e| format!("Failed to read {}: {}", dir.as_ref().display(), e))?;
+
+    let mut engines = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("wasm") {
+            continue;
+        }
+        let Some(manifest) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| manifests.get(stem))
+        else {
+            continue;
+        };
+
+        engines.push(WasmGameEngine::from_file(manifest.clone(), &path)?);
+    }
+
+    let mut loaded = Vec::new();
+    for engine in engines {
+        let versioned_game_type_id = engine.versioned_game_type_id();
+        if let Err(e) = registry.register_engine(Box::new(engine)) {
+            for game_type_id in &loaded {
+                registry.unregister_engine(game_type_id);
+            }
+            return Err(e);
+        }
+        loaded.push(versioned_game_type_id);
+    }
+    Ok(loaded)
+}
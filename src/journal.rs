@@ -0,0 +1,169 @@
+//! A journal of settlement progress, so partially applied settlements can be
+//! completed.
+//!
+//! A settlement is usually several base chain transactions, and any of them
+//! can fail after the others have landed. The journal records every planned
+//! transaction and how far it got:
+//!
+//! ```text
+//!
+//!   Planned  ->  Submitted  ->  Confirmed
+//!      ^             |
+//!      +-------------+   blockhash expired without the transaction landing;
+//!                        re-planned on a new blockhash and signed again
+//! ```
+//!
+//! A transaction is marked `Submitted`, and the journal saved, before it is
+//! sent. After a crash, resuming checks each submitted transaction's
+//! signature on the base chain, so nothing that already landed is sent twice.
+
+use {
+    crate::signing::PendingSettlement,
+    serde::{Deserialize, Serialize},
+    solana_sdk::{pubkey::Pubkey, signature::Signature, signer::Signer},
+    std::{fs, path::PathBuf},
+};
+
+/// How far a settlement transaction got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SettlementStatus {
+    Planned,
+    Submitted,
+    Confirmed,
+}
+
+/// A settlement transaction and its status.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub settlement: PendingSettlement,
+    pub status: SettlementStatus,
+}
+
+/// The settlement transactions of a channel, in the order they're sent.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SettlementJournal {
+    entries: Vec<JournalEntry>,
+    /// Where the journal is saved after every change, if anywhere.
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl SettlementJournal {
+    /// A journal kept in memory only.
+    pub fn new(planned: Vec<PendingSettlement>) -> Self {
+        Self {
+            entries: planned
+                .into_iter()
+                .map(|settlement| JournalEntry {
+                    settlement,
+                    status: SettlementStatus::Planned,
+                })
+                .collect(),
+            path: None,
+        }
+    }
+
+    /// A journal saved to `path`.
+    pub fn create(
+        path: impl Into<PathBuf>,
+        planned: Vec<PendingSettlement>,
+    ) -> Result<Self, String> {
+        let mut journal = Self::new(planned);
+        journal.path = Some(path.into());
+        journal.save()?;
+        Ok(journal)
+    }
+
+    /// Load a journal saved to `path`, to resume its settlement.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, String> {
+        let path = path.into();
+        let json = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let mut journal: Self = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+        journal.path = Some(path);
+        Ok(journal)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        // Write to a temporary file first, so a crash never leaves a torn
+        // journal behind.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, json).map_err(|e| e.to_string())?;
+        fs::rename(&tmp, path).map_err(|e| e.to_string())
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// The transactions that haven't been confirmed yet.
+    pub fn unconfirmed(&self) -> impl Iterator<Item = usize> + '_ {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.status != SettlementStatus::Confirmed)
+            .map(|(index, _)| index)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.unconfirmed().next().is_none()
+    }
+
+    /// The signatures of the confirmed transactions.
+    pub fn confirmed_signatures(&self) -> Vec<Signature> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.status == SettlementStatus::Confirmed)
+            .map(|entry| entry.settlement.signature())
+            .collect()
+    }
+
+    /// Update the status of a transaction, saving the journal.
+    pub fn set_status(&mut self, index: usize, status: SettlementStatus) -> Result<(), String> {
+        self.entries[index].status = status;
+        self.save()
+    }
+
+    /// Replace a transaction that can no longer land, e.g. because its
+    /// blockhash expired, saving the journal.
+    pub fn replan(&mut self, index: usize, settlement: PendingSettlement) -> Result<(), String> {
+        self.entries[index] = JournalEntry {
+            settlement,
+            status: SettlementStatus::Planned,
+        };
+        self.save()
+    }
+
+    /// Sign every planned transaction that needs `signer`, saving the
+    /// journal.
+    pub fn sign(&mut self, signer: &dyn Signer) -> Result<(), String> {
+        for entry in &mut self.entries {
+            if entry.status == SettlementStatus::Planned {
+                entry.settlement.sign(signer)?;
+            }
+        }
+        self.save()
+    }
+
+    /// Add a signature produced elsewhere to a planned transaction, saving
+    /// the journal.
+    pub fn add_signature(
+        &mut self,
+        index: usize,
+        signer: &Pubkey,
+        signature: Signature,
+    ) -> Result<(), String> {
+        let entry = self
+            .entries
+            .get_mut(index)
+            .ok_or_else(|| format!("No settlement transaction {}", index))?;
+        if entry.status != SettlementStatus::Planned {
+            return Err("Settlement transaction was already submitted".to_string());
+        }
+        entry.settlement.add_signature(signer, signature)?;
+        self.save()
+    }
+}
@@ -53,33 +53,64 @@
 //! `TransactionProcessingCallback` interface, and provides it to the
 //! `TransactionBatchProcessor` to process PayTube transactions.
 
-mod game_channel;
+pub mod channel;
+pub mod channel_transaction;
+pub mod clock;
+pub mod escrow;
+pub mod events;
+pub mod fees;
+pub mod game_channel;
 pub mod game_traits;
 pub mod games;
+pub mod journal;
 mod loader;
+pub mod packer;
 mod processor;
 mod settler;
 mod settler_two;
+pub mod signing;
+pub mod simulation;
+pub mod storage;
+pub mod token;
 pub mod transaction;
 pub mod transaction_two;
 
 // pub use game_channel::PlayChannel;
+pub use settler::NettingMode;
 use {
     crate::{
-        loader::PayTubeAccountLoader, settler::PayTubeSettler, settler_two::PlayChannelSettler,
+        channel::{ChannelDeposit, ChannelSession},
+        channel_transaction::ChannelTransaction,
+        fees::ChannelFees,
+        journal::{JournalEntry, SettlementJournal},
+        loader::PayTubeAccountLoader,
+        packer::SettlementPacker,
+        settler::PayTubeSettler,
+        settler_two::PlayChannelSettler,
+        signing::PendingSettlement,
+        simulation::SimulationReport,
+        storage::{ChannelStorage, StorageRecord},
+        token::{token_amount, MintInfo},
         transaction::PayTubeTransaction,
     },
-    processor::{create_transaction_batch_processor, get_transaction_check_results},
+    channel_transaction::create_svm_transactions as create_channel_svm_transactions,
+    processor::{check_transfer_ids, get_transaction_check_results, PayTubeRuntime},
     solana_client::rpc_client::RpcClient,
-    solana_compute_budget::compute_budget::ComputeBudget,
     solana_sdk::{
-        feature_set::FeatureSet, fee::FeeStructure, hash::Hash, rent_collector::RentCollector,
-        signature::Keypair,
+        account::ReadableAccount,
+        instruction::{Instruction as SolanaInstruction, InstructionError},
+        message::Message,
+        pubkey::Pubkey,
+        signature::{Keypair, Signature},
+        signer::Signer,
+        transaction::{Result as TransactionResult, TransactionError},
     },
-    solana_svm::transaction_processor::{
-        TransactionProcessingConfig, TransactionProcessingEnvironment,
+    solana_svm::transaction_processing_callback::TransactionProcessingCallback,
+    std::{
+        collections::{HashMap, HashSet},
+        path::PathBuf,
+        sync::Mutex,
     },
-    std::sync::Arc,
     transaction::{create_svm_transactions, create_svm_transactions2},
     transaction_two::RpsTransaction,
 };
@@ -88,15 +119,425 @@ use {
 ///
 /// Facilitates native SOL or SPL token transfers amongst various channel
 /// participants, settling the final changes in balances to the base chain.
+/// Transactions invoking any other program can be run through the channel as
+/// well.
+///
+/// A channel that hasn't been `open`ed settles every batch of transfers
+/// straight away. An open channel limits transfers to the participants'
+/// deposits and settles the net result when it is `close`d.
 pub struct PayTubeChannel {
-    /// I think you know why this is a bad idea...
-    keys: Vec<Keypair>,
+    /// Controls the channel's deposits and pays its fees.
+    authority: Keypair,
+    /// Participants' signers held by the channel. Participants without one
+    /// sign settlement transactions themselves.
+    signers: Vec<Box<dyn Signer>>,
     rpc_client: RpcClient,
+    packer: SettlementPacker,
+    nonce_accounts: Vec<Pubkey>,
+    netting: NettingMode,
+    fees: Option<ChannelFees>,
+    /// Pays for token accounts created for new token holders, if not the
+    /// authority.
+    token_account_payer: Option<Pubkey>,
+    session: Mutex<Option<ChannelSession>>,
+    /// Where settlement journals are saved, if anywhere.
+    journal_path: Option<PathBuf>,
+    /// The last settlement, if it didn't complete.
+    unfinished_settlement: Mutex<Option<SettlementJournal>>,
+    /// Senders' ids of the transfers processed by the channel.
+    transfer_ids: Mutex<HashSet<(Pubkey, u64)>>,
+    /// Durable storage for the open session and the transfer ids, if any.
+    storage: Mutex<Option<Box<dyn ChannelStorage>>>,
 }
 
 impl PayTubeChannel {
+    /// Create a channel holding every participant's keypair. The first key
+    /// is the channel's authority.
     pub fn new(keys: Vec<Keypair>, rpc_client: RpcClient) -> Self {
-        Self { keys, rpc_client }
+        let mut keys = keys.into_iter();
+        let authority = keys.next().expect("A channel needs an authority key");
+        let signers = keys.map(|key| Box::new(key) as Box<dyn Signer>).collect();
+        Self::with_signers(authority, signers, rpc_client)
+    }
+
+    /// Create a channel that only holds its own `authority` key, and
+    /// whichever participants' `signers` are provided.
+    pub fn with_signers(
+        authority: Keypair,
+        signers: Vec<Box<dyn Signer>>,
+        rpc_client: RpcClient,
+    ) -> Self {
+        Self {
+            authority,
+            signers,
+            rpc_client,
+            packer: SettlementPacker::default(),
+            nonce_accounts: Vec::new(),
+            netting: NettingMode::default(),
+            fees: None,
+            token_account_payer: None,
+            session: Mutex::new(None),
+            journal_path: None,
+            unfinished_settlement: Mutex::new(None),
+            transfer_ids: Mutex::new(HashSet::new()),
+            storage: Mutex::new(None),
+        }
+    }
+
+    /// Save the progress of every settlement to a journal at `path`, so an
+    /// interrupted settlement can be resumed after a restart.
+    pub fn with_journal(mut self, path: impl Into<PathBuf>) -> Self {
+        self.journal_path = Some(path.into());
+        self
+    }
+
+    /// Pack settlement transactions with `packer`, e.g. to compile them
+    /// against address lookup tables covering the participants.
+    pub fn with_packer(mut self, packer: SettlementPacker) -> Self {
+        self.packer = packer;
+        self
+    }
+
+    /// Build settlement transactions on the durable nonces in
+    /// `nonce_accounts` instead of a recent blockhash, so participants can
+    /// take their time co-signing them. The channel's authority must be the
+    /// nonce authority, and there must be a nonce account per settlement
+    /// transaction.
+    pub fn with_nonce_accounts(mut self, nonce_accounts: Vec<Pubkey>) -> Self {
+        self.nonce_accounts = nonce_accounts;
+        self
+    }
+
+    /// How the transfers of a channel that isn't open are netted before
+    /// they're settled. Open channels always settle each participant's final
+    /// balance.
+    pub fn with_netting(mut self, netting: NettingMode) -> Self {
+        self.netting = netting;
+        self
+    }
+
+    /// Charge `fees` on the channel's transfers. They apply to channels
+    /// opened after this is set.
+    pub fn with_fees(mut self, fees: ChannelFees) -> Self {
+        self.fees = Some(fees);
+        self
+    }
+
+    /// Have `payer` pay for the token accounts created when tokens are sent
+    /// to someone who doesn't hold any yet, rather than the authority. The
+    /// payer has to sign settlements.
+    pub fn with_token_account_payer(mut self, payer: Pubkey) -> Self {
+        self.token_account_payer = Some(payer);
+        self
+    }
+
+    /// Persist the open channel's balances and the ids of the transfers it
+    /// processed to `storage`, restoring the ones it holds from before a
+    /// restart. The channel needs a storage of its own. The mints of a
+    /// restored session's deposits are loaded again from the base chain.
+    pub fn with_storage(mut self, storage: Box<dyn ChannelStorage>) -> Result<Self, String> {
+        let snapshot = storage
+            .load()
+            .map_err(|e| format!("Failed to load channel state: {}", e))?;
+        if let Some(stored) = snapshot.session {
+            let account_loader = PayTubeAccountLoader::new(&self.rpc_client);
+            let session = ChannelSession::restore(stored, |pubkey| {
+                account_loader.get_account_shared_data(pubkey)
+            })?;
+            *self.session.get_mut().unwrap() = Some(session);
+        }
+        self.transfer_ids
+            .get_mut()
+            .unwrap()
+            .extend(snapshot.transfer_ids);
+        *self.storage.get_mut().unwrap() = Some(storage);
+        Ok(self)
+    }
+
+    fn persist(&self, record: StorageRecord) -> Result<(), String> {
+        if let Some(storage) = self.storage.lock().unwrap().as_mut() {
+            storage
+                .append(&record)
+                .map_err(|e| format!("Failed to persist channel state: {}", e))?;
+        }
+        Ok(())
+    }
+
+    fn settler(&self) -> PayTubeSettler {
+        PayTubeSettler::with_packer(&self.rpc_client, self.packer.clone())
+            .with_nonce_accounts(self.nonce_accounts.clone())
+            .with_netting(self.netting)
+            .with_fees(self.fees.clone())
+            .with_token_account_payer(self.token_account_payer())
+    }
+
+    fn token_account_payer(&self) -> Pubkey {
+        self.token_account_payer.unwrap_or_else(|| self.authority())
+    }
+
+    /// The key controlling the channel's deposits, and paying its fees.
+    pub fn authority(&self) -> Pubkey {
+        self.authority.pubkey()
+    }
+
+    fn all_signers(&self) -> Vec<&dyn Signer> {
+        std::iter::once(&self.authority as &dyn Signer)
+            .chain(self.signers.iter().map(|signer| signer.as_ref()))
+            .collect()
+    }
+
+    /// Instructions, signed by the depositor, that lock up a deposit for this
+    /// channel. They must land on the base chain before the channel is opened.
+    pub fn deposit_instructions(
+        &self,
+        deposit: &ChannelDeposit,
+    ) -> Result<Vec<SolanaInstruction>, String> {
+        let account_loader = PayTubeAccountLoader::new(&self.rpc_client);
+        let mint = deposit
+            .mint
+            .map(|mint| {
+                let account = account_loader
+                    .get_account_shared_data(&mint)
+                    .ok_or_else(|| format!("Mint {} not found", mint))?;
+                MintInfo::from_account(&mint, &account)
+            })
+            .transpose()?;
+        ChannelSession::escrow(&self.authority(), mint.as_ref()).deposit_instructions(
+            &self.authority(),
+            &deposit.owner,
+            deposit.amount,
+        )
+    }
+
+    /// Open the channel to `participants`, after verifying their `deposits`
+    /// on the base chain.
+    pub fn open(
+        &mut self,
+        participants: &[Pubkey],
+        deposits: &[ChannelDeposit],
+    ) -> Result<(), String> {
+        if self.session.lock().unwrap().is_some() {
+            return Err("Channel is already open".to_string());
+        }
+        let account_loader = PayTubeAccountLoader::new(&self.rpc_client);
+        let session = ChannelSession::open(
+            self.authority(),
+            participants,
+            deposits,
+            self.fees.clone(),
+            |pubkey| account_loader.get_account_shared_data(pubkey),
+        )?;
+        self.persist(StorageRecord::SessionUpdated(Some(session.to_stored())))?;
+        *self.session.lock().unwrap() = Some(session);
+        Ok(())
+    }
+
+    /// A participant's balance in the open channel.
+    pub fn balance(&self, owner: &Pubkey, mint: Option<Pubkey>) -> Option<u64> {
+        self.session
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|session| session.balance(owner, mint))
+    }
+
+    /// The transactions settling the participants' final balances from
+    /// their deposits and revoking their token delegations.
+    fn settlement_transactions(
+        &self,
+        session: &ChannelSession,
+    ) -> Result<Vec<PendingSettlement>, String> {
+        let mut pending = self.prepare_settlement(session)?;
+        if !session.shares_settlement_costs() {
+            return Ok(pending);
+        }
+
+        // Charge the participants what settling costs, reimbursing the
+        // authority as part of the settlement. Charging them changes the
+        // settlement, and can change how many signatures it takes, so the
+        // cost is charged again until it covers the settlement it's part of.
+        let lamports_per_signature = self.lamports_per_signature()?;
+        let mut charged = 0;
+        loop {
+            let signatures: u64 = pending
+                .iter()
+                .map(|settlement| settlement.signers().len() as u64)
+                .sum();
+            let cost = signatures * lamports_per_signature;
+            if cost <= charged {
+                return Ok(pending);
+            }
+            let mut charged_session = session.clone();
+            charged_session.charge_settlement_cost(cost);
+            pending = self.prepare_settlement(&charged_session)?;
+            charged = cost;
+        }
+    }
+
+    /// What the base chain charges per signature: the fee of a message with
+    /// a single signature.
+    fn lamports_per_signature(&self) -> Result<u64, String> {
+        let blockhash = self
+            .rpc_client
+            .get_latest_blockhash()
+            .map_err(|e| e.to_string())?;
+        let message = Message::new_with_blockhash(&[], Some(&self.authority()), &blockhash);
+        self.rpc_client
+            .get_fee_for_message(&message)
+            .map_err(|e| e.to_string())
+    }
+
+    fn prepare_settlement(
+        &self,
+        session: &ChannelSession,
+    ) -> Result<Vec<PendingSettlement>, String> {
+        let mut instructions = session.close_instructions(|deposit| {
+            self.rpc_client
+                .get_balance(deposit)
+                .map_err(|e| e.to_string())
+        })?;
+        instructions.extend(session.revoke_instructions()?);
+
+        self.settler()
+            .prepare(&instructions, &self.authority(), &self.all_signers())
+    }
+
+    /// The settlement transactions closing the channel would produce,
+    /// without closing it.
+    pub fn plan_close(&self) -> Result<Vec<PendingSettlement>, String> {
+        let session = self.session.lock().unwrap();
+        self.settlement_transactions(session.as_ref().ok_or("Channel is not open")?)
+    }
+
+    /// Simulate settlement transactions on the base chain, without sending
+    /// them. They don't need to be fully signed.
+    pub fn simulate_settlement(
+        &self,
+        pending: &[PendingSettlement],
+    ) -> Result<Vec<SimulationReport>, String> {
+        self.settler().simulate(pending)
+    }
+
+    /// Close the channel, returning its settlement transactions signed by
+    /// the channel and the participants' signers it holds.
+    ///
+    /// The remaining participants sign each transaction's `message_data`,
+    /// and the transactions are sent with `submit_settlement` once complete.
+    pub fn prepare_close(&mut self) -> Result<Vec<PendingSettlement>, String> {
+        let mut session = self.session.lock().unwrap();
+        let pending =
+            self.settlement_transactions(session.as_ref().ok_or("Channel is not open")?)?;
+        self.persist(StorageRecord::SessionUpdated(None))?;
+        *session = None;
+        Ok(pending)
+    }
+
+    /// Submit the settlement transactions of a closed channel. Nothing is
+    /// sent unless every transaction is fully signed.
+    pub fn submit_settlement(
+        &self,
+        pending: &[PendingSettlement],
+    ) -> Result<Vec<Signature>, String> {
+        // A settlement that can't be sent at all isn't kept as unfinished,
+        // so it can be signed and submitted again.
+        for settlement in pending {
+            settlement.transaction()?;
+        }
+        if self.unfinished_settlement.lock().unwrap().is_some() {
+            return Err("An earlier settlement is unfinished".to_string());
+        }
+        let journal = match &self.journal_path {
+            Some(path) => {
+                if path.exists() && !SettlementJournal::load(path)?.is_complete() {
+                    return Err("An earlier settlement is unfinished".to_string());
+                }
+                SettlementJournal::create(path, pending.to_vec())?
+            }
+            None => SettlementJournal::new(pending.to_vec()),
+        };
+        self.run_settlement(journal)
+    }
+
+    /// Complete the last settlement, if it didn't complete, or the one in the
+    /// channel's journal after a restart.
+    pub fn resume_settlement(&self) -> Result<Vec<Signature>, String> {
+        let unfinished = self.unfinished_settlement.lock().unwrap().take();
+        let journal = match (unfinished, &self.journal_path) {
+            (Some(journal), _) => journal,
+            (None, Some(path)) if path.exists() => SettlementJournal::load(path)?,
+            _ => return Err("No settlement to resume".to_string()),
+        };
+        self.run_settlement(journal)
+    }
+
+    /// The transactions of the last settlement, if it didn't complete, or of
+    /// the unfinished one in the channel's journal after a restart.
+    ///
+    /// Planned transactions can be missing signatures, e.g. after being
+    /// planned again on a new blockhash. They're signed with
+    /// `add_settlement_signature` before the settlement is resumed.
+    pub fn unfinished_settlement(&self) -> Result<Option<Vec<JournalEntry>>, String> {
+        let mut unfinished = self.unfinished_settlement.lock().unwrap();
+        Ok(self
+            .unfinished_journal(&mut unfinished)?
+            .map(|journal| journal.entries().to_vec()))
+    }
+
+    /// Add a participant's signature, over its `message_data`, to transaction
+    /// `index` of the unfinished settlement.
+    pub fn add_settlement_signature(
+        &self,
+        index: usize,
+        signer: &Pubkey,
+        signature: Signature,
+    ) -> Result<(), String> {
+        let mut unfinished = self.unfinished_settlement.lock().unwrap();
+        self.unfinished_journal(&mut unfinished)?
+            .ok_or("No settlement to resume")?
+            .add_signature(index, signer, signature)
+    }
+
+    /// The unfinished settlement held by the channel, loading it from the
+    /// channel's journal after a restart.
+    fn unfinished_journal<'a>(
+        &self,
+        unfinished: &'a mut Option<SettlementJournal>,
+    ) -> Result<Option<&'a mut SettlementJournal>, String> {
+        if unfinished.is_none() {
+            if let Some(path) = self.journal_path.as_ref().filter(|path| path.exists()) {
+                let journal = SettlementJournal::load(path)?;
+                if !journal.is_complete() {
+                    *unfinished = Some(journal);
+                }
+            }
+        }
+        Ok(unfinished.as_mut())
+    }
+
+    fn run_settlement(&self, mut journal: SettlementJournal) -> Result<Vec<Signature>, String> {
+        let result = self.settler().settle(&mut journal, &self.all_signers());
+        if result.is_err() {
+            *self.unfinished_settlement.lock().unwrap() = Some(journal);
+        }
+        result
+    }
+
+    /// Close the channel and settle it straight away, for channels holding
+    /// the signers of every participant that needs to sign.
+    ///
+    /// If any signature is missing, the channel stays open.
+    pub fn close(&mut self) -> Result<Vec<Signature>, String> {
+        let mut session = self.session.lock().unwrap();
+        let pending =
+            self.settlement_transactions(session.as_ref().ok_or("Channel is not open")?)?;
+        for transaction in &pending {
+            transaction.transaction()?;
+        }
+        self.persist(StorageRecord::SessionUpdated(None))?;
+        *session = None;
+        drop(session);
+
+        self.submit_settlement(&pending)
     }
 
     /// The PayTube API. Processes a batch of PayTube transactions.
@@ -108,57 +549,256 @@ impl PayTubeChannel {
     /// * Custom Solana transaction ordering (e.g. MEV).
     ///
     /// The general scaffold of the PayTube API would remain the same.
-    pub fn process_paytube_transfers(&self, transactions: &[PayTubeTransaction]) {
-        // PayTube default configs.
-        let compute_budget = ComputeBudget::default();
-        let feature_set = FeatureSet::all_enabled();
-        let fee_structure = FeeStructure::default();
-        let lamports_per_signature = fee_structure.lamports_per_signature;
-        let rent_collector = RentCollector::default();
+    ///
+    /// Returns the outcome of each transfer, with the error it was rejected
+    /// or failed with, or an error if the transfers couldn't be settled.
+    pub fn process_paytube_transfers(
+        &self,
+        transactions: &[PayTubeTransaction],
+    ) -> Result<Vec<TransactionResult<()>>, String> {
+        let mut session = self.session.lock().unwrap();
 
         // PayTube loader/callback implementation.
         let account_loader = PayTubeAccountLoader::new(&self.rpc_client);
 
-        // Solana SVM transaction batch processor.
-        let processor =
-            create_transaction_batch_processor(&account_loader, &feature_set, &compute_budget);
-
-        // The PayTube transaction processing runtime environment.
-        let processing_environment = TransactionProcessingEnvironment {
-            blockhash: Hash::default(),
-            epoch_total_stake: None,
-            epoch_vote_accounts: None,
-            feature_set: Arc::new(feature_set),
-            fee_structure: Some(&fee_structure),
-            lamports_per_signature,
-            rent_collector: Some(&rent_collector),
+        // Solana SVM transaction batch processor, with PayTube's configs. An
+        // open channel charges its own fees, when it settles.
+        let runtime = PayTubeRuntime::new(&account_loader)?;
+        let runtime = match session.as_ref() {
+            Some(_) => runtime.without_fees(),
+            None => runtime,
         };
 
-        // The PayTube transaction processing config for Solana SVM.
-        let processing_config = TransactionProcessingConfig {
-            compute_budget: Some(compute_budget),
-            ..Default::default()
+        // 1. Convert to an SVM transaction batch, with token transfers going
+        // through their mint's token program, creating the recipient's token
+        // account if needed. Transfers of tokens whose mint can't be loaded
+        // are rejected on their own.
+        let mints: HashMap<Pubkey, MintInfo> = transactions
+            .iter()
+            .filter_map(|transaction| transaction.mint)
+            .filter_map(|mint| {
+                let account = account_loader.get_account_shared_data(&mint)?;
+                let mint_info = MintInfo::from_account(&mint, &account).ok()?;
+                Some((mint, mint_info))
+            })
+            .collect();
+        let is_known_mint = |transaction: &&PayTubeTransaction| {
+            transaction
+                .mint
+                .map_or(true, |mint| mints.contains_key(&mint))
         };
+        let accepted: Vec<PayTubeTransaction> =
+            transactions.iter().filter(is_known_mint).cloned().collect();
+        // Participants of an open channel spend their balances in the
+        // channel, not what they hold on the base chain.
+        if let Some(session) = session.as_ref() {
+            let accounts = session.virtual_accounts(&accepted, &mints, |address| {
+                account_loader.get_account_shared_data(address)
+            })?;
+            account_loader.store_accounts(&accounts);
+        }
+        let svm_transactions = create_svm_transactions(
+            &accepted,
+            &mints,
+            &self.token_account_payer(),
+            |token_account| {
+                account_loader
+                    .get_account_shared_data(token_account)
+                    .is_some()
+            },
+        )?;
 
-        // 1. Convert to an SVM transaction batch.
-        let svm_transactions = create_svm_transactions(transactions);
-
-        // 2. Process transactions with the SVM API.
-        let results = processor.load_and_execute_sanitized_transactions(
+        // 2. Pre-check the batch. A transfer id is only processed once, token
+        // accounts given for a transfer must belong to its sender and
+        // recipient, and an open channel only accepts transfers between its
+        // participants that their deposits can cover. Otherwise, senders
+        // must hold the channel's fees on top of what they send, as the fees
+        // are settled along with the transfers.
+        let mut check_results = get_transaction_check_results(
             &account_loader,
             &svm_transactions,
-            get_transaction_check_results(svm_transactions.len(), lamports_per_signature),
-            &processing_environment,
-            &processing_config,
+            runtime.lamports_per_signature(),
+        );
+        check_transfer_ids(
+            &self.transfer_ids.lock().unwrap(),
+            &accepted,
+            &mut check_results,
         );
+        for (transaction, check_result) in accepted.iter().zip(check_results.iter_mut()) {
+            if check_result.is_ok() {
+                if let Err(e) = transaction.check_token_accounts(&mints, |address| {
+                    account_loader.get_account_shared_data(address)
+                }) {
+                    *check_result = Err(TransactionError::InstructionError(0, e));
+                }
+            }
+        }
+        match (session.as_ref(), self.fees.as_ref()) {
+            (Some(session), _) => session.check_transfers(&accepted, &mut check_results),
+            (None, Some(fees)) => {
+                fees.check_transfers(&accepted, &mut check_results, |transaction| {
+                    let mint_info = transaction.mint.and_then(|mint| mints.get(&mint));
+                    let source = match mint_info {
+                        Some(mint_info) => transaction.token_accounts(mint_info).0,
+                        None => transaction.from,
+                    };
+                    account_loader
+                        .get_account_shared_data(&source)
+                        .and_then(|account| match mint_info {
+                            Some(_) => token_amount(&account),
+                            None => Some(account.lamports()),
+                        })
+                        .unwrap_or_default()
+                })
+            }
+            (None, None) => {}
+        }
+
+        // 3. Process transactions with the SVM API.
+        let results = runtime.execute(&account_loader, &svm_transactions, check_results);
 
         // println!("{:?}", results.loaded_transactions);
 
-        // 3. Convert results into a final ledger using a `PayTubeSettler`.
-        let settler = PayTubeSettler::new(&self.rpc_client);
+        let accepted_outcomes: Vec<TransactionResult<()>> = accepted
+            .iter()
+            .zip(&results.execution_results)
+            .map(|(transaction, result)| {
+                result.flattened_result()?;
+                match session.as_mut() {
+                    Some(session) => session.apply_transfer(transaction),
+                    None => Ok(()),
+                }
+            })
+            .collect();
+        let processed_ids: Vec<(Pubkey, u64)> = accepted
+            .iter()
+            .zip(&accepted_outcomes)
+            .filter(|(_, outcome)| outcome.is_ok())
+            .filter_map(|(transaction, _)| Some((transaction.from, transaction.id?)))
+            .collect();
+        let changed_session = session
+            .as_ref()
+            .filter(|_| accepted_outcomes.iter().any(|outcome| outcome.is_ok()))
+            .map(ChannelSession::to_stored);
+        let mut accepted_outcomes = accepted_outcomes.into_iter();
+        let outcomes = transactions
+            .iter()
+            .map(|transaction| {
+                if is_known_mint(&transaction) {
+                    accepted_outcomes.next().unwrap()
+                } else {
+                    Err(TransactionError::InstructionError(
+                        0,
+                        InstructionError::InvalidAccountData,
+                    ))
+                }
+            })
+            .collect();
 
-        // 4. Submit to the Solana base chain.
-        settler.process_settle(transactions, results, &self.keys);
+        // An open channel settles its net balances when it's closed.
+        if session.is_none() {
+            // 4. Convert results into a final ledger using a `PayTubeSettler`.
+            let settler = self.settler();
+
+            // 5. Submit to the Solana base chain.
+            let pending =
+                settler.plan(&accepted, results, &self.authority(), &self.all_signers())?;
+            self.submit_settlement(&pending)?;
+        }
+
+        if let Some(stored) = changed_session {
+            self.persist(StorageRecord::SessionUpdated(Some(stored)))?;
+        }
+        if !processed_ids.is_empty() {
+            self.persist(StorageRecord::TransfersProcessed(processed_ids.clone()))?;
+        }
+        self.transfer_ids.lock().unwrap().extend(processed_ids);
+        Ok(outcomes)
+    }
+
+    /// Process a batch of channel transactions, which can invoke any
+    /// program, and settle the successful ones by replaying them on the base
+    /// chain, each in its own transaction paid for by its fee payer.
+    ///
+    /// Transactions are executed one after the other, each seeing the
+    /// accounts left by the ones before it. Transactions missing any of their
+    /// signatures are rejected. The rest are settled straight away, even if
+    /// the channel is open, so the channel must hold the key of every signer;
+    /// otherwise nothing is settled. Channels that don't use
+    /// `prepare_channel_transactions` instead.
+    ///
+    /// Returns the outcome of each transaction, with the error it was
+    /// rejected or failed with, or an error if the successful ones couldn't
+    /// be settled.
+    pub fn process_channel_transactions(
+        &self,
+        transactions: &[ChannelTransaction],
+    ) -> Result<Vec<TransactionResult<()>>, String> {
+        let (outcomes, pending) = self.prepare_channel_transactions(transactions)?;
+        if !pending.is_empty() {
+            self.submit_settlement(&pending)?;
+        }
+        Ok(outcomes)
+    }
+
+    /// Process a batch of channel transactions like
+    /// `process_channel_transactions`, returning the outcome of each with the
+    /// settlement transactions of the successful ones, signed by the channel
+    /// for the keys it holds.
+    ///
+    /// The settlement transactions are new messages, so the remaining
+    /// signers sign each one's `message_data` before they're sent with
+    /// `submit_settlement`.
+    pub fn prepare_channel_transactions(
+        &self,
+        transactions: &[ChannelTransaction],
+    ) -> Result<(Vec<TransactionResult<()>>, Vec<PendingSettlement>), String> {
+        // PayTube loader/callback implementation.
+        let account_loader = PayTubeAccountLoader::new(&self.rpc_client);
+
+        // Solana SVM transaction batch processor, with PayTube's configs.
+        let runtime = PayTubeRuntime::new(&account_loader)?;
+
+        // 1. Convert to SVM transactions.
+        let svm_transactions = create_channel_svm_transactions(transactions);
+
+        // 2. Process each transaction with the SVM API, keeping the accounts
+        // of the successful ones for the transactions after them.
+        let outcomes: Vec<TransactionResult<()>> = transactions
+            .iter()
+            .zip(svm_transactions)
+            .map(|(transaction, svm_transaction)| {
+                // The channel signs the settlement for the keys it holds,
+                // so only on behalf of those who signed the transaction.
+                if !transaction.is_complete() {
+                    return Err(TransactionError::SignatureFailure);
+                }
+                let results = runtime.check_and_execute(&account_loader, &[svm_transaction?]);
+                results.execution_results[0].flattened_result()?;
+                if let Ok(loaded_transaction) = &results.loaded_transactions[0] {
+                    account_loader.store_accounts(&loaded_transaction.accounts);
+                }
+                Ok(())
+            })
+            .collect();
+
+        // 3. Plan the replay of the successful transactions on the Solana
+        // base chain, each on its own, so one's signatures never authorize
+        // another's instructions.
+        let groups: Vec<(&[SolanaInstruction], Pubkey)> = transactions
+            .iter()
+            .zip(&outcomes)
+            .filter(|(_, outcome)| outcome.is_ok())
+            .map(|(transaction, _)| (transaction.instructions.as_slice(), transaction.fee_payer))
+            .collect();
+        let pending = if groups.is_empty() {
+            Vec::new()
+        } else {
+            self.settler()
+                .prepare_separately(&groups, &self.all_signers())?
+        };
+
+        Ok((outcomes, pending))
     }
 }
 
@@ -169,6 +809,8 @@ pub struct PlayChannel {
     pub rpc_client: RpcClient,
     /// Signers for settlement
     pub keys: Vec<Keypair>,
+    /// Game programs executed in the channel, by program id and ELF
+    programs: Vec<(Pubkey, Vec<u8>)>,
 }
 
 impl PlayChannel {
@@ -181,53 +823,36 @@ impl PlayChannel {
             keys,
             // game_manager,
             rpc_client,
+            programs: Vec::new(),
         }
     }
 
-    pub fn process_plays(&self, transactions: &[RpsTransaction]) {
-        // PayTube default configs.
-        let compute_budget = ComputeBudget::default();
-        let feature_set = FeatureSet::all_enabled();
-        let fee_structure = FeeStructure::default();
-        let lamports_per_signature = fee_structure.lamports_per_signature;
-        let rent_collector = RentCollector::default();
+    /// Execute the game program `elf_bytes`, such as
+    /// `rock_paper_scissors.so`, for plays invoking `program_id`
+    pub fn with_program(mut self, program_id: Pubkey, elf_bytes: Vec<u8>) -> Self {
+        self.programs.push((program_id, elf_bytes));
+        self
+    }
 
+    /// Process plays of the channel's game programs, failing if a program
+    /// can't be loaded.
+    pub fn process_plays(&self, transactions: &[RpsTransaction]) -> Result<(), String> {
         // PayTube loader/callback implementation.
         let account_loader = PayTubeAccountLoader::new(&self.rpc_client);
 
-        // Solana SVM transaction batch processor.
-        let processor =
-            create_transaction_batch_processor(&account_loader, &feature_set, &compute_budget);
-
-        // The PayTube transaction processing runtime environment.
-        let processing_environment = TransactionProcessingEnvironment {
-            blockhash: Hash::default(),
-            epoch_total_stake: None,
-            epoch_vote_accounts: None,
-            feature_set: Arc::new(feature_set),
-            fee_structure: Some(&fee_structure),
-            lamports_per_signature,
-            rent_collector: Some(&rent_collector),
-        };
-
-        // The PayTube transaction processing config for Solana SVM.
-        let processing_config = TransactionProcessingConfig {
-            compute_budget: Some(compute_budget),
-            ..Default::default()
-        };
+        // Solana SVM transaction batch processor, with PayTube's configs and
+        // the channel's game programs.
+        let mut runtime = PayTubeRuntime::new(&account_loader)?;
+        for (program_id, elf_bytes) in &self.programs {
+            runtime = runtime.with_program(*program_id, elf_bytes)?;
+        }
 
         // 1. Convert to an SVM transaction batch.
         let svm_transactions = create_svm_transactions2(transactions);
 
         // println!("{:?}", svm_transactions);
         // 2. Process transactions with the SVM API.
-        let results = processor.load_and_execute_sanitized_transactions(
-            &account_loader,
-            &svm_transactions,
-            get_transaction_check_results(svm_transactions.len(), lamports_per_signature),
-            &processing_environment,
-            &processing_config,
-        );
+        let results = runtime.check_and_execute(&account_loader, &svm_transactions);
 
         // println!("{:?}", results.loaded_transactions.len());
         // 3. Convert results into a final ledger using a `PayTubeSettler`.
@@ -236,5 +861,6 @@ impl PlayChannel {
         // 4. Submit to the Solana base chain.
         settler.process_settle(transactions, results, &self.keys);
         println!("After settling");
+        Ok(())
     }
 }
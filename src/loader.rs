@@ -26,11 +26,32 @@ pub struct PayTubeAccountLoader<'a> {
 
 impl<'a> PayTubeAccountLoader<'a> {
     pub fn new(rpc_client: &'a RpcClient) -> Self {
+        Self::with_cache(rpc_client, HashMap::new())
+    }
+
+    /// Create a loader seeded with accounts from a previous run, so state
+    /// produced in the channel takes precedence over the base chain.
+    pub fn with_cache(
+        rpc_client: &'a RpcClient,
+        cache: HashMap<Pubkey, AccountSharedData>,
+    ) -> Self {
         Self {
-            cache: RwLock::new(HashMap::new()),
+            cache: RwLock::new(cache),
             rpc_client,
         }
     }
+
+    /// Write post-execution accounts back into the cache.
+    pub fn store_accounts(&self, accounts: &[(Pubkey, AccountSharedData)]) {
+        let mut cache = self.cache.write().unwrap();
+        for (pubkey, account) in accounts {
+            cache.insert(*pubkey, account.clone());
+        }
+    }
+
+    pub fn into_cache(self) -> HashMap<Pubkey, AccountSharedData> {
+        self.cache.into_inner().unwrap()
+    }
 }
 
 /// SVM implementation of the `AccountLoader` plugin trait.
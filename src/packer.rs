@@ -0,0 +1,246 @@
+//! Packing settlement instructions into as few transactions as possible.
+//!
+//! A settlement can touch hundreds of accounts. Each transaction has to fit
+//! in a single packet and stay within the compute limit, and every extra
+//! signer or account key costs space. The packer greedily fills each
+//! transaction with instructions until the next one wouldn't fit.
+//!
+//! With address lookup tables, account keys are referenced by a one byte
+//! index instead of 32 bytes, so large participant sets pack into far fewer
+//! (v0) transactions.
+//!
+//! Transactions can also be built on durable nonces rather than a recent
+//! blockhash, so they stay valid while they're being co-signed. Each
+//! transaction then starts by advancing its own nonce account.
+
+use {
+    crate::token::is_token_program,
+    solana_compute_budget::compute_budget_processor::{
+        DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT, MAX_COMPUTE_UNIT_LIMIT,
+    },
+    solana_sdk::{
+        hash::Hash,
+        instruction::Instruction,
+        message::{v0, AddressLookupTableAccount, Message, VersionedMessage},
+        packet::PACKET_DATA_SIZE,
+        pubkey::Pubkey,
+        signature::Signature,
+        system_instruction, system_program,
+    },
+};
+
+/// Compute used by an SPL Token or Token-2022 instruction, with some
+/// headroom.
+const TOKEN_INSTRUCTION_COMPUTE_UNITS: u32 = 6_000;
+/// Compute used creating an associated token account, with some headroom.
+const ASSOCIATED_TOKEN_ACCOUNT_INSTRUCTION_COMPUTE_UNITS: u32 = 40_000;
+/// Compute used by a System program instruction.
+const SYSTEM_INSTRUCTION_COMPUTE_UNITS: u32 = 150;
+
+/// A durable nonce account, and the nonce currently stored in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettlementNonce {
+    pub account: Pubkey,
+    pub authority: Pubkey,
+    pub nonce: Hash,
+}
+
+/// Greedily packs instructions into transactions.
+#[derive(Debug, Clone)]
+pub struct SettlementPacker {
+    /// The largest serialized transaction, including signatures.
+    pub max_transaction_size: usize,
+    /// The compute budget of a single transaction.
+    pub max_compute_units: u32,
+    /// Lookup tables to compile v0 messages against. Legacy messages are
+    /// used when there are none.
+    pub lookup_tables: Vec<AddressLookupTableAccount>,
+}
+
+impl Default for SettlementPacker {
+    fn default() -> Self {
+        Self {
+            max_transaction_size: PACKET_DATA_SIZE,
+            max_compute_units: MAX_COMPUTE_UNIT_LIMIT,
+            lookup_tables: Vec::new(),
+        }
+    }
+}
+
+impl SettlementPacker {
+    pub fn with_lookup_tables(lookup_tables: Vec<AddressLookupTableAccount>) -> Self {
+        Self {
+            lookup_tables,
+            ..Self::default()
+        }
+    }
+
+    /// A conservative estimate of the compute an instruction uses.
+    pub fn estimated_compute_units(instruction: &Instruction) -> u32 {
+        if instruction.program_id == system_program::id() {
+            SYSTEM_INSTRUCTION_COMPUTE_UNITS
+        } else if is_token_program(&instruction.program_id) {
+            TOKEN_INSTRUCTION_COMPUTE_UNITS
+        } else if instruction.program_id == spl_associated_token_account::id() {
+            ASSOCIATED_TOKEN_ACCOUNT_INSTRUCTION_COMPUTE_UNITS
+        } else {
+            DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT
+        }
+    }
+
+    /// Pack `instructions`, in order, into as few messages as fit.
+    pub fn pack(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        recent_blockhash: Hash,
+    ) -> Result<Vec<VersionedMessage>, String> {
+        self.pack_groups(&Self::ungrouped(instructions), payer, recent_blockhash)
+    }
+
+    /// Pack `instructions` like `pack`, building each message on the next of
+    /// the durable `nonces` instead of a recent blockhash.
+    pub fn pack_with_nonces(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        nonces: &[SettlementNonce],
+    ) -> Result<Vec<VersionedMessage>, String> {
+        self.pack_groups_with_nonces(&Self::ungrouped(instructions), payer, nonces)
+    }
+
+    /// Pack `groups` of instructions, in order, into as few messages as fit,
+    /// never splitting a group across messages.
+    pub fn pack_groups(
+        &self,
+        groups: &[&[Instruction]],
+        payer: &Pubkey,
+        recent_blockhash: Hash,
+    ) -> Result<Vec<VersionedMessage>, String> {
+        self.pack_each(groups, payer, |_| Ok((None, recent_blockhash)))
+    }
+
+    /// Pack `groups` like `pack_groups`, building each message on the next
+    /// of the durable `nonces` instead of a recent blockhash.
+    pub fn pack_groups_with_nonces(
+        &self,
+        groups: &[&[Instruction]],
+        payer: &Pubkey,
+        nonces: &[SettlementNonce],
+    ) -> Result<Vec<VersionedMessage>, String> {
+        self.pack_each(groups, payer, |index| {
+            let nonce = nonces
+                .get(index)
+                .ok_or_else(|| "Not enough nonce accounts for the settlement".to_string())?;
+            Ok((
+                Some(system_instruction::advance_nonce_account(
+                    &nonce.account,
+                    &nonce.authority,
+                )),
+                nonce.nonce,
+            ))
+        })
+    }
+
+    /// Every instruction in a group of its own.
+    fn ungrouped(instructions: &[Instruction]) -> Vec<&[Instruction]> {
+        instructions.iter().map(std::slice::from_ref).collect()
+    }
+
+    /// Greedily pack `groups`. `start` returns the instruction the message at
+    /// an index starts with, if any, and its blockhash.
+    fn pack_each(
+        &self,
+        groups: &[&[Instruction]],
+        payer: &Pubkey,
+        start: impl Fn(usize) -> Result<(Option<Instruction>, Hash), String>,
+    ) -> Result<Vec<VersionedMessage>, String> {
+        let mut messages = Vec::new();
+        let (first, mut recent_blockhash) = start(0)?;
+        let mut current: Vec<Instruction> = first.into_iter().collect();
+        let mut current_message = None;
+        for group in groups {
+            current.extend_from_slice(group);
+            match self.fit(&current, payer, recent_blockhash)? {
+                Some(message) => current_message = Some(message),
+                None => {
+                    // Close off the transaction without this group, and
+                    // start the next one with it.
+                    let full = current_message
+                        .take()
+                        .ok_or_else(|| "Instruction does not fit in a transaction".to_string())?;
+                    messages.push(full);
+                    let (first, next_blockhash) = start(messages.len())?;
+                    current = first.into_iter().collect();
+                    current.extend_from_slice(group);
+                    recent_blockhash = next_blockhash;
+                    current_message = Some(
+                        self.fit(&current, payer, recent_blockhash)?
+                            .ok_or_else(|| {
+                                "Instruction does not fit in a transaction".to_string()
+                            })?,
+                    );
+                }
+            }
+        }
+        messages.extend(current_message);
+        Ok(messages)
+    }
+
+    /// Compile `instructions` into a message, if it fits the limits.
+    fn fit(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        recent_blockhash: Hash,
+    ) -> Result<Option<VersionedMessage>, String> {
+        let compute_units: u64 = instructions
+            .iter()
+            .map(|instruction| Self::estimated_compute_units(instruction) as u64)
+            .sum();
+        if compute_units > self.max_compute_units as u64 {
+            return Ok(None);
+        }
+
+        let message = self.compile(instructions, payer, recent_blockhash)?;
+        if transaction_size(&message) > self.max_transaction_size {
+            return Ok(None);
+        }
+        Ok(Some(message))
+    }
+
+    fn compile(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        recent_blockhash: Hash,
+    ) -> Result<VersionedMessage, String> {
+        if self.lookup_tables.is_empty() {
+            return Ok(VersionedMessage::Legacy(Message::new_with_blockhash(
+                instructions,
+                Some(payer),
+                &recent_blockhash,
+            )));
+        }
+        v0::Message::try_compile(payer, instructions, &self.lookup_tables, recent_blockhash)
+            .map(VersionedMessage::V0)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// The serialized size of a transaction for `message`, once signed.
+pub fn transaction_size(message: &VersionedMessage) -> usize {
+    let signatures = message.header().num_required_signatures as usize;
+    short_vec_len(signatures)
+        + signatures * std::mem::size_of::<Signature>()
+        + message.serialize().len()
+}
+
+/// Length of the compact-u16 length prefix of a serialized vector.
+fn short_vec_len(len: usize) -> usize {
+    match len {
+        0..=0x7f => 1,
+        0x80..=0x3fff => 2,
+        _ => 3,
+    }
+}
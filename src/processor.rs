@@ -1,22 +1,37 @@
 //! A helper to initialize Solana SVM API's `TransactionBatchProcessor`.
 
 use {
+    crate::transaction::PayTubeTransaction,
     solana_bpf_loader_program::syscalls::create_program_runtime_environment_v1,
     solana_compute_budget::compute_budget::ComputeBudget,
     solana_program_runtime::loaded_programs::{
-        BlockRelation, ForkGraph, LoadProgramMetrics, ProgramCacheEntry,
+        BlockRelation, ForkGraph, LoadProgramMetrics, ProgramCache, ProgramCacheEntry,
     },
     solana_sdk::{
-        account::ReadableAccount, clock::Slot, feature_set::FeatureSet, pubkey::Pubkey, transaction,
+        account::ReadableAccount,
+        bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+        clock::Slot,
+        feature_set::FeatureSet,
+        fee::FeeStructure,
+        hash::Hash,
+        nonce_account,
+        pubkey::Pubkey,
+        rent_collector::RentCollector,
+        signature::Signature,
+        transaction::{self, SanitizedTransaction, TransactionError},
     },
     solana_svm::{
         account_loader::CheckedTransactionDetails,
+        nonce_info::NoncePartial,
         transaction_processing_callback::TransactionProcessingCallback,
-        transaction_processor::TransactionBatchProcessor,
+        transaction_processor::{
+            LoadAndExecuteSanitizedTransactionsOutput, TransactionBatchProcessor,
+            TransactionProcessingConfig, TransactionProcessingEnvironment,
+        },
     },
     solana_system_program::system_processor,
     std::{
-        fs,
+        collections::HashSet,
         sync::{Arc, RwLock},
     },
 };
@@ -38,12 +53,13 @@ impl ForkGraph for PayTubeForkGraph {
 /// `TransactionBatchProcessor` for use within PayTube.
 ///
 /// We're simply configuring the mocked fork graph on the SVM API's program
-/// cache, then adding the System program to the processor's builtins.
-pub(crate) fn create_transaction_batch_processor<CB: TransactionProcessingCallback>(
+/// cache, then adding the System program to the processor's builtins. Other
+/// programs are added with `PayTubeRuntime::with_program`.
+fn create_transaction_batch_processor<CB: TransactionProcessingCallback>(
     callbacks: &CB,
     feature_set: &FeatureSet,
     compute_budget: &ComputeBudget,
-) -> TransactionBatchProcessor<PayTubeForkGraph> {
+) -> Result<TransactionBatchProcessor<PayTubeForkGraph>, String> {
     let processor = TransactionBatchProcessor::<PayTubeForkGraph>::default();
 
     {
@@ -57,59 +73,20 @@ pub(crate) fn create_transaction_batch_processor<CB: TransactionProcessingCallba
         // (Use Loader v4 program to initialize runtime v2 if desired)
         cache.environments.program_runtime_v1 = Arc::new(
             create_program_runtime_environment_v1(feature_set, compute_budget, false, false)
-                .unwrap(),
+                .map_err(|e| format!("Failed to create program runtime: {}", e))?,
         );
 
-        // Add the SPL Token program to the cache.
-        if let Some(program_account) = callbacks.get_account_shared_data(&spl_token::id()) {
-            let elf_bytes = program_account.data();
-            let program_runtime_environment = cache.environments.program_runtime_v1.clone();
-            cache.assign_program(
-                spl_token::id(),
-                Arc::new(
-                    ProgramCacheEntry::new(
-                        &solana_sdk::bpf_loader::id(),
-                        program_runtime_environment,
-                        0,
-                        0,
-                        elf_bytes,
-                        elf_bytes.len(),
-                        &mut LoadProgramMetrics::default(),
-                    )
-                    .unwrap(),
-                ),
-            );
+        // Add the SPL Token, Token-2022 and Associated Token Account programs
+        // to the cache.
+        for program_id in [
+            spl_token::id(),
+            spl_token_2022::id(),
+            spl_associated_token_account::id(),
+        ] {
+            if let Some(elf_bytes) = program_elf(callbacks, &program_id) {
+                assign_bpf_program(&mut cache, program_id, &elf_bytes)?;
+            }
         }
-
-        //Add Rock Paper Scissors to Cache
-        let program_id: Pubkey = "B6iwgaDVFX7LXDMokCYT8Ya21gr2FbsUTBPFh2mcfxNa"
-            .parse()
-            .unwrap();
-        let data = fs::read(
-            std::env::current_dir()
-                .unwrap()
-                .join("rock_paper_scissors.so")
-                .to_str()
-                .unwrap(),
-        )
-        .expect("read program binary");
-        let elf_bytes: &[u8] = &data;
-        let program_runtime_environment = cache.environments.program_runtime_v1.clone();
-        cache.assign_program(
-            program_id,
-            Arc::new(
-                ProgramCacheEntry::new(
-                    &solana_sdk::bpf_loader::id(),
-                    program_runtime_environment,
-                    0,
-                    0,
-                    elf_bytes,
-                    elf_bytes.len(),
-                    &mut LoadProgramMetrics::default(),
-                )
-                .unwrap(),
-            ),
-        );
     }
 
     // Add the system program builtin.
@@ -136,21 +113,249 @@ pub(crate) fn create_transaction_batch_processor<CB: TransactionProcessingCallba
         ),
     );
 
-    processor
+    // Add the upgradeable BPF Loader builtin, for the Token-2022 program.
+    processor.add_builtin(
+        callbacks,
+        bpf_loader_upgradeable::id(),
+        "solana_bpf_loader_upgradeable_program",
+        ProgramCacheEntry::new_builtin(
+            0,
+            b"solana_bpf_loader_upgradeable_program".len(),
+            solana_bpf_loader_program::Entrypoint::vm,
+        ),
+    );
+
+    Ok(processor)
+}
+
+/// A transaction batch processor together with PayTube's default configs,
+/// for executing transactions in a channel.
+pub(crate) struct PayTubeRuntime {
+    pub(crate) processor: TransactionBatchProcessor<PayTubeForkGraph>,
+    compute_budget: ComputeBudget,
+    feature_set: Arc<FeatureSet>,
+    fee_structure: FeeStructure,
+    rent_collector: RentCollector,
 }
 
-/// This functions is also a mock. In the Agave validator, the bank pre-checks
-/// transactions before providing them to the SVM API. We mock this step in
-/// PayTube, since we don't need to perform such pre-checks.
-pub(crate) fn get_transaction_check_results(
-    len: usize,
+impl PayTubeRuntime {
+    pub(crate) fn new<CB: TransactionProcessingCallback>(callbacks: &CB) -> Result<Self, String> {
+        // PayTube default configs.
+        let compute_budget = ComputeBudget::default();
+        let feature_set = FeatureSet::all_enabled();
+        let processor =
+            create_transaction_batch_processor(callbacks, &feature_set, &compute_budget)?;
+        Ok(Self {
+            processor,
+            compute_budget,
+            feature_set: Arc::new(feature_set),
+            fee_structure: FeeStructure::default(),
+            rent_collector: RentCollector::default(),
+        })
+    }
+
+    /// Execute the SBF program `elf_bytes` for `program_id`, whether or not
+    /// it's deployed on the base chain.
+    pub(crate) fn with_program(self, program_id: Pubkey, elf_bytes: &[u8]) -> Result<Self, String> {
+        assign_bpf_program(
+            &mut self.processor.program_cache.write().unwrap(),
+            program_id,
+            elf_bytes,
+        )?;
+        Ok(self)
+    }
+
+    /// Execute without charging fees, for channels that charge their own.
+    pub(crate) fn without_fees(mut self) -> Self {
+        self.fee_structure.lamports_per_signature = 0;
+        self
+    }
+
+    pub(crate) fn lamports_per_signature(&self) -> u64 {
+        self.fee_structure.lamports_per_signature
+    }
+
+    /// Pre-check `svm_transactions` with `get_transaction_check_results`,
+    /// then execute them.
+    pub(crate) fn check_and_execute<CB: TransactionProcessingCallback>(
+        &self,
+        callbacks: &CB,
+        svm_transactions: &[SanitizedTransaction],
+    ) -> LoadAndExecuteSanitizedTransactionsOutput {
+        let check_results = get_transaction_check_results(
+            callbacks,
+            svm_transactions,
+            self.lamports_per_signature(),
+        );
+        self.execute(callbacks, svm_transactions, check_results)
+    }
+
+    /// Execute `svm_transactions` that passed their `check_results`.
+    pub(crate) fn execute<CB: TransactionProcessingCallback>(
+        &self,
+        callbacks: &CB,
+        svm_transactions: &[SanitizedTransaction],
+        check_results: Vec<transaction::Result<CheckedTransactionDetails>>,
+    ) -> LoadAndExecuteSanitizedTransactionsOutput {
+        // The PayTube transaction processing runtime environment.
+        let processing_environment = TransactionProcessingEnvironment {
+            blockhash: Hash::default(),
+            epoch_total_stake: None,
+            epoch_vote_accounts: None,
+            feature_set: self.feature_set.clone(),
+            fee_structure: Some(&self.fee_structure),
+            lamports_per_signature: self.lamports_per_signature(),
+            rent_collector: Some(&self.rent_collector),
+        };
+
+        // The PayTube transaction processing config for Solana SVM.
+        let processing_config = TransactionProcessingConfig {
+            compute_budget: Some(self.compute_budget),
+            ..Default::default()
+        };
+
+        self.processor.load_and_execute_sanitized_transactions(
+            callbacks,
+            svm_transactions,
+            check_results,
+            &processing_environment,
+            &processing_config,
+        )
+    }
+}
+
+/// The ELF of a deployed program. Programs deployed with the upgradeable
+/// loader keep it in a separate program data account, after its metadata.
+fn program_elf<CB: TransactionProcessingCallback>(
+    callbacks: &CB,
+    program_id: &Pubkey,
+) -> Option<Vec<u8>> {
+    let program_account = callbacks.get_account_shared_data(program_id)?;
+    if !bpf_loader_upgradeable::check_id(program_account.owner()) {
+        return Some(program_account.data().to_vec());
+    }
+    let UpgradeableLoaderState::Program {
+        programdata_address,
+    } = program_account.deserialize_data().ok()?
+    else {
+        return None;
+    };
+    let programdata_account = callbacks.get_account_shared_data(&programdata_address)?;
+    programdata_account
+        .data()
+        .get(UpgradeableLoaderState::size_of_programdata_metadata()..)
+        .map(<[u8]>::to_vec)
+}
+
+/// Compile an SBF program's ELF and add it to the program cache, so
+/// transactions invoking `program_id` can be executed without the program
+/// being deployed on the base chain.
+pub(crate) fn assign_bpf_program(
+    cache: &mut ProgramCache<PayTubeForkGraph>,
+    program_id: Pubkey,
+    elf_bytes: &[u8],
+) -> Result<(), String> {
+    let program_runtime_environment = cache.environments.program_runtime_v1.clone();
+    let entry = ProgramCacheEntry::new(
+        &solana_sdk::bpf_loader::id(),
+        program_runtime_environment,
+        0,
+        0,
+        elf_bytes,
+        elf_bytes.len(),
+        &mut LoadProgramMetrics::default(),
+    )
+    .map_err(|e| format!("Failed to load program {}: {}", program_id, e))?;
+    cache.assign_program(program_id, Arc::new(entry));
+    Ok(())
+}
+
+/// In the Agave validator, the bank pre-checks transactions before providing
+/// them to the SVM API. PayTube performs the subset of those checks that make
+/// sense for a channel:
+///
+/// * A signed transaction may only appear once in a batch. PayTube transfers
+///   are unsigned and checked by their ids instead, see `check_transfer_ids`.
+/// * The fee payer must exist and be able to pay the signature fees.
+/// * A transaction built on a durable nonce must use the nonce currently
+///   stored in its nonce account, and be signed by the nonce's authority.
+///
+/// Channels layer their own checks, such as deposit limits, on top.
+pub(crate) fn get_transaction_check_results<CB: TransactionProcessingCallback>(
+    callbacks: &CB,
+    svm_transactions: &[SanitizedTransaction],
     lamports_per_signature: u64,
 ) -> Vec<transaction::Result<CheckedTransactionDetails>> {
-    vec![
-        transaction::Result::Ok(CheckedTransactionDetails {
-            nonce: None,
-            lamports_per_signature,
-        });
-        len
-    ]
+    let mut seen = HashSet::new();
+    svm_transactions
+        .iter()
+        .map(|transaction| {
+            let signature = transaction.signature();
+            if *signature != Signature::default() && !seen.insert(*signature) {
+                return Err(TransactionError::AlreadyProcessed);
+            }
+
+            let fee = lamports_per_signature * transaction.signatures().len() as u64;
+            let fee_payer = callbacks
+                .get_account_shared_data(transaction.message().fee_payer())
+                .ok_or(TransactionError::AccountNotFound)?;
+            if fee_payer.lamports() < fee {
+                return Err(TransactionError::InsufficientFundsForFee);
+            }
+
+            let nonce = transaction
+                .message()
+                .get_durable_nonce()
+                .map(|address| check_durable_nonce(callbacks, transaction, address))
+                .transpose()?;
+
+            Ok(CheckedTransactionDetails {
+                nonce,
+                lamports_per_signature,
+            })
+        })
+        .collect()
+}
+
+/// PayTube transfers are unsigned, so a repeat of a transfer can only be told
+/// apart from a legitimate second payment by its sender's id for it. A
+/// transfer whose id was `processed` before, or appears earlier in the batch,
+/// fails as already processed.
+pub(crate) fn check_transfer_ids<T>(
+    processed: &HashSet<(Pubkey, u64)>,
+    transactions: &[PayTubeTransaction],
+    check_results: &mut [transaction::Result<T>],
+) {
+    let mut seen = HashSet::new();
+    for (transaction, check_result) in transactions.iter().zip(check_results.iter_mut()) {
+        let Some(id) = transaction.id else {
+            continue;
+        };
+        let key = (transaction.from, id);
+        if processed.contains(&key) || !seen.insert(key) {
+            *check_result = Err(TransactionError::AlreadyProcessed);
+        }
+    }
+}
+
+/// Check that a transaction can use the durable nonce stored at `address`.
+fn check_durable_nonce<CB: TransactionProcessingCallback>(
+    callbacks: &CB,
+    transaction: &SanitizedTransaction,
+    address: &Pubkey,
+) -> transaction::Result<NoncePartial> {
+    let account = callbacks
+        .get_account_shared_data(address)
+        .ok_or(TransactionError::BlockhashNotFound)?;
+    let nonce_data =
+        nonce_account::verify_nonce_account(&account, transaction.message().recent_blockhash())
+            .ok_or(TransactionError::BlockhashNotFound)?;
+    if !transaction
+        .message()
+        .get_ix_signers(0)
+        .any(|signer| signer == &nonce_data.authority)
+    {
+        return Err(TransactionError::BlockhashNotFound);
+    }
+    Ok(NoncePartial::new(*address, account))
 }
@@ -11,15 +11,23 @@
 //! channel is about to close are needed to create the settlement transaction.
 
 use {
-    crate::transaction::PayTubeTransaction,
-    solana_client::rpc_client::RpcClient,
+    crate::{
+        fees::ChannelFees,
+        journal::{SettlementJournal, SettlementStatus},
+        packer::{SettlementNonce, SettlementPacker},
+        signing::PendingSettlement,
+        simulation::{simulate_transaction, SimulationReport},
+        token::MintInfo,
+        transaction::PayTubeTransaction,
+    },
+    solana_client::{nonce_utils, rpc_client::RpcClient},
     solana_sdk::{
-        instruction::Instruction as SolanaInstruction, pubkey::Pubkey, signature::Keypair,
-        signer::Signer, system_instruction, transaction::Transaction as SolanaTransaction,
+        account::AccountSharedData, clock::Epoch, commitment_config::CommitmentConfig, hash::Hash,
+        instruction::Instruction as SolanaInstruction, pubkey::Pubkey, signature::Signature,
+        signer::Signer, system_instruction,
     },
     solana_svm::transaction_processor::LoadAndExecuteSanitizedTransactionsOutput,
-    spl_associated_token_account::get_associated_token_address,
-    std::collections::HashMap,
+    std::collections::{HashMap, HashSet},
 };
 
 /// The key used for storing ledger entries.
@@ -30,6 +38,9 @@ use {
 ///
 /// This design allows the ledger to combine transfers from a -> b and b -> a
 /// in the same entry, calculating the final delta between two parties.
+///
+/// The parties to a SOL transfer are wallets, and the parties to a token
+/// transfer are token accounts.
 #[derive(PartialEq, Eq, Hash)]
 struct LedgerKey {
     mint: Option<Pubkey>,
@@ -41,16 +52,31 @@ struct LedgerKey {
 ///
 /// The value is stored as a signed `i128`, in order to include a sign but also
 /// provide enough room to store `u64::MAX`.
+///
+/// Token amounts are recorded as received, net of any transfer fee withheld
+/// by the mint, and grossed up again when the transfers are settled.
 struct Ledger {
     ledger: HashMap<LedgerKey, i128>,
+    mints: HashMap<Pubkey, MintInfo>,
+    epoch: Epoch,
+    /// The wallet holding each token account in the ledger.
+    owners: HashMap<Pubkey, Pubkey>,
 }
 
 impl Ledger {
     fn new(
         paytube_transactions: &[PayTubeTransaction],
         svm_output: LoadAndExecuteSanitizedTransactionsOutput,
+        fees: Option<&ChannelFees>,
+        mints: HashMap<Pubkey, MintInfo>,
+        epoch: Epoch,
     ) -> Self {
-        let mut ledger: HashMap<LedgerKey, i128> = HashMap::new();
+        let mut ledger = Self {
+            ledger: HashMap::new(),
+            mints,
+            epoch,
+            owners: HashMap::new(),
+        };
         paytube_transactions
             .iter()
             .zip(svm_output.execution_results)
@@ -59,58 +85,281 @@ impl Ledger {
                 // successful.
                 if result.was_executed_successfully() {
                     let mint = transaction.mint;
-                    let mut keys = [transaction.from, transaction.to];
-                    keys.sort();
-                    let amount = if keys.iter().position(|k| k.eq(&transaction.from)).unwrap() == 0
-                    {
-                        transaction.amount as i128
-                    } else {
-                        -(transaction.amount as i128)
-                    };
-                    *ledger.entry(LedgerKey { mint, keys }).or_default() += amount;
+                    let from = ledger.party(mint, transaction.from, transaction.from_token_account);
+                    let to = ledger.party(mint, transaction.to, transaction.to_token_account);
+                    ledger.record(mint, from, to, transaction.amount);
+
+                    // Fees accrue to the operator's fee account.
+                    if let Some(fees) = fees {
+                        let fee = fees.fee(&transaction.from, transaction.amount);
+                        if fee > 0 {
+                            let operator = ledger.party(mint, fees.operator, None);
+                            ledger.record(mint, from, operator, fee);
+                        }
+                    }
                 }
 
                 println!("Yes")
             });
-        Self { ledger }
+        ledger
+    }
+
+    /// The ledger party for `owner` in a transfer of `mint`: the wallet
+    /// itself for SOL, and `token_account` or otherwise the associated token
+    /// account for tokens.
+    fn party(
+        &mut self,
+        mint: Option<Pubkey>,
+        owner: Pubkey,
+        token_account: Option<Pubkey>,
+    ) -> Pubkey {
+        let Some(mint_info) = mint.and_then(|mint| self.mints.get(&mint)) else {
+            return owner;
+        };
+        let token_account = token_account.unwrap_or_else(|| mint_info.token_account(&owner));
+        self.owners.insert(token_account, owner);
+        token_account
+    }
+
+    fn record(&mut self, mint: Option<Pubkey>, from: Pubkey, to: Pubkey, amount: u64) {
+        let withheld = mint
+            .and_then(|mint| self.mints.get(&mint))
+            .map(|mint_info| mint_info.fee(self.epoch, amount))
+            .unwrap_or_default();
+        let amount = amount - withheld;
+        let mut keys = [from, to];
+        keys.sort();
+        let amount = if keys[0] == from {
+            amount as i128
+        } else {
+            -(amount as i128)
+        };
+        *self.ledger.entry(LedgerKey { mint, keys }).or_default() += amount;
+    }
+
+    /// Recipients whose associated token account doesn't exist, according to
+    /// `token_account_exists`, have it created by `token_account_payer`
+    /// right before their first transfer.
+    fn generate_base_chain_instructions(
+        &self,
+        netting: NettingMode,
+        token_account_payer: &Pubkey,
+        token_account_exists: impl Fn(&Pubkey) -> bool,
+    ) -> Result<Vec<SolanaInstruction>, String> {
+        let transfers = match netting {
+            NettingMode::Pairwise => self.pairwise_transfers(),
+            NettingMode::Global => self.global_transfers(),
+        };
+        let mut created = HashSet::new();
+        let mut instructions = Vec::new();
+        for (mint, from, to, amount) in transfers {
+            let Some(mint) = mint else {
+                instructions.push(system_instruction::transfer(&from, &to, amount));
+                continue;
+            };
+            let mint_info = self
+                .mints
+                .get(&mint)
+                .ok_or_else(|| format!("Mint {} not found", mint))?;
+            let owner = self.owners[&from];
+            let recipient = self.owners[&to];
+            if to == mint_info.token_account(&recipient)
+                && !created.contains(&to)
+                && !token_account_exists(&to)
+            {
+                instructions.push(
+                    mint_info.create_token_account_instruction(token_account_payer, &recipient),
+                );
+                created.insert(to);
+            }
+            // Send enough that the recipient receives `amount` after the
+            // transfer fee.
+            let amount = mint_info.gross_amount(self.epoch, amount);
+            instructions.push(mint_info.transfer_instruction(&from, &to, &owner, amount)?);
+        }
+        Ok(instructions)
     }
 
-    fn generate_base_chain_instructions(&self) -> Vec<SolanaInstruction> {
+    /// One transfer per pair of parties, for their net delta.
+    fn pairwise_transfers(&self) -> Vec<(Option<Pubkey>, Pubkey, Pubkey, u64)> {
         self.ledger
             .iter()
             .map(|(key, amount)| {
-                let (from, to, amount) = if *amount < 0 {
-                    (key.keys[1], key.keys[0], (amount * -1) as u64)
+                if *amount < 0 {
+                    (key.mint, key.keys[1], key.keys[0], (amount * -1) as u64)
                 } else {
-                    (key.keys[0], key.keys[1], *amount as u64)
-                };
-                if let Some(mint) = key.mint {
-                    let source_pubkey = get_associated_token_address(&from, &mint);
-                    let destination_pubkey = get_associated_token_address(&to, &mint);
-                    return spl_token::instruction::transfer(
-                        &spl_token::id(),
-                        &source_pubkey,
-                        &destination_pubkey,
-                        &from,
-                        &[],
-                        amount,
-                    )
-                    .unwrap();
+                    (key.mint, key.keys[0], key.keys[1], *amount as u64)
                 }
-                system_instruction::transfer(&from, &to, amount)
             })
-            .collect::<Vec<_>>()
+            .collect()
+    }
+
+    /// Transfers settling each party's net position per mint, with the
+    /// largest debtors paying the largest creditors first.
+    ///
+    /// A -> B, B -> C and C -> A of equal amounts cancel out entirely, and
+    /// every party is in at most `debtors + creditors - 1` transfers per mint.
+    fn global_transfers(&self) -> Vec<(Option<Pubkey>, Pubkey, Pubkey, u64)> {
+        let mut positions: HashMap<(Option<Pubkey>, Pubkey), i128> = HashMap::new();
+        for (key, amount) in &self.ledger {
+            *positions.entry((key.mint, key.keys[0])).or_default() -= amount;
+            *positions.entry((key.mint, key.keys[1])).or_default() += amount;
+        }
+
+        let mut mints: Vec<Option<Pubkey>> = positions.keys().map(|(mint, _)| *mint).collect();
+        mints.sort();
+        mints.dedup();
+
+        let mut transfers = Vec::new();
+        for mint in mints {
+            let mut debtors: Vec<(Pubkey, i128)> = Vec::new();
+            let mut creditors: Vec<(Pubkey, i128)> = Vec::new();
+            for ((position_mint, party), amount) in &positions {
+                if *position_mint != mint {
+                    continue;
+                }
+                if *amount < 0 {
+                    debtors.push((*party, -amount));
+                } else if *amount > 0 {
+                    creditors.push((*party, *amount));
+                }
+            }
+            debtors.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+            creditors.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+            let mut creditors = creditors.into_iter().peekable();
+            for (debtor, mut owed) in debtors {
+                while owed > 0 {
+                    let Some((creditor, due)) = creditors.peek_mut() else {
+                        break;
+                    };
+                    let amount = owed.min(*due);
+                    transfers.push((mint, debtor, *creditor, amount as u64));
+                    owed -= amount;
+                    *due -= amount;
+                    if *due == 0 {
+                        creditors.next();
+                    }
+                }
+            }
+        }
+        transfers
     }
 }
 
+/// How the ledger is reduced to base chain transfers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NettingMode {
+    /// Transfers between each pair of parties are netted against each other,
+    /// leaving at most one transfer per pair.
+    #[default]
+    Pairwise,
+    /// Each party's transfers are netted into a single position, and debtors
+    /// pay creditors directly, even if they never transacted with each
+    /// other.
+    Global,
+}
+
 /// PayTube final transaction settler.
 pub struct PayTubeSettler<'a> {
     rpc_client: &'a RpcClient,
+    packer: SettlementPacker,
+    /// Durable nonce accounts to build settlement transactions on, one per
+    /// transaction, instead of a recent blockhash.
+    nonce_accounts: Vec<Pubkey>,
+    netting: NettingMode,
+    fees: Option<ChannelFees>,
+    /// Pays for token accounts created for new token holders, if not the
+    /// settlement's fee payer.
+    token_account_payer: Option<Pubkey>,
 }
 
 impl<'a> PayTubeSettler<'a> {
     pub fn new(rpc_client: &'a RpcClient) -> Self {
-        Self { rpc_client }
+        Self::with_packer(rpc_client, SettlementPacker::default())
+    }
+
+    pub fn with_packer(rpc_client: &'a RpcClient, packer: SettlementPacker) -> Self {
+        Self {
+            rpc_client,
+            packer,
+            nonce_accounts: Vec::new(),
+            netting: NettingMode::default(),
+            fees: None,
+            token_account_payer: None,
+        }
+    }
+
+    /// Charge `fees` on the transfers being settled.
+    pub fn with_fees(mut self, fees: Option<ChannelFees>) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    /// Have `payer` pay for the token accounts created for recipients who
+    /// don't hold a mint's tokens yet. It has to sign the settlement.
+    pub fn with_token_account_payer(mut self, payer: Pubkey) -> Self {
+        self.token_account_payer = Some(payer);
+        self
+    }
+
+    pub fn with_netting(mut self, netting: NettingMode) -> Self {
+        self.netting = netting;
+        self
+    }
+
+    /// Build settlement transactions on the durable nonces stored in
+    /// `nonce_accounts`, so they can be co-signed over any length of time and
+    /// submitted later. A settlement needs a nonce account per transaction,
+    /// and the accounts mustn't be used by any other settlement until it is
+    /// confirmed.
+    pub fn with_nonce_accounts(mut self, nonce_accounts: Vec<Pubkey>) -> Self {
+        self.nonce_accounts = nonce_accounts;
+        self
+    }
+
+    fn load_nonce(&self, account: &Pubkey) -> Result<SettlementNonce, String> {
+        let data = nonce_utils::get_account_with_commitment(
+            self.rpc_client,
+            account,
+            self.rpc_client.commitment(),
+        )
+        .and_then(|nonce_account| nonce_utils::data_from_account(&nonce_account))
+        .map_err(|e| format!("Invalid nonce account {}: {}", account, e))?;
+        Ok(SettlementNonce {
+            account: *account,
+            authority: data.authority,
+            nonce: data.blockhash(),
+        })
+    }
+
+    /// Whether a transaction built on `recent_blockhash` can still land, and
+    /// if not, the blockhash to build it on instead.
+    fn refresh_blockhash(
+        &self,
+        recent_blockhash: Hash,
+        nonce_account: Option<Pubkey>,
+    ) -> Result<Option<Hash>, String> {
+        match nonce_account {
+            // A durable nonce stays valid until it's advanced.
+            Some(nonce_account) => {
+                let nonce = self.load_nonce(&nonce_account)?.nonce;
+                Ok((nonce != recent_blockhash).then_some(nonce))
+            }
+            None => {
+                if self
+                    .rpc_client
+                    .is_blockhash_valid(&recent_blockhash, CommitmentConfig::processed())
+                    .map_err(|e| e.to_string())?
+                {
+                    return Ok(None);
+                }
+                self.rpc_client
+                    .get_latest_blockhash()
+                    .map(Some)
+                    .map_err(|e| e.to_string())
+            }
+        }
     }
 
     /// Settle the payment channel results to the Solana blockchain.
@@ -118,26 +367,244 @@ impl<'a> PayTubeSettler<'a> {
         &self,
         paytube_transactions: &[PayTubeTransaction],
         svm_output: LoadAndExecuteSanitizedTransactionsOutput,
-        keys: &[Keypair],
-    ) {
+        payer: &Pubkey,
+        signers: &[&dyn Signer],
+    ) -> Result<Vec<Signature>, String> {
+        let pending = self.plan(paytube_transactions, svm_output, payer, signers)?;
+        self.settle(&mut SettlementJournal::new(pending), signers)
+    }
+
+    /// Build the settlement transactions for the payment channel results.
+    pub fn plan(
+        &self,
+        paytube_transactions: &[PayTubeTransaction],
+        svm_output: LoadAndExecuteSanitizedTransactionsOutput,
+        payer: &Pubkey,
+        signers: &[&dyn Signer],
+    ) -> Result<Vec<PendingSettlement>, String> {
+        // Look up the token program, decimals and transfer fee of every mint
+        // transferred.
+        let mints = MintInfo::load_all(
+            paytube_transactions
+                .iter()
+                .filter_map(|transaction| transaction.mint)
+                .collect::<HashSet<_>>(),
+            |mint| {
+                self.rpc_client
+                    .get_account(mint)
+                    .ok()
+                    .map(AccountSharedData::from)
+            },
+        )?;
+        let epoch = self
+            .rpc_client
+            .get_epoch_info()
+            .map_err(|e| e.to_string())?
+            .epoch;
+
         // Build the ledger from the processed PayTube transactions.
-        let ledger = Ledger::new(paytube_transactions, svm_output);
-
-        // Build the Solana instructions from the ledger.
-        let instructions = ledger.generate_base_chain_instructions();
-
-        // Send the transactions to the Solana blockchain.
-        let recent_blockhash = self.rpc_client.get_latest_blockhash().unwrap();
-        instructions.chunks(10).for_each(|chunk| {
-            let transaction = SolanaTransaction::new_signed_with_payer(
-                chunk,
-                Some(&keys[0].pubkey()),
-                keys,
-                recent_blockhash,
-            );
+        let ledger = Ledger::new(
+            paytube_transactions,
+            svm_output,
+            self.fees.as_ref(),
+            mints,
+            epoch,
+        );
+
+        // Build the Solana instructions from the ledger, creating any missing
+        // token accounts along the way.
+        let instructions = ledger.generate_base_chain_instructions(
+            self.netting,
+            self.token_account_payer.as_ref().unwrap_or(payer),
+            |token_account| self.rpc_client.get_account(token_account).is_ok(),
+        )?;
+
+        self.prepare(&instructions, payer, signers)
+    }
+
+    /// Build the settlement transactions for `instructions`, signed by
+    /// whichever of `signers` they need. Any other signatures have to be
+    /// added before they can be submitted.
+    pub fn prepare(
+        &self,
+        instructions: &[SolanaInstruction],
+        payer: &Pubkey,
+        signers: &[&dyn Signer],
+    ) -> Result<Vec<PendingSettlement>, String> {
+        let groups: Vec<&[SolanaInstruction]> =
+            instructions.iter().map(std::slice::from_ref).collect();
+        self.prepare_groups(&groups, payer, signers)
+    }
+
+    /// Build settlement transactions like `prepare`, keeping each group of
+    /// instructions together in one transaction, so it stays atomic.
+    pub fn prepare_groups(
+        &self,
+        groups: &[&[SolanaInstruction]],
+        payer: &Pubkey,
+        signers: &[&dyn Signer],
+    ) -> Result<Vec<PendingSettlement>, String> {
+        let messages = if self.nonce_accounts.is_empty() {
+            let recent_blockhash = self
+                .rpc_client
+                .get_latest_blockhash()
+                .map_err(|e| e.to_string())?;
+            self.packer.pack_groups(groups, payer, recent_blockhash)?
+        } else {
+            let nonces = self
+                .nonce_accounts
+                .iter()
+                .map(|account| self.load_nonce(account))
+                .collect::<Result<Vec<_>, String>>()?;
+            self.packer
+                .pack_groups_with_nonces(groups, payer, &nonces)?
+        };
+        messages
+            .into_iter()
+            .map(|message| {
+                let mut pending = PendingSettlement::from_message(message);
+                for signer in signers {
+                    pending.sign(*signer)?;
+                }
+                Ok(pending)
+            })
+            .collect()
+    }
+
+    /// Build a settlement transaction for each group of instructions, paid
+    /// for by the group's own payer. Unlike `prepare_groups`, groups never
+    /// share a transaction, so a signature given for one group's
+    /// instructions can't authorize another's.
+    pub fn prepare_separately(
+        &self,
+        groups: &[(&[SolanaInstruction], Pubkey)],
+        signers: &[&dyn Signer],
+    ) -> Result<Vec<PendingSettlement>, String> {
+        let nonces = self
+            .nonce_accounts
+            .iter()
+            .map(|account| self.load_nonce(account))
+            .collect::<Result<Vec<_>, String>>()?;
+        let recent_blockhash = if nonces.is_empty() {
+            Some(
+                self.rpc_client
+                    .get_latest_blockhash()
+                    .map_err(|e| e.to_string())?,
+            )
+        } else {
+            None
+        };
+
+        let mut pending = Vec::new();
+        for (index, (instructions, payer)) in groups.iter().enumerate() {
+            let group = [*instructions];
+            let messages = match recent_blockhash {
+                Some(recent_blockhash) => {
+                    self.packer.pack_groups(&group, payer, recent_blockhash)?
+                }
+                None => self.packer.pack_groups_with_nonces(
+                    &group,
+                    payer,
+                    nonces.get(index..).unwrap_or_default(),
+                )?,
+            };
+            for message in messages {
+                let mut settlement = PendingSettlement::from_message(message);
+                for signer in signers {
+                    settlement.sign(*signer)?;
+                }
+                pending.push(settlement);
+            }
+        }
+        Ok(pending)
+    }
+
+    /// Simulate settlement transactions on the Solana blockchain, without
+    /// sending them. They don't need to be fully signed.
+    pub fn simulate(&self, pending: &[PendingSettlement]) -> Result<Vec<SimulationReport>, String> {
+        pending
+            .iter()
+            .map(|settlement| {
+                simulate_transaction(self.rpc_client, settlement.partial_transaction())
+            })
+            .collect()
+    }
+
+    /// Submit settlement transactions to the Solana blockchain. Nothing is
+    /// sent unless every transaction is fully signed.
+    pub fn submit(&self, pending: &[PendingSettlement]) -> Result<Vec<Signature>, String> {
+        self.settle(&mut SettlementJournal::new(pending.to_vec()), &[])
+    }
+
+    /// Send every transaction in `journal` that hasn't been confirmed yet,
+    /// recording its progress. Nothing is sent unless every planned
+    /// transaction is fully signed.
+    ///
+    /// Resuming a journal after a failure is idempotent: submitted
+    /// transactions are looked up by signature before being sent again, and
+    /// transactions whose blockhash expired, or whose durable nonce was
+    /// advanced, without landing are planned again on a new blockhash and
+    /// signed by `signers`.
+    pub fn settle(
+        &self,
+        journal: &mut SettlementJournal,
+        signers: &[&dyn Signer],
+    ) -> Result<Vec<Signature>, String> {
+        for entry in journal.entries() {
+            if entry.status == SettlementStatus::Planned {
+                entry.settlement.transaction()?;
+            }
+        }
+
+        let unconfirmed: Vec<usize> = journal.unconfirmed().collect();
+        for index in unconfirmed {
+            let entry = &journal.entries()[index];
+            let signature = entry.settlement.signature();
+            if entry.status == SettlementStatus::Submitted {
+                // It may have landed before the last attempt heard back.
+                match self
+                    .rpc_client
+                    .get_signature_status_with_commitment_and_history(
+                        &signature,
+                        self.rpc_client.commitment(),
+                        true,
+                    )
+                    .map_err(|e| e.to_string())?
+                {
+                    Some(Ok(())) => {
+                        journal.set_status(index, SettlementStatus::Confirmed)?;
+                        continue;
+                    }
+                    Some(Err(e)) => {
+                        return Err(format!(
+                            "Settlement transaction {} failed: {}",
+                            signature, e
+                        ))
+                    }
+                    None => {}
+                }
+            }
+
+            if let Some(recent_blockhash) = self.refresh_blockhash(
+                entry.settlement.recent_blockhash(),
+                entry.settlement.nonce_account(),
+            )? {
+                // It never landed, and now it never will.
+                let mut settlement = entry.settlement.clone();
+                settlement.set_recent_blockhash(recent_blockhash);
+                for signer in signers {
+                    settlement.sign(*signer)?;
+                }
+                journal.replan(index, settlement)?;
+            }
+
+            let transaction = journal.entries()[index].settlement.transaction()?.clone();
+            journal.set_status(index, SettlementStatus::Submitted)?;
             self.rpc_client
                 .send_and_confirm_transaction(&transaction)
-                .unwrap();
-        });
+                .map_err(|e| e.to_string())?;
+            journal.set_status(index, SettlementStatus::Confirmed)?;
+        }
+        Ok(journal.confirmed_signatures())
     }
 }
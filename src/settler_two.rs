@@ -11,17 +11,61 @@
 //! channel is about to close are needed to create the settlement transaction.
 
 use {
-    crate::transaction_two::RpsTransaction,
-    solana_client::rpc_client::RpcClient,
+    crate::{
+        escrow::StakeEscrow,
+        game_traits::{ArchivedGame, GameInstanceId, Payout, PlayerId},
+        packer::SettlementPacker,
+        signing::PendingSettlement,
+        simulation::{simulate_transaction, SimulationReport},
+        transaction_two::RpsTransaction,
+    },
+    solana_client::{
+        client_error::{ClientError, ClientErrorKind},
+        rpc_client::RpcClient,
+        rpc_request::RpcError,
+    },
     solana_sdk::{
-        instruction::Instruction as SolanaInstruction, pubkey::Pubkey, signature::Keypair,
-        signer::Signer, system_instruction, transaction::Transaction as SolanaTransaction,
+        instruction::Instruction as SolanaInstruction,
+        pubkey::Pubkey,
+        signature::{Keypair, Signature},
+        signer::Signer,
+        system_instruction,
+        transaction::VersionedTransaction,
     },
     solana_svm::transaction_processor::LoadAndExecuteSanitizedTransactionsOutput,
     spl_associated_token_account::get_associated_token_address,
-    std::collections::HashMap,
+    std::{collections::HashMap, thread, time::Duration},
 };
 
+/// How a finished game's payouts are settled to the base chain.
+#[derive(Debug, Clone)]
+pub struct SettlementConfig {
+    /// How many times a transaction is resent after a transient RPC failure.
+    pub max_retries: u32,
+    /// How long to wait between retries.
+    pub retry_delay: Duration,
+}
+
+impl Default for SettlementConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Errors worth retrying: the request never reached the node, or the node
+/// failed to answer it.
+fn is_transient(error: &ClientError) -> bool {
+    matches!(
+        error.kind(),
+        ClientErrorKind::Io(_)
+            | ClientErrorKind::Reqwest(_)
+            | ClientErrorKind::RpcError(RpcError::RpcRequestError(_))
+    )
+}
+
 /// The key used for storing ledger entries.
 ///
 /// Each entry in the ledger represents the movement of SOL or tokens between
@@ -59,7 +103,6 @@ impl RPSLedger {
                 // successful.
                 // println!("Result from transactions{:?}", result);
                 if result.was_executed_successfully() {
-
                     println!("Yes")
                 } else {
                     println!("No")
@@ -120,17 +163,198 @@ impl<'a> PlayChannelSettler<'a> {
         let instructions = ledger.generate_base_chain_instructions();
 
         // Send the transactions to the Solana blockchain.
-        let recent_blockhash = self.rpc_client.get_latest_blockhash().unwrap();
-        instructions.chunks(10).for_each(|chunk| {
-            let transaction = SolanaTransaction::new_signed_with_payer(
-                chunk,
-                Some(&keys[0].pubkey()),
-                keys,
-                recent_blockhash,
-            );
+        let transactions = self.sign(&instructions, keys).unwrap();
+        transactions.iter().for_each(|transaction| {
             self.rpc_client
-                .send_and_confirm_transaction(&transaction)
+                .send_and_confirm_transaction(transaction)
                 .unwrap();
         });
     }
+
+    /// Build the transactions settling an archived game, without sending
+    /// them: its unsettled payouts out of the players' stakes held in
+    /// `escrow`, then the refunds of whatever else is escrowed, as built by
+    /// `build_payout_transactions`.
+    pub fn plan(
+        &self,
+        escrow: &StakeEscrow,
+        archived: &ArchivedGame,
+        keys: &[Keypair],
+    ) -> Result<Vec<(Option<usize>, VersionedTransaction)>, String> {
+        let stakes: Vec<(PlayerId, u64)> = archived
+            .final_state
+            .wagering_state
+            .iter()
+            .flat_map(|wagering_state| wagering_state.player_stakes.values())
+            .map(|stake| (stake.player_id, stake.amount))
+            .collect();
+        let settled: Vec<bool> = (0..archived.payouts.len())
+            .map(|index| archived.is_payout_settled(index))
+            .collect();
+
+        self.build_payout_transactions(
+            escrow,
+            &archived.final_state.game_instance_id,
+            &stakes,
+            &archived.payouts,
+            &settled,
+            keys,
+        )
+    }
+
+    /// Simulate settlement transactions, such as those of `plan`, on the
+    /// Solana blockchain, without sending them.
+    pub fn simulate(
+        &self,
+        transactions: &[VersionedTransaction],
+    ) -> Result<Vec<SimulationReport>, String> {
+        transactions
+            .iter()
+            .map(|transaction| simulate_transaction(self.rpc_client, transaction))
+            .collect()
+    }
+
+    /// Pack `instructions` into transactions paid for by the first of
+    /// `keys`, each signed by whichever of `keys` it needs.
+    fn sign(
+        &self,
+        instructions: &[SolanaInstruction],
+        keys: &[Keypair],
+    ) -> Result<Vec<VersionedTransaction>, String> {
+        let payer = keys
+            .first()
+            .ok_or_else(|| "No settlement keys".to_string())?;
+        let recent_blockhash = self
+            .rpc_client
+            .get_latest_blockhash()
+            .map_err(|e| e.to_string())?;
+        SettlementPacker::default()
+            .pack(instructions, &payer.pubkey(), recent_blockhash)?
+            .into_iter()
+            .map(|message| {
+                let mut pending = PendingSettlement::from_message(message);
+                for key in keys {
+                    pending.sign(key)?;
+                }
+                pending.transaction().cloned()
+            })
+            .collect()
+    }
+
+    /// Build the transactions paying a finished game's `payouts` out of the
+    /// players' escrowed `stakes`.
+    ///
+    /// Every payout that isn't `settled` yet is paid by a transaction of its
+    /// own, tagged with the payout's index, so a partially settled game can
+    /// be resumed. They're followed by the transactions returning whatever
+    /// else is escrowed to the players, tagged `None`.
+    pub fn build_payout_transactions(
+        &self,
+        escrow: &StakeEscrow,
+        game_instance_id: &GameInstanceId,
+        stakes: &[(PlayerId, u64)],
+        payouts: &[Payout],
+        settled: &[bool],
+        keys: &[Keypair],
+    ) -> Result<Vec<(Option<usize>, VersionedTransaction)>, String> {
+        if !keys.iter().any(|key| key.pubkey() == escrow.authority) {
+            return Err("Escrow authority is not held by the channel".to_string());
+        }
+
+        let amounts: Vec<(PlayerId, u64)> = payouts
+            .iter()
+            .map(|payout| (payout.player_id, payout.amount))
+            .collect();
+        let funding = StakeEscrow::fund_payouts(stakes, &amounts)?;
+
+        let mut transactions = Vec::new();
+        // What the unsettled payouts still draw from each deposit
+        let mut pending: HashMap<PlayerId, u64> = HashMap::new();
+        for (index, ((to, _), draws)) in amounts.iter().zip(funding).enumerate() {
+            if settled.get(index).copied().unwrap_or(false) {
+                continue;
+            }
+            let instructions = draws
+                .into_iter()
+                // A token stake paid back to its owner stays where it is.
+                .filter(|(from, _)| escrow.mint.is_none() || from != to)
+                .map(|(from, amount)| {
+                    *pending.entry(from).or_default() += amount;
+                    escrow.transfer_instruction(game_instance_id, &from, to, amount)
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            if instructions.is_empty() {
+                continue;
+            }
+            let mut signed = self.sign(&instructions, keys)?;
+            if signed.len() != 1 {
+                return Err("Payout does not fit in one transaction".to_string());
+            }
+            transactions.push((Some(index), signed.remove(0)));
+        }
+
+        // Release whatever else is held in SOL deposits, so nothing is left
+        // stranded. Token stakes were only delegated and need no transfer.
+        if escrow.mint.is_none() {
+            let mut refunds = Vec::new();
+            for (player_id, _) in stakes {
+                let held = self
+                    .rpc_client
+                    .get_balance(&escrow.deposit_address(game_instance_id, player_id))
+                    .map_err(|e| e.to_string())?;
+                let amount = held.saturating_sub(pending.get(player_id).copied().unwrap_or(0));
+                if amount > 0 {
+                    refunds.push(escrow.transfer_instruction(
+                        game_instance_id,
+                        player_id,
+                        player_id,
+                        amount,
+                    )?);
+                }
+            }
+            if !refunds.is_empty() {
+                for transaction in self.sign(&refunds, keys)? {
+                    transactions.push((None, transaction));
+                }
+            }
+        }
+
+        Ok(transactions)
+    }
+
+    /// Send a transaction and wait for confirmation, retrying on transient
+    /// RPC failures. `on_retry` is called with the attempt number and the
+    /// error before each retry.
+    ///
+    /// The same signed transaction is resent on every attempt, so a retry
+    /// after a send that actually landed is deduplicated by the cluster
+    /// rather than paying out twice. A failed send, e.g. one that timed out
+    /// after landing, or a resend rejected as already processed, is looked
+    /// up on the cluster before it's retried or reported, so a transaction
+    /// that landed is always reported as confirmed.
+    pub fn send_with_retry(
+        &self,
+        transaction: &VersionedTransaction,
+        config: &SettlementConfig,
+        mut on_retry: impl FnMut(u32, &ClientError),
+    ) -> Result<Signature, String> {
+        let signature = transaction.signatures[0];
+        let mut attempt = 0;
+        loop {
+            match self.rpc_client.send_and_confirm_transaction(transaction) {
+                Ok(signature) => return Ok(signature),
+                Err(e) => {
+                    if let Ok(Some(status)) = self.rpc_client.get_signature_status(&signature) {
+                        return status.map(|_| signature).map_err(|e| e.to_string());
+                    }
+                    if !is_transient(&e) || attempt >= config.max_retries {
+                        return Err(e.to_string());
+                    }
+                    attempt += 1;
+                    on_retry(attempt, &e);
+                    thread::sleep(config.retry_delay);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,197 @@
+//! Signing settlement transactions without holding participants' keys.
+//!
+//! Settling a channel moves participants' funds, so the settlement
+//! transactions need their signatures. Rather than handing the channel their
+//! keypairs, participants can:
+//!
+//! * give the channel a `Signer` that signs on their behalf, such as a
+//!   `CallbackSigner` forwarding the message to a wallet, or
+//! * sign the `message_data` of each `PendingSettlement` themselves and hand
+//!   the signatures back with `add_signature`.
+//!
+//! Settlement transactions are only submitted once every required signature
+//! has been gathered.
+
+use {
+    serde::{Deserialize, Serialize},
+    solana_sdk::{
+        hash::Hash,
+        instruction::Instruction,
+        message::{Message, VersionedMessage},
+        pubkey::Pubkey,
+        signature::Signature,
+        signer::{Signer, SignerError},
+        system_instruction, system_program,
+        transaction::VersionedTransaction,
+    },
+    std::fmt,
+};
+
+/// A settlement transaction waiting for its signatures.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingSettlement {
+    transaction: VersionedTransaction,
+}
+
+impl PendingSettlement {
+    pub fn new(instructions: &[Instruction], payer: &Pubkey, recent_blockhash: Hash) -> Self {
+        let message = Message::new_with_blockhash(instructions, Some(payer), &recent_blockhash);
+        Self::from_message(VersionedMessage::Legacy(message))
+    }
+
+    /// A pending settlement for a compiled (legacy or v0) message.
+    pub fn from_message(message: VersionedMessage) -> Self {
+        let signatures = message.header().num_required_signatures as usize;
+        Self {
+            transaction: VersionedTransaction {
+                signatures: vec![Signature::default(); signatures],
+                message,
+            },
+        }
+    }
+
+    /// The bytes each signer signs.
+    pub fn message_data(&self) -> Vec<u8> {
+        self.transaction.message.serialize()
+    }
+
+    /// The transaction's id: the fee payer's signature.
+    pub fn signature(&self) -> Signature {
+        self.transaction.signatures[0]
+    }
+
+    pub fn recent_blockhash(&self) -> Hash {
+        *self.transaction.message.recent_blockhash()
+    }
+
+    /// The durable nonce account the transaction is built on, if any.
+    pub fn nonce_account(&self) -> Option<Pubkey> {
+        let message = &self.transaction.message;
+        let account_keys = message.static_account_keys();
+        let instruction = message.instructions().first()?;
+        let advance_nonce =
+            system_instruction::advance_nonce_account(&Pubkey::default(), &Pubkey::default());
+        if account_keys.get(instruction.program_id_index as usize) != Some(&system_program::id())
+            || instruction.data != advance_nonce.data
+        {
+            return None;
+        }
+        account_keys
+            .get(*instruction.accounts.first()? as usize)
+            .copied()
+    }
+
+    /// Move the transaction to a new blockhash. Every signature has to be
+    /// given again.
+    pub fn set_recent_blockhash(&mut self, recent_blockhash: Hash) {
+        self.transaction
+            .message
+            .set_recent_blockhash(recent_blockhash);
+        for signature in &mut self.transaction.signatures {
+            *signature = Signature::default();
+        }
+    }
+
+    /// Every key that must sign the transaction, fee payer first.
+    pub fn signers(&self) -> &[Pubkey] {
+        let message = &self.transaction.message;
+        &message.static_account_keys()[..message.header().num_required_signatures as usize]
+    }
+
+    /// The signers that haven't signed yet.
+    pub fn missing_signers(&self) -> Vec<Pubkey> {
+        self.signers()
+            .iter()
+            .zip(&self.transaction.signatures)
+            .filter(|(_, signature)| **signature == Signature::default())
+            .map(|(signer, _)| *signer)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing_signers().is_empty()
+    }
+
+    /// Add a signature produced elsewhere, after checking it's valid.
+    pub fn add_signature(&mut self, signer: &Pubkey, signature: Signature) -> Result<(), String> {
+        let position = self
+            .signers()
+            .iter()
+            .position(|key| key == signer)
+            .ok_or_else(|| format!("{} does not sign this transaction", signer))?;
+        if !signature.verify(signer.as_ref(), &self.message_data()) {
+            return Err(format!("Invalid signature from {}", signer));
+        }
+        self.transaction.signatures[position] = signature;
+        Ok(())
+    }
+
+    /// Sign with `signer`, if the transaction needs it.
+    pub fn sign(&mut self, signer: &dyn Signer) -> Result<bool, String> {
+        let pubkey = signer.try_pubkey().map_err(|e| e.to_string())?;
+        if !self.signers().contains(&pubkey) {
+            return Ok(false);
+        }
+        let signature = signer
+            .try_sign_message(&self.message_data())
+            .map_err(|e| e.to_string())?;
+        self.add_signature(&pubkey, signature)?;
+        Ok(true)
+    }
+
+    /// The transaction with the signatures gathered so far, e.g. to simulate
+    /// it before it's complete.
+    pub fn partial_transaction(&self) -> &VersionedTransaction {
+        &self.transaction
+    }
+
+    /// The fully signed transaction, ready to submit.
+    pub fn transaction(&self) -> Result<&VersionedTransaction, String> {
+        let missing = self.missing_signers();
+        if !missing.is_empty() {
+            return Err(format!("Missing signatures from {:?}", missing));
+        }
+        Ok(&self.transaction)
+    }
+}
+
+/// A `Signer` that asks a callback for signatures, e.g. to forward the
+/// message to a participant's wallet.
+pub struct CallbackSigner<F> {
+    pubkey: Pubkey,
+    callback: F,
+}
+
+impl<F> CallbackSigner<F>
+where
+    F: Fn(&[u8]) -> Result<Signature, SignerError>,
+{
+    pub fn new(pubkey: Pubkey, callback: F) -> Self {
+        Self { pubkey, callback }
+    }
+}
+
+impl<F> Signer for CallbackSigner<F>
+where
+    F: Fn(&[u8]) -> Result<Signature, SignerError>,
+{
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        Ok(self.pubkey)
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        (self.callback)(message)
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+impl<F> fmt::Debug for CallbackSigner<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallbackSigner")
+            .field("pubkey", &self.pubkey)
+            .finish()
+    }
+}
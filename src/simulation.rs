@@ -0,0 +1,110 @@
+//! Dry runs of settlement transactions against the base chain.
+//!
+//! Before anything is committed, a settler's planned transactions can be
+//! simulated by the RPC node, reporting whether each would succeed, the
+//! compute it would use and the balances of the accounts it writes to.
+//!
+//! Every transaction is simulated against the current state of the base
+//! chain, independently of the others, and signatures aren't verified, so
+//! settlements can be checked before they've been co-signed.
+
+use {
+    crate::token::is_token_program,
+    solana_client::{
+        rpc_client::RpcClient,
+        rpc_config::{RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig},
+    },
+    solana_sdk::{
+        account::{Account, ReadableAccount},
+        pubkey::Pubkey,
+        signature::Signature,
+        transaction::{self, VersionedTransaction},
+    },
+    spl_token_2022::{extension::StateWithExtensions, state::Account as TokenAccount},
+};
+
+/// An account's balance after a simulated transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostBalance {
+    pub address: Pubkey,
+    pub lamports: u64,
+    /// The token balance, if the account is a token account.
+    pub token_amount: Option<u64>,
+}
+
+/// The outcome of a simulated settlement transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationReport {
+    /// The transaction's fee payer signature, if already signed.
+    pub signature: Signature,
+    pub result: transaction::Result<()>,
+    pub compute_units: Option<u64>,
+    pub logs: Vec<String>,
+    /// Balances of every account the transaction writes to.
+    pub post_balances: Vec<PostBalance>,
+}
+
+impl SimulationReport {
+    pub fn is_success(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// Simulate `transaction` on the base chain.
+pub fn simulate_transaction(
+    rpc_client: &RpcClient,
+    transaction: &VersionedTransaction,
+) -> Result<SimulationReport, String> {
+    let message = &transaction.message;
+    // Writable accounts loaded from lookup tables aren't static keys, so
+    // balances are only reported for the static ones.
+    let writable: Vec<Pubkey> = message
+        .static_account_keys()
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| message.is_maybe_writable(*index, None))
+        .map(|(_, address)| *address)
+        .collect();
+
+    let response = rpc_client
+        .simulate_transaction_with_config(
+            transaction,
+            RpcSimulateTransactionConfig {
+                sig_verify: false,
+                replace_recent_blockhash: false,
+                commitment: Some(rpc_client.commitment()),
+                accounts: Some(RpcSimulateTransactionAccountsConfig {
+                    encoding: None,
+                    addresses: writable.iter().map(Pubkey::to_string).collect(),
+                }),
+                ..RpcSimulateTransactionConfig::default()
+            },
+        )
+        .map_err(|e| e.to_string())?
+        .value;
+
+    let post_balances = writable
+        .iter()
+        .zip(response.accounts.unwrap_or_default())
+        .filter_map(|(address, account)| {
+            let account: Account = account?.decode()?;
+            let token_amount = is_token_program(account.owner())
+                .then(|| StateWithExtensions::<TokenAccount>::unpack(account.data()).ok())
+                .flatten()
+                .map(|token_account| token_account.base.amount);
+            Some(PostBalance {
+                address: *address,
+                lamports: account.lamports(),
+                token_amount,
+            })
+        })
+        .collect();
+
+    Ok(SimulationReport {
+        signature: transaction.signatures[0],
+        result: response.err.map_or(Ok(()), Err),
+        compute_units: response.units_consumed,
+        logs: response.logs.unwrap_or_default(),
+        post_balances,
+    })
+}
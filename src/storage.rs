@@ -0,0 +1,348 @@
+//! Durable storage for channel state.
+//!
+//! Everything a channel knows about its games - active `GameState`s with
+//! their signed moves and wagering state, archived games and the accounts
+//! cached from the base chain - otherwise only lives in process memory, as do
+//! the balances of an open payment channel and the transfers it processed.
+//! The `ChannelStorage` trait lets that state be persisted as it changes, so a
+//! channel can be rebuilt after a crash or restart.
+//!
+//! `FileStorage` is a simple embedded implementation: every change is appended
+//! to a log, and the log is periodically compacted into a snapshot.
+//!
+//! ```text
+//!
+//!   <dir>/snapshot.bin     full channel state at the last checkpoint
+//!   <dir>/log.bin          records appended since the last checkpoint
+//!
+//!   recovery = snapshot + replay(log)
+//! ```
+
+use {
+    crate::{
+        fees::ChannelFees,
+        game_traits::{decode_legacy, ArchivedGame, GameEncoding, GameInstanceId, GameState},
+    },
+    borsh::{BorshDeserialize, BorshSerialize},
+    serde::{Deserialize, Serialize},
+    solana_sdk::{
+        account::{Account, AccountSharedData},
+        pubkey::Pubkey,
+    },
+    std::{
+        collections::HashMap,
+        fs::{self, File, OpenOptions},
+        io::{self, BufReader, Read, Write},
+        path::{Path, PathBuf},
+    },
+};
+
+/// An account as persisted by the channel.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+pub struct StoredAccount {
+    pub pubkey: Pubkey,
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+    pub executable: bool,
+    pub rent_epoch: u64,
+}
+
+impl StoredAccount {
+    pub fn new(pubkey: Pubkey, account: AccountSharedData) -> Self {
+        let account = Account::from(account);
+        Self {
+            pubkey,
+            lamports: account.lamports,
+            data: account.data,
+            owner: account.owner,
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+        }
+    }
+
+    pub fn into_account(self) -> (Pubkey, AccountSharedData) {
+        let account = Account {
+            lamports: self.lamports,
+            data: self.data,
+            owner: self.owner,
+            executable: self.executable,
+            rent_epoch: self.rent_epoch,
+        };
+        (self.pubkey, AccountSharedData::from(account))
+    }
+}
+
+/// An open payment channel, as persisted by the channel. Balances are keyed
+/// by owner and mint, `None` being SOL.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+pub struct StoredSession {
+    pub authority: Pubkey,
+    pub participants: Vec<Pubkey>,
+    pub deposits: Vec<(Pubkey, Option<Pubkey>, u64)>,
+    pub balances: Vec<(Pubkey, Option<Pubkey>, u64)>,
+    pub fees: Option<ChannelFees>,
+}
+
+/// A single change to the channel state, appended to the log.
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+pub enum StorageRecord {
+    /// A game was created or its state changed.
+    GameUpdated(GameState),
+    /// A finished game was moved into the history store.
+    GameArchived(ArchivedGame),
+    /// A game was removed, either from the active set or the history.
+    GameRemoved(GameInstanceId),
+    /// Accounts were loaded or modified in the channel.
+    AccountsUpdated(Vec<StoredAccount>),
+    /// A creator's next unassigned game id moved on.
+    GameCounterUpdated(Pubkey, u64),
+    /// The payment channel was opened, its balances changed, or it was
+    /// closed.
+    SessionUpdated(Option<StoredSession>),
+    /// Transfers were processed, by their senders' ids.
+    TransfersProcessed(Vec<(Pubkey, u64)>),
+}
+
+/// The full channel state at a point in time.
+#[derive(Debug, Clone, Default, BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+pub struct ChannelSnapshot {
+    pub active_games: Vec<GameState>,
+    pub game_history: Vec<ArchivedGame>,
+    pub accounts: Vec<StoredAccount>,
+    /// Each creator's next unassigned game id.
+    #[serde(default)]
+    pub game_counters: Vec<(Pubkey, u64)>,
+    /// The open payment channel, if any.
+    #[serde(default)]
+    pub session: Option<StoredSession>,
+    /// Senders' ids of the transfers processed by the payment channel.
+    #[serde(default)]
+    pub transfer_ids: Vec<(Pubkey, u64)>,
+}
+
+impl ChannelSnapshot {
+    /// Apply a logged change on top of this snapshot.
+    pub fn apply(&mut self, record: StorageRecord) {
+        match record {
+            StorageRecord::GameUpdated(game_state) => {
+                let id = game_state.game_instance_id;
+                self.active_games.retain(|g| g.game_instance_id != id);
+                self.active_games.push(game_state);
+            }
+            StorageRecord::GameArchived(archived) => {
+                let id = archived.final_state.game_instance_id;
+                self.active_games.retain(|g| g.game_instance_id != id);
+                self.game_history
+                    .retain(|a| a.final_state.game_instance_id != id);
+                self.game_history.push(archived);
+            }
+            StorageRecord::GameRemoved(id) => {
+                self.active_games.retain(|g| g.game_instance_id != id);
+                self.game_history
+                    .retain(|a| a.final_state.game_instance_id != id);
+            }
+            StorageRecord::AccountsUpdated(accounts) => {
+                let mut by_key: HashMap<Pubkey, StoredAccount> = self
+                    .accounts
+                    .drain(..)
+                    .map(|account| (account.pubkey, account))
+                    .collect();
+                for account in accounts {
+                    by_key.insert(account.pubkey, account);
+                }
+                self.accounts = by_key.into_values().collect();
+            }
+            StorageRecord::GameCounterUpdated(creator, next_game_id) => {
+                self.game_counters.retain(|(pubkey, _)| *pubkey != creator);
+                self.game_counters.push((creator, next_game_id));
+            }
+            StorageRecord::SessionUpdated(session) => self.session = session,
+            StorageRecord::TransfersProcessed(transfer_ids) => {
+                self.transfer_ids.extend(transfer_ids);
+            }
+        }
+    }
+}
+
+impl GameEncoding for StoredAccount {}
+
+// Of the records, only archived games changed layout between versions.
+impl GameEncoding for StorageRecord {
+    fn from_legacy_bytes(version: u8, data: &[u8]) -> Result<Self, String> {
+        match data.split_first() {
+            Some((&1, archived)) => decode_legacy(archived, |reader| {
+                ArchivedGame::deserialize_versioned(reader, version)
+            })
+            .map(StorageRecord::GameArchived),
+            _ => Self::try_from_slice(data).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl GameEncoding for ChannelSnapshot {
+    fn from_legacy_bytes(version: u8, data: &[u8]) -> Result<Self, String> {
+        decode_legacy(data, |reader| {
+            let active_games = Vec::<GameState>::deserialize_reader(reader)?;
+            let len = u32::deserialize_reader(reader)?;
+            let game_history = (0..len)
+                .map(|_| ArchivedGame::deserialize_versioned(reader, version))
+                .collect::<io::Result<_>>()?;
+            let accounts = Vec::<StoredAccount>::deserialize_reader(reader)?;
+            // Game counters were added in version 5, the payment channel's
+            // session and transfers in version 6.
+            let game_counters = if version >= 5 {
+                Vec::<(Pubkey, u64)>::deserialize_reader(reader)?
+            } else {
+                Vec::new()
+            };
+            Ok(Self {
+                active_games,
+                game_history,
+                accounts,
+                game_counters,
+                session: None,
+                transfer_ids: Vec::new(),
+            })
+        })
+    }
+}
+
+/// Pluggable persistence backend for channel state.
+pub trait ChannelStorage: Send {
+    /// Durably record a single change.
+    fn append(&mut self, record: &StorageRecord) -> io::Result<()>;
+
+    /// Replace all stored state with `snapshot`, discarding the log.
+    fn checkpoint(&mut self, snapshot: &ChannelSnapshot) -> io::Result<()>;
+
+    /// Rebuild the latest state from storage.
+    fn load(&self) -> io::Result<ChannelSnapshot>;
+}
+
+/// Storage that keeps everything in memory. Useful for tests.
+#[derive(Default)]
+pub struct MemoryStorage {
+    snapshot: ChannelSnapshot,
+    log: Vec<StorageRecord>,
+}
+
+impl ChannelStorage for MemoryStorage {
+    fn append(&mut self, record: &StorageRecord) -> io::Result<()> {
+        self.log.push(record.clone());
+        Ok(())
+    }
+
+    fn checkpoint(&mut self, snapshot: &ChannelSnapshot) -> io::Result<()> {
+        self.snapshot = snapshot.clone();
+        self.log.clear();
+        Ok(())
+    }
+
+    fn load(&self) -> io::Result<ChannelSnapshot> {
+        let mut snapshot = self.snapshot.clone();
+        self.log
+            .iter()
+            .for_each(|record| snapshot.apply(record.clone()));
+        Ok(snapshot)
+    }
+}
+
+/// Embedded on-disk storage: an append-only log plus snapshots.
+///
+/// Log entries are length-prefixed, versioned records. A torn write at the
+/// end of the log (e.g. a crash mid-append) is cut off when the log is
+/// opened, so that later records aren't appended after it. Any other record
+/// that can't be decoded fails recovery.
+pub struct FileStorage {
+    dir: PathBuf,
+    log: File,
+}
+
+impl FileStorage {
+    const SNAPSHOT: &'static str = "snapshot.bin";
+    const LOG: &'static str = "log.bin";
+
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(Self::LOG);
+        let log = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        let (_, complete_len) = Self::read_log(&path)?;
+        if complete_len < log.metadata()?.len() {
+            log.set_len(complete_len)?;
+            log.sync_all()?;
+        }
+        Ok(Self { dir, log })
+    }
+
+    /// Read the records in the log at `path`, along with the length of the
+    /// log up to the end of the last complete record.
+    fn read_log(path: &Path) -> io::Result<(Vec<StorageRecord>, u64)> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut records = Vec::new();
+        let mut offset = 0u64;
+        loop {
+            let mut len = [0u8; 4];
+            match reader.read_exact(&mut len) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            // A record running past the end of the log is a torn write.
+            let len = u32::from_le_bytes(len) as u64;
+            if offset + 4 + len > file_len {
+                break;
+            }
+            let mut buf = vec![0u8; len as usize];
+            reader.read_exact(&mut buf)?;
+            let record = StorageRecord::from_bytes(&buf).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid log record at offset {}: {}", offset, e),
+                )
+            })?;
+            records.push(record);
+            offset += 4 + len;
+        }
+        Ok((records, offset))
+    }
+}
+
+impl ChannelStorage for FileStorage {
+    fn append(&mut self, record: &StorageRecord) -> io::Result<()> {
+        let bytes = record.to_bytes().map_err(io::Error::other)?;
+        let mut entry = (bytes.len() as u32).to_le_bytes().to_vec();
+        entry.extend_from_slice(&bytes);
+        self.log.write_all(&entry)?;
+        self.log.sync_data()
+    }
+
+    fn checkpoint(&mut self, snapshot: &ChannelSnapshot) -> io::Result<()> {
+        // Write the snapshot next to the old one and swap it in atomically.
+        let tmp = self.dir.join(format!("{}.tmp", Self::SNAPSHOT));
+        let mut file = File::create(&tmp)?;
+        file.write_all(&snapshot.to_bytes().map_err(io::Error::other)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, self.dir.join(Self::SNAPSHOT))?;
+
+        self.log.set_len(0)?;
+        self.log.sync_all()
+    }
+
+    fn load(&self) -> io::Result<ChannelSnapshot> {
+        let mut snapshot = match fs::read(self.dir.join(Self::SNAPSHOT)) {
+            Ok(bytes) => ChannelSnapshot::from_bytes(&bytes).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => ChannelSnapshot::default(),
+            Err(e) => return Err(e),
+        };
+        let (records, _) = Self::read_log(&self.dir.join(Self::LOG))?;
+        for record in records {
+            snapshot.apply(record);
+        }
+        Ok(snapshot)
+    }
+}
@@ -0,0 +1,243 @@
+//! Token mints, whether owned by SPL Token or Token-2022.
+//!
+//! Transfers of a mint's tokens go through the program that owns the mint,
+//! to token accounts derived for that program, and use `transfer_checked`
+//! with the mint's decimals, which both programs support.
+//!
+//! Token-2022 mints can have a transfer fee: the recipient receives the
+//! amount sent less the fee, which is withheld in their token account.
+//!
+//! Recipients that don't hold a mint's tokens yet have their associated token
+//! account created in the same transaction as the transfer, paid for by
+//! whoever the channel designates.
+
+use {
+    solana_sdk::{
+        account::{AccountSharedData, ReadableAccount, WritableAccount},
+        clock::Epoch,
+        instruction::{Instruction, InstructionError},
+        pubkey::Pubkey,
+        rent::Rent,
+    },
+    spl_associated_token_account::{
+        get_associated_token_address_with_program_id,
+        instruction::create_associated_token_account_idempotent,
+    },
+    spl_token_2022::{
+        extension::{
+            transfer_fee::{TransferFeeAmount, TransferFeeConfig},
+            BaseStateWithExtensions, BaseStateWithExtensionsMut, ExtensionType,
+            StateWithExtensions, StateWithExtensionsMut,
+        },
+        state::{Account, AccountState, Mint},
+    },
+    std::collections::HashMap,
+};
+
+/// Whether `program_id` is SPL Token or Token-2022.
+pub fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == spl_token::id() || *program_id == spl_token_2022::id()
+}
+
+/// The amount of tokens `account` holds, if it's a token account.
+pub fn token_amount(account: &AccountSharedData) -> Option<u64> {
+    StateWithExtensions::<Account>::unpack(account.data())
+        .ok()
+        .map(|state| state.base.amount)
+}
+
+/// What's needed to move a mint's tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MintInfo {
+    pub mint: Pubkey,
+    /// SPL Token or Token-2022.
+    pub program_id: Pubkey,
+    pub decimals: u8,
+    pub transfer_fee: Option<TransferFeeConfig>,
+}
+
+impl MintInfo {
+    pub fn from_account(mint: &Pubkey, account: &AccountSharedData) -> Result<Self, String> {
+        let program_id = *account.owner();
+        if !is_token_program(&program_id) {
+            return Err(format!("{} is not a token mint", mint));
+        }
+        // Token-2022's mint layout extends SPL Token's, so both unpack the
+        // same way.
+        let state = StateWithExtensions::<Mint>::unpack(account.data())
+            .map_err(|e| format!("Invalid mint {}: {}", mint, e))?;
+        Ok(Self {
+            mint: *mint,
+            program_id,
+            decimals: state.base.decimals,
+            transfer_fee: state.get_extension::<TransferFeeConfig>().ok().copied(),
+        })
+    }
+
+    /// Load the mints of `mints` with `load_account`.
+    pub fn load_all(
+        mints: impl IntoIterator<Item = Pubkey>,
+        load_account: impl Fn(&Pubkey) -> Option<AccountSharedData>,
+    ) -> Result<HashMap<Pubkey, Self>, String> {
+        mints
+            .into_iter()
+            .map(|mint| {
+                let account =
+                    load_account(&mint).ok_or_else(|| format!("Mint {} not found", mint))?;
+                Ok((mint, Self::from_account(&mint, &account)?))
+            })
+            .collect()
+    }
+
+    /// An owner's associated token account for the mint.
+    pub fn token_account(&self, owner: &Pubkey) -> Pubkey {
+        get_associated_token_address_with_program_id(owner, &self.mint, &self.program_id)
+    }
+
+    /// Create an owner's associated token account for the mint, paid for by
+    /// `payer`. Does nothing if the account already exists.
+    pub fn create_token_account_instruction(&self, payer: &Pubkey, owner: &Pubkey) -> Instruction {
+        create_associated_token_account_idempotent(payer, owner, &self.mint, &self.program_id)
+    }
+
+    /// The fee withheld when sending `amount` in `epoch`.
+    pub fn fee(&self, epoch: Epoch, amount: u64) -> u64 {
+        self.transfer_fee
+            .and_then(|config| config.calculate_epoch_fee(epoch, amount))
+            .unwrap_or_default()
+    }
+
+    /// What has to be sent in `epoch` for the recipient to receive `amount`.
+    pub fn gross_amount(&self, epoch: Epoch, amount: u64) -> u64 {
+        let fee = self
+            .transfer_fee
+            .and_then(|config| config.calculate_inverse_epoch_fee(epoch, amount))
+            .unwrap_or_default();
+        amount.saturating_add(fee)
+    }
+
+    /// Check that `account` is a token account for the mint, held by
+    /// `owner`.
+    pub fn check_token_account(
+        &self,
+        owner: &Pubkey,
+        account: &AccountSharedData,
+    ) -> Result<(), InstructionError> {
+        if *account.owner() != self.program_id {
+            return Err(InstructionError::InvalidAccountOwner);
+        }
+        let state = StateWithExtensions::<Account>::unpack(account.data())
+            .map_err(|_| InstructionError::InvalidAccountData)?;
+        if state.base.mint != self.mint {
+            return Err(InstructionError::InvalidAccountData);
+        }
+        if state.base.owner != *owner {
+            return Err(InstructionError::IllegalOwner);
+        }
+        Ok(())
+    }
+
+    /// A transfer of `amount` from the `source` token account, held by
+    /// `owner`, to the `destination` token account.
+    pub fn transfer_instruction(
+        &self,
+        source: &Pubkey,
+        destination: &Pubkey,
+        owner: &Pubkey,
+        amount: u64,
+    ) -> Result<Instruction, String> {
+        spl_token_2022::instruction::transfer_checked(
+            &self.program_id,
+            source,
+            &self.mint,
+            destination,
+            owner,
+            &[],
+            amount,
+            self.decimals,
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    /// Delegate `amount` of the `token_account`, held by `owner`, to
+    /// `delegate`, replacing any earlier delegation.
+    pub fn approve_instruction(
+        &self,
+        token_account: &Pubkey,
+        delegate: &Pubkey,
+        owner: &Pubkey,
+        amount: u64,
+    ) -> Result<Instruction, String> {
+        spl_token_2022::instruction::approve_checked(
+            &self.program_id,
+            token_account,
+            &self.mint,
+            delegate,
+            owner,
+            &[],
+            amount,
+            self.decimals,
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    /// `owner`'s token account for the mint, holding `amount`: `account` with
+    /// its amount replaced, or a new account if there's none.
+    pub fn token_account_holding(
+        &self,
+        owner: &Pubkey,
+        account: Option<AccountSharedData>,
+        amount: u64,
+    ) -> Result<AccountSharedData, String> {
+        let mut account = match account {
+            Some(account) => account,
+            None => self.new_token_account(owner)?,
+        };
+        let mut state = StateWithExtensionsMut::<Account>::unpack(account.data_as_mut_slice())
+            .map_err(|e| format!("Invalid token account: {}", e))?;
+        state.base.amount = amount;
+        state.pack_base();
+        Ok(account)
+    }
+
+    /// An empty token account for the mint, with the extensions its mint
+    /// requires.
+    fn new_token_account(&self, owner: &Pubkey) -> Result<AccountSharedData, String> {
+        let extensions = match self.transfer_fee {
+            Some(_) => vec![ExtensionType::TransferFeeAmount],
+            None => Vec::new(),
+        };
+        let len = ExtensionType::try_calculate_account_len::<Account>(&extensions)
+            .map_err(|e| e.to_string())?;
+        let mut account =
+            AccountSharedData::new(Rent::default().minimum_balance(len), len, &self.program_id);
+
+        let mut state =
+            StateWithExtensionsMut::<Account>::unpack_uninitialized(account.data_as_mut_slice())
+                .map_err(|e| e.to_string())?;
+        if self.transfer_fee.is_some() {
+            state
+                .init_extension::<TransferFeeAmount>(true)
+                .map_err(|e| e.to_string())?;
+        }
+        state.base = Account {
+            mint: self.mint,
+            owner: *owner,
+            state: AccountState::Initialized,
+            ..Account::default()
+        };
+        state.pack_base();
+        state.init_account_type().map_err(|e| e.to_string())?;
+        Ok(account)
+    }
+
+    /// Revoke the delegation of the `token_account`, held by `owner`.
+    pub fn revoke_instruction(
+        &self,
+        token_account: &Pubkey,
+        owner: &Pubkey,
+    ) -> Result<Instruction, String> {
+        spl_token_2022::instruction::revoke(&self.program_id, token_account, owner, &[])
+            .map_err(|e| e.to_string())
+    }
+}
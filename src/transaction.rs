@@ -7,79 +7,148 @@
 //! case Solana.
 
 use {
-    crate::transaction_two::RpsTransaction,
+    crate::{token::MintInfo, transaction_two::RpsTransaction},
     solana_sdk::{
-        instruction::Instruction as SolanaInstruction,
+        account::AccountSharedData,
+        instruction::{Instruction as SolanaInstruction, InstructionError},
         pubkey::Pubkey,
         system_instruction,
         transaction::{
             SanitizedTransaction as SolanaSanitizedTransaction, Transaction as SolanaTransaction,
         },
     },
-    spl_associated_token_account::get_associated_token_address,
-    std::collections::HashSet,
+    std::collections::{HashMap, HashSet},
 };
 
 /// A simple PayTube transaction. Transfers SPL tokens or SOL from one account
 /// to another.
 ///
 /// A `None` value for `mint` represents native SOL.
+///
+/// Tokens move between the sender's and recipient's associated token
+/// accounts, unless other token accounts they hold are given instead. The
+/// token account fields default to `None`, so transfers between associated
+/// token accounts can leave them out with `..Default::default()`.
+#[derive(Debug, Clone, Default)]
 pub struct PayTubeTransaction {
     pub mint: Option<Pubkey>,
     pub from: Pubkey,
     pub to: Pubkey,
     pub amount: u64,
+    /// The sender's token account to transfer from.
+    pub from_token_account: Option<Pubkey>,
+    /// The recipient's token account to transfer to.
+    pub to_token_account: Option<Pubkey>,
+    /// An id the sender gives the transfer. A channel processes each of a
+    /// sender's ids once; transfers without an id can be repeated.
+    pub id: Option<u64>,
 }
 
-impl From<&PayTubeTransaction> for SolanaInstruction {
-    fn from(value: &PayTubeTransaction) -> Self {
-        let PayTubeTransaction {
-            mint,
-            from,
-            to,
-            amount,
-        } = value;
-        if let Some(mint) = mint {
-            let source_pubkey = get_associated_token_address(from, mint);
-            let destination_pubkey = get_associated_token_address(to, mint);
-            return spl_token::instruction::transfer(
-                &spl_token::id(),
-                &source_pubkey,
-                &destination_pubkey,
-                from,
-                &[],
-                *amount,
-            )
-            .unwrap();
+impl PayTubeTransaction {
+    /// The source and destination token accounts of a transfer of
+    /// `mint_info`'s tokens.
+    pub fn token_accounts(&self, mint_info: &MintInfo) -> (Pubkey, Pubkey) {
+        (
+            self.from_token_account
+                .unwrap_or_else(|| mint_info.token_account(&self.from)),
+            self.to_token_account
+                .unwrap_or_else(|| mint_info.token_account(&self.to)),
+        )
+    }
+
+    /// Check the token accounts given for a token transfer, loading them
+    /// with `load_account`: each must hold the transfer's mint, and belong to
+    /// the sender or recipient respectively.
+    pub fn check_token_accounts(
+        &self,
+        mints: &HashMap<Pubkey, MintInfo>,
+        load_account: impl Fn(&Pubkey) -> Option<AccountSharedData>,
+    ) -> Result<(), InstructionError> {
+        let Some(mint_info) = self.mint.and_then(|mint| mints.get(&mint)) else {
+            return Ok(());
+        };
+        for (token_account, owner) in [
+            (self.from_token_account, &self.from),
+            (self.to_token_account, &self.to),
+        ] {
+            if let Some(token_account) = token_account {
+                let account =
+                    load_account(&token_account).ok_or(InstructionError::MissingAccount)?;
+                mint_info.check_token_account(owner, &account)?;
+            }
         }
-        system_instruction::transfer(from, to, *amount)
+        Ok(())
     }
-}
 
-impl From<&PayTubeTransaction> for SolanaTransaction {
-    fn from(value: &PayTubeTransaction) -> Self {
-        SolanaTransaction::new_with_payer(&[SolanaInstruction::from(value)], Some(&value.from))
+    /// The Solana instruction performing the transfer. Token transfers go
+    /// through their mint's program, from `mints`, with `transfer_checked`.
+    pub fn to_instruction(
+        &self,
+        mints: &HashMap<Pubkey, MintInfo>,
+    ) -> Result<SolanaInstruction, String> {
+        let Some(mint) = self.mint else {
+            return Ok(system_instruction::transfer(
+                &self.from,
+                &self.to,
+                self.amount,
+            ));
+        };
+        let mint_info = mints
+            .get(&mint)
+            .ok_or_else(|| format!("Unknown mint {}", mint))?;
+        let (source, destination) = self.token_accounts(mint_info);
+        mint_info.transfer_instruction(&source, &destination, &self.from, self.amount)
     }
-}
 
-impl From<&PayTubeTransaction> for SolanaSanitizedTransaction {
-    fn from(value: &PayTubeTransaction) -> Self {
+    /// If `token_account_payer` is set, the recipient's token account is
+    /// created first, paid for by it.
+    fn to_svm_transaction(
+        &self,
+        mints: &HashMap<Pubkey, MintInfo>,
+        token_account_payer: Option<&Pubkey>,
+    ) -> Result<SolanaSanitizedTransaction, String> {
+        let create_token_account = self
+            .mint
+            .and_then(|mint| mints.get(&mint))
+            .zip(token_account_payer)
+            .map(|(mint_info, payer)| mint_info.create_token_account_instruction(payer, &self.to));
+        let instructions: Vec<SolanaInstruction> = create_token_account
+            .into_iter()
+            .chain(std::iter::once(self.to_instruction(mints)?))
+            .collect();
         SolanaSanitizedTransaction::try_from_legacy_transaction(
-            SolanaTransaction::from(value),
+            SolanaTransaction::new_with_payer(&instructions, Some(&self.from)),
             &HashSet::new(),
         )
-        .unwrap()
+        .map_err(|e| e.to_string())
     }
 }
 
 /// Create a batch of Solana transactions, for the Solana SVM's transaction
 /// processor, from a batch of PayTube instructions.
+///
+/// `mints` are the mints of the batch's token transfers, and must include
+/// every one of them. Recipients whose associated token account doesn't
+/// exist, according to `token_account_exists`, have it created by
+/// `token_account_payer`.
 pub fn create_svm_transactions(
     paytube_transactions: &[PayTubeTransaction],
-) -> Vec<SolanaSanitizedTransaction> {
+    mints: &HashMap<Pubkey, MintInfo>,
+    token_account_payer: &Pubkey,
+    token_account_exists: impl Fn(&Pubkey) -> bool,
+) -> Result<Vec<SolanaSanitizedTransaction>, String> {
     paytube_transactions
         .iter()
-        .map(SolanaSanitizedTransaction::from)
+        .map(|transaction| {
+            let missing = transaction.to_token_account.is_none()
+                && transaction
+                    .mint
+                    .and_then(|mint| mints.get(&mint))
+                    .is_some_and(|mint_info| {
+                        !token_account_exists(&mint_info.token_account(&transaction.to))
+                    });
+            transaction.to_svm_transaction(mints, missing.then_some(token_account_payer))
+        })
         .collect()
 }
 
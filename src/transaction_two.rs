@@ -27,7 +27,8 @@ pub struct RpsTransaction {
     pub player_two: Option<Pubkey>,
     pub choice: Choice, // 0 = Rock, 1 = Paper, 2 = Scissors,
     pub program_id: Pubkey,
-    pub first_tx: bool
+    pub first_tx: bool,
+    pub game_id: u64, // Creator's game counter, part of the game PDA seeds
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
@@ -45,7 +46,8 @@ impl From<&RpsTransaction> for SolanaInstruction {
             player_two,
             choice,
             program_id,
-            first_tx 
+            first_tx,
+            game_id,
         } = value;
 
         
@@ -55,8 +57,7 @@ impl From<&RpsTransaction> for SolanaInstruction {
             let discriminator2 = [44, 62, 102, 247, 126, 208, 130, 215];
             let mut ix_data = discriminator2.to_vec();
             // let player_two_data = borsh::to_vec(&player_two.unwrap()).unwrap();
-            let game_id = borsh::to_vec(&2u64).unwrap();
-            // let game_id = &2u64.to_le_bytes()
+            let game_id = borsh::to_vec(game_id).unwrap();
             
             if let Some(player) = player_two {
                 let player_two_data = borsh::to_vec(&player).unwrap();
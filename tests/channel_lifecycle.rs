@@ -0,0 +1,300 @@
+mod setup;
+
+use {
+    paytube_svm::{
+        channel::{ChannelDeposit, NOT_A_PARTICIPANT},
+        storage::FileStorage,
+        transaction::PayTubeTransaction,
+        PayTubeChannel,
+    },
+    setup::{open_channel, system_account, TestValidatorContext},
+    solana_sdk::{
+        instruction::InstructionError, pubkey::Pubkey, signature::Keypair, signer::Signer,
+        transaction::TransactionError,
+    },
+};
+
+#[test]
+fn test_open_transfer_close() {
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(10_000_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+    let rpc_client = test_validator.get_rpc_client();
+
+    let mut paytube_channel = PayTubeChannel::new(
+        vec![
+            payer.insecure_clone(),
+            alice.insecure_clone(),
+            bob.insecure_clone(),
+        ],
+        test_validator.get_rpc_client(),
+    );
+
+    // Deposits have to be locked up before the channel opens.
+    let deposit = ChannelDeposit {
+        owner: alice_pubkey,
+        mint: None,
+        amount: 5_000_000,
+    };
+    assert!(paytube_channel.open(&[alice_pubkey], &[deposit]).is_err());
+    open_channel(
+        &mut paytube_channel,
+        &rpc_client,
+        &payer,
+        &[&alice, &bob],
+        None,
+        5_000_000,
+    );
+
+    let outcomes = paytube_channel
+        .process_paytube_transfers(&[
+            // Alice -> Bob 2_000_000
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 2_000_000,
+                mint: None,
+                ..Default::default()
+            },
+            // Alice -> Bob 4_000_000, more than Alice has left in the channel
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 4_000_000,
+                mint: None,
+                ..Default::default()
+            },
+            // Bob -> Alice 1_000_000
+            PayTubeTransaction {
+                from: bob_pubkey,
+                to: alice_pubkey,
+                amount: 1_000_000,
+                mint: None,
+                ..Default::default()
+            },
+            // Bob -> Carol 1_000_000, who isn't in the channel
+            PayTubeTransaction {
+                from: bob_pubkey,
+                to: Pubkey::new_unique(),
+                amount: 1_000_000,
+                mint: None,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+    assert_eq!(
+        outcomes,
+        vec![
+            Ok(()),
+            Err(TransactionError::InstructionError(
+                0,
+                InstructionError::InsufficientFunds
+            )),
+            Ok(()),
+            Err(TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(NOT_A_PARTICIPANT)
+            )),
+        ]
+    );
+    assert_eq!(
+        paytube_channel.balance(&alice_pubkey, None),
+        Some(4_000_000)
+    );
+    assert_eq!(paytube_channel.balance(&bob_pubkey, None), Some(6_000_000));
+
+    // A sender's transfer id is only processed once, in a batch or later.
+    let transfer = |from, to, id| PayTubeTransaction {
+        from,
+        to,
+        amount: 1_000_000,
+        mint: None,
+        id: Some(id),
+        ..Default::default()
+    };
+    let outcomes = paytube_channel
+        .process_paytube_transfers(&[
+            transfer(alice_pubkey, bob_pubkey, 1),
+            transfer(alice_pubkey, bob_pubkey, 1),
+        ])
+        .unwrap();
+    assert_eq!(
+        outcomes,
+        vec![Ok(()), Err(TransactionError::AlreadyProcessed)]
+    );
+    let outcomes = paytube_channel
+        .process_paytube_transfers(&[
+            transfer(alice_pubkey, bob_pubkey, 1),
+            transfer(bob_pubkey, alice_pubkey, 1),
+        ])
+        .unwrap();
+    assert_eq!(
+        outcomes,
+        vec![Err(TransactionError::AlreadyProcessed), Ok(())]
+    );
+    assert_eq!(
+        paytube_channel.balance(&alice_pubkey, None),
+        Some(4_000_000)
+    );
+
+    // Nothing is settled until the channel closes.
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 5_000_000);
+    paytube_channel.close().unwrap();
+
+    // Ledger:
+    // Alice:   10_000_000 - 2_000_000 + 1_000_000  = 9_000_000
+    // Bob:     10_000_000 + 2_000_000 - 1_000_000  = 11_000_000
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 9_000_000);
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 11_000_000);
+    assert!(paytube_channel.close().is_err());
+}
+
+#[test]
+fn test_transfers_spend_channel_balances() {
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(10_000_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+    let rpc_client = test_validator.get_rpc_client();
+
+    let mut paytube_channel = PayTubeChannel::new(
+        vec![
+            payer.insecure_clone(),
+            alice.insecure_clone(),
+            bob.insecure_clone(),
+        ],
+        test_validator.get_rpc_client(),
+    );
+
+    // Both deposit nearly everything they hold.
+    open_channel(
+        &mut paytube_channel,
+        &rpc_client,
+        &payer,
+        &[&alice, &bob],
+        None,
+        9_000_000,
+    );
+
+    // Transfers spend the deposits, and what was received in the channel,
+    // rather than what's left in the wallets.
+    let transfer = |from, to, amount| PayTubeTransaction {
+        from,
+        to,
+        amount,
+        mint: None,
+        ..Default::default()
+    };
+    let outcomes = paytube_channel
+        .process_paytube_transfers(&[transfer(alice_pubkey, bob_pubkey, 6_000_000)])
+        .unwrap();
+    assert_eq!(outcomes, vec![Ok(())]);
+    let outcomes = paytube_channel
+        .process_paytube_transfers(&[transfer(bob_pubkey, alice_pubkey, 12_000_000)])
+        .unwrap();
+    assert_eq!(outcomes, vec![Ok(())]);
+    assert_eq!(
+        paytube_channel.balance(&alice_pubkey, None),
+        Some(15_000_000)
+    );
+    assert_eq!(paytube_channel.balance(&bob_pubkey, None), Some(3_000_000));
+
+    // Ledger:
+    // Alice:   1_000_000 + 9_000_000 - 6_000_000 + 12_000_000  = 16_000_000
+    // Bob:     1_000_000 + 9_000_000 + 6_000_000 - 12_000_000  = 4_000_000
+    paytube_channel.close().unwrap();
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 16_000_000);
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 4_000_000);
+}
+
+#[test]
+fn test_restore_open_channel() {
+    let dir = std::env::temp_dir().join(format!("paytube-{}", Pubkey::new_unique()));
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(10_000_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+    let rpc_client = test_validator.get_rpc_client();
+
+    let channel = || {
+        PayTubeChannel::new(
+            vec![
+                payer.insecure_clone(),
+                alice.insecure_clone(),
+                bob.insecure_clone(),
+            ],
+            test_validator.get_rpc_client(),
+        )
+        .with_storage(Box::new(FileStorage::open(&dir).unwrap()))
+        .unwrap()
+    };
+    let alice_pays_bob = PayTubeTransaction {
+        from: alice_pubkey,
+        to: bob_pubkey,
+        amount: 2_000_000,
+        mint: None,
+        id: Some(1),
+        ..Default::default()
+    };
+
+    {
+        let mut paytube_channel = channel();
+        open_channel(
+            &mut paytube_channel,
+            &rpc_client,
+            &payer,
+            &[&alice, &bob],
+            None,
+            5_000_000,
+        );
+        let outcomes = paytube_channel
+            .process_paytube_transfers(&[alice_pays_bob.clone()])
+            .unwrap();
+        assert_eq!(outcomes, vec![Ok(())]);
+    }
+
+    // After a restart, the channel is still open with the same balances, and
+    // remembers the transfers it processed.
+    let mut paytube_channel = channel();
+    assert_eq!(
+        paytube_channel.balance(&alice_pubkey, None),
+        Some(3_000_000)
+    );
+    assert_eq!(paytube_channel.balance(&bob_pubkey, None), Some(7_000_000));
+    let outcomes = paytube_channel
+        .process_paytube_transfers(&[alice_pays_bob])
+        .unwrap();
+    assert_eq!(outcomes, vec![Err(TransactionError::AlreadyProcessed)]);
+
+    paytube_channel.close().unwrap();
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 8_000_000);
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 12_000_000);
+
+    // Once closed, it stays closed.
+    assert_eq!(channel().balance(&alice_pubkey, None), None);
+}
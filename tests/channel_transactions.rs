@@ -0,0 +1,131 @@
+mod setup;
+
+use {
+    paytube_svm::{channel_transaction::ChannelTransaction, PayTubeChannel},
+    setup::{system_account, TestValidatorContext},
+    solana_sdk::{
+        signature::Keypair, signer::Signer, system_instruction, transaction::TransactionError,
+    },
+};
+
+#[test]
+fn test_channel_transactions() {
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+    let carol = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+    let carol_pubkey = carol.pubkey();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(10_000_000)),
+        (carol_pubkey, system_account(1_000_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+    let payer_pubkey = payer.pubkey();
+
+    let rpc_client = test_validator.get_rpc_client();
+
+    // Alice and Bob both pay Carol, with Alice paying the fees.
+    let mut both_pay_carol = ChannelTransaction::new(
+        vec![
+            system_instruction::transfer(&alice_pubkey, &carol_pubkey, 1_000_000),
+            system_instruction::transfer(&bob_pubkey, &carol_pubkey, 2_000_000),
+        ],
+        alice_pubkey,
+    );
+    both_pay_carol.sign(&alice).unwrap();
+    both_pay_carol.sign(&bob).unwrap();
+    // Carol can only afford this with what was just received.
+    let mut carol_pays_alice = ChannelTransaction::new(
+        vec![system_instruction::transfer(
+            &carol_pubkey,
+            &alice_pubkey,
+            2_500_000,
+        )],
+        carol_pubkey,
+    );
+    carol_pays_alice.sign(&carol).unwrap();
+    // The channel doesn't sign for its authority, or anyone else, unless
+    // they signed the transaction.
+    let mut authority_pays_alice = ChannelTransaction::new(
+        vec![system_instruction::transfer(
+            &payer_pubkey,
+            &alice_pubkey,
+            1_000_000,
+        )],
+        alice_pubkey,
+    );
+    authority_pays_alice.sign(&alice).unwrap();
+    assert_eq!(authority_pays_alice.missing_signers(), vec![payer_pubkey]);
+
+    let paytube_channel = PayTubeChannel::new(vec![payer, alice, bob, carol], rpc_client);
+
+    let results = paytube_channel
+        .process_channel_transactions(&[both_pay_carol, carol_pays_alice, authority_pays_alice])
+        .unwrap();
+    assert_eq!(
+        results,
+        vec![Ok(()), Ok(()), Err(TransactionError::SignatureFailure)]
+    );
+
+    // Each settlement transaction is paid for by its fee payer.
+    let rpc_client = test_validator.get_rpc_client();
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 11_490_000);
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 8_000_000);
+    assert_eq!(rpc_client.get_balance(&carol_pubkey).unwrap(), 1_495_000);
+}
+
+#[test]
+fn test_participants_sign_channel_transaction_settlements() {
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(1_000_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+
+    let rpc_client = test_validator.get_rpc_client();
+
+    let mut alice_pays_bob = ChannelTransaction::new(
+        vec![system_instruction::transfer(
+            &alice_pubkey,
+            &bob_pubkey,
+            1_000_000,
+        )],
+        alice_pubkey,
+    );
+    alice_pays_bob.sign(&alice).unwrap();
+
+    // The channel only holds its authority's key.
+    let paytube_channel = PayTubeChannel::new(vec![payer], rpc_client);
+
+    // Nothing is settled without Alice's signature on the settlement.
+    assert!(paytube_channel
+        .process_channel_transactions(&[alice_pays_bob.clone()])
+        .is_err());
+
+    let (results, mut pending) = paytube_channel
+        .prepare_channel_transactions(&[alice_pays_bob])
+        .unwrap();
+    assert_eq!(results, vec![Ok(())]);
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].missing_signers(), vec![alice_pubkey]);
+
+    let signature = alice.sign_message(&pending[0].message_data());
+    pending[0].add_signature(&alice_pubkey, signature).unwrap();
+    paytube_channel.submit_settlement(&pending).unwrap();
+
+    let rpc_client = test_validator.get_rpc_client();
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 8_995_000);
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 2_000_000);
+}
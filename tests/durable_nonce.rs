@@ -0,0 +1,101 @@
+mod setup;
+
+use {
+    paytube_svm::{transaction::PayTubeTransaction, PayTubeChannel},
+    setup::{open_channel, system_account, TestValidatorContext},
+    solana_client::{nonce_utils, rpc_client::RpcClient},
+    solana_sdk::{
+        hash::Hash, nonce::State as NonceState, pubkey::Pubkey, signature::Keypair, signer::Signer,
+        system_instruction, transaction::Transaction,
+    },
+};
+
+fn stored_nonce(rpc_client: &RpcClient, nonce_account: &Pubkey) -> Hash {
+    let account = nonce_utils::get_account(rpc_client, nonce_account).unwrap();
+    nonce_utils::data_from_account(&account)
+        .unwrap()
+        .blockhash()
+}
+
+#[test]
+fn test_settle_on_durable_nonces() {
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(10_000_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+    let rpc_client = test_validator.get_rpc_client();
+
+    // Nonce accounts controlled by the channel's authority.
+    let nonce_accounts: Vec<Keypair> = (0..2).map(|_| Keypair::new()).collect();
+    let rent = rpc_client
+        .get_minimum_balance_for_rent_exemption(NonceState::size())
+        .unwrap();
+    for nonce_account in &nonce_accounts {
+        let transaction = Transaction::new_signed_with_payer(
+            &system_instruction::create_nonce_account(
+                &payer.pubkey(),
+                &nonce_account.pubkey(),
+                &payer.pubkey(),
+                rent,
+            ),
+            Some(&payer.pubkey()),
+            &[&payer, nonce_account],
+            rpc_client.get_latest_blockhash().unwrap(),
+        );
+        rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .unwrap();
+    }
+
+    let mut paytube_channel = PayTubeChannel::new(
+        vec![
+            payer.insecure_clone(),
+            alice.insecure_clone(),
+            bob.insecure_clone(),
+        ],
+        test_validator.get_rpc_client(),
+    )
+    .with_nonce_accounts(nonce_accounts.iter().map(Keypair::pubkey).collect());
+
+    open_channel(
+        &mut paytube_channel,
+        &rpc_client,
+        &payer,
+        &[&alice, &bob],
+        None,
+        5_000_000,
+    );
+
+    paytube_channel
+        .process_paytube_transfers(&[PayTubeTransaction {
+            from: alice_pubkey,
+            to: bob_pubkey,
+            amount: 2_000_000,
+            mint: None,
+            ..Default::default()
+        }])
+        .unwrap();
+
+    // The settlement is built on the stored nonce rather than a blockhash.
+    let pending = paytube_channel.prepare_close().unwrap();
+    assert_eq!(pending.len(), 1);
+    let nonce_account = nonce_accounts[0].pubkey();
+    let nonce = stored_nonce(&rpc_client, &nonce_account);
+    assert_eq!(pending[0].nonce_account(), Some(nonce_account));
+    assert_eq!(pending[0].recent_blockhash(), nonce);
+
+    paytube_channel.submit_settlement(&pending).unwrap();
+
+    // Landing the settlement advanced the nonce.
+    assert_ne!(stored_nonce(&rpc_client, &nonce_account), nonce);
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 8_000_000);
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 12_000_000);
+}
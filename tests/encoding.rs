@@ -0,0 +1,150 @@
+use {
+    borsh::BorshSerialize,
+    paytube_svm::game_traits::{
+        ArchivedGame, GameConfig, GameEncoding, GameMove, GameState, Payout, PlayerStake,
+        WageringConfig, WageringState, WageringType, GAME_ENCODING_VERSION,
+    },
+    solana_sdk::{pubkey::Pubkey, signature::Signature},
+    std::collections::HashMap,
+};
+
+fn wagered_game_state() -> GameState {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let game_instance_id = Pubkey::new_unique();
+
+    let player_stakes = [alice, bob]
+        .iter()
+        .map(|player_id| {
+            (
+                *player_id,
+                PlayerStake {
+                    player_id: *player_id,
+                    amount: 1_000,
+                    token_mint: None,
+                    committed: true,
+                    committed_at: 1,
+                },
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    GameState {
+        game_instance_id,
+        game_type_id: "rock_paper_scissors@1".to_string(),
+        players: vec![alice, bob],
+        current_player: Some(bob),
+        state_data: vec![1, 2, 3],
+        move_history: vec![GameMove {
+            game_instance_id,
+            player_id: alice,
+            move_id: 0,
+            move_data: vec![0],
+            signature: Signature::from([9u8; 64]),
+            timestamp: 2,
+        }],
+        is_finished: false,
+        winner: None,
+        created_at: 1,
+        last_updated: 2,
+        wagering_state: Some(WageringState {
+            config: WageringConfig {
+                wagering_type: WageringType::SplitPot {
+                    winner_percentage: 70,
+                    runner_up_percentage: 30,
+                },
+                min_stake: 100,
+                max_stake: Some(10_000),
+                equal_stakes: true,
+            },
+            player_stakes,
+            total_pot: 2_000,
+            stakes_committed: true,
+            payouts: None,
+        }),
+        last_activity: 2,
+    }
+}
+
+#[test]
+fn test_borsh_round_trip() {
+    let game_state = wagered_game_state();
+    let bytes = game_state.to_bytes().unwrap();
+    assert_eq!(bytes[0], GAME_ENCODING_VERSION);
+    assert_eq!(GameState::from_bytes(&bytes).unwrap(), game_state);
+
+    let game_move = &game_state.move_history[0];
+    assert_eq!(
+        GameMove::from_bytes(&game_move.to_bytes().unwrap()).unwrap(),
+        *game_move
+    );
+}
+
+#[test]
+fn test_json_round_trip() {
+    let game_state = wagered_game_state();
+    let json = game_state.to_json().unwrap();
+    assert_eq!(GameState::from_json(&json).unwrap(), game_state);
+}
+
+#[test]
+fn test_unknown_version_rejected() {
+    let mut bytes = wagered_game_state().to_bytes().unwrap();
+    bytes[0] = GAME_ENCODING_VERSION + 1;
+    assert!(GameState::from_bytes(&bytes).is_err());
+
+    let json = format!(r#"{{"version":{},"data":{{}}}}"#, GAME_ENCODING_VERSION + 1);
+    assert!(GameState::from_json(&json).is_err());
+}
+
+#[test]
+fn test_older_versions_decoded() {
+    // Version 1 game configs had no game id.
+    let mut bytes = vec![1];
+    "rock_paper_scissors".serialize(&mut bytes).unwrap();
+    2u8.serialize(&mut bytes).unwrap();
+    2u8.serialize(&mut bytes).unwrap();
+    300u64.serialize(&mut bytes).unwrap();
+    0u64.serialize(&mut bytes).unwrap();
+    HashMap::<String, String>::new()
+        .serialize(&mut bytes)
+        .unwrap();
+    None::<WageringConfig>.serialize(&mut bytes).unwrap();
+
+    let config = GameConfig::from_bytes(&bytes).unwrap();
+    assert_eq!(config.game_type_id, "rock_paper_scissors");
+    assert_eq!(config.timeout_seconds, 300);
+    assert_eq!(config.game_id, None);
+
+    // Version 2 archived games had no settlement signatures.
+    let final_state = wagered_game_state();
+    let payouts = vec![Payout {
+        player_id: final_state.players[0],
+        amount: 2_000,
+        rank: 1,
+        percentage: 100,
+    }];
+    let mut bytes = vec![2];
+    final_state.serialize(&mut bytes).unwrap();
+    payouts.serialize(&mut bytes).unwrap();
+    7u64.serialize(&mut bytes).unwrap();
+
+    let archived = ArchivedGame {
+        final_state,
+        payouts,
+        archived_at: 7,
+        settlement_signatures: Vec::new(),
+        payout_signatures: Vec::new(),
+    };
+    assert_eq!(ArchivedGame::from_bytes(&bytes).unwrap(), archived);
+
+    let mut json: serde_json::Value = serde_json::from_str(&archived.to_json().unwrap()).unwrap();
+    json["version"] = 2.into();
+    let data = json["data"].as_object_mut().unwrap();
+    data.remove("settlement_signatures");
+    data.remove("payout_signatures");
+    assert_eq!(
+        ArchivedGame::from_json(&json.to_string()).unwrap(),
+        archived
+    );
+}
@@ -0,0 +1,118 @@
+mod setup;
+
+use {
+    paytube_svm::{
+        events::ChannelEvent,
+        game_channel::{PlayChannel, SettlementConfig},
+        game_traits::{GameActionResult, GameEngineRegistry},
+        games::{rock_paper_scissors::RPSChoice, RPSEngine},
+    },
+    setup::rps_move,
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::pubkey::Pubkey,
+};
+
+#[test]
+fn test_game_lifecycle_events() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let mallory = Pubkey::new_unique();
+
+    let mut registry = GameEngineRegistry::new();
+    registry
+        .register_engine(Box::new(RPSEngine::new()))
+        .unwrap();
+    // Playing a game never touches the base chain.
+    let mut channel = PlayChannel::with_registry(
+        vec![],
+        RpcClient::new("http://localhost:8899".to_string()),
+        registry,
+    );
+    let events = channel.subscribe();
+
+    let game = channel
+        .create_game(&"rock_paper_scissors".to_string(), &[alice, bob], 0, None)
+        .unwrap();
+
+    let rejected = rps_move(game, mallory, RPSChoice::Paper);
+    assert!(matches!(
+        channel.process_game_move(&rejected).unwrap(),
+        GameActionResult::Failure(_)
+    ));
+    let first = rps_move(game, alice, RPSChoice::Rock);
+    channel.process_game_move(&first).unwrap();
+    let second = rps_move(game, bob, RPSChoice::Scissors);
+    channel.process_game_move(&second).unwrap();
+
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        vec![
+            ChannelEvent::GameCreated {
+                game_instance_id: game,
+                game_type_id: "rock_paper_scissors@1".to_string(),
+                players: vec![alice, bob],
+            },
+            ChannelEvent::MoveRejected {
+                game_move: rejected,
+                reason: "Player not in game".to_string(),
+            },
+            ChannelEvent::MoveApplied { game_move: first },
+            ChannelEvent::MoveApplied { game_move: second },
+            ChannelEvent::GameEnded {
+                game_instance_id: game,
+                winner: Some(alice),
+            },
+        ]
+    );
+
+    // Dropped subscribers don't stop the channel from emitting.
+    drop(events);
+    channel
+        .create_game(&"rock_paper_scissors".to_string(), &[alice, bob], 0, None)
+        .unwrap();
+}
+
+#[test]
+fn test_failed_auto_settlement_keeps_move() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    let mut registry = GameEngineRegistry::new();
+    registry
+        .register_engine(Box::new(RPSEngine::new()))
+        .unwrap();
+    // Without an escrow there is nothing to settle from.
+    let mut channel = PlayChannel::with_registry(
+        vec![],
+        RpcClient::new("http://localhost:8899".to_string()),
+        registry,
+    )
+    .with_auto_settlement(SettlementConfig::default());
+    let events = channel.subscribe();
+
+    let game = channel
+        .create_game(&"rock_paper_scissors".to_string(), &[alice, bob], 100, None)
+        .unwrap();
+    channel.commit_stake(&game, alice, 100).unwrap();
+    channel.commit_stake(&game, bob, 100).unwrap();
+
+    channel
+        .process_game_move(&rps_move(game, alice, RPSChoice::Rock))
+        .unwrap();
+    assert_eq!(
+        channel
+            .process_game_move(&rps_move(game, bob, RPSChoice::Scissors))
+            .unwrap(),
+        GameActionResult::GameEnded {
+            winner: Some(alice)
+        }
+    );
+
+    let archived = channel.game_manager.get_archived_game(&game).unwrap();
+    assert_eq!(archived.final_state.move_history.len(), 2);
+    assert!(events.try_iter().any(|event| event
+        == ChannelEvent::SettlementFailed {
+            game_instance_id: game,
+            reason: "Channel has no escrow to settle from".to_string(),
+        }));
+}
@@ -0,0 +1,195 @@
+mod setup;
+
+use {
+    paytube_svm::{
+        fees::{ChannelFees, FeePolicy},
+        transaction::PayTubeTransaction,
+        PayTubeChannel,
+    },
+    setup::{open_channel, system_account, TestValidatorContext},
+    solana_sdk::{
+        instruction::InstructionError, signature::Keypair, signer::Signer,
+        transaction::TransactionError,
+    },
+};
+
+#[test]
+fn test_channel_fees() {
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+    let operator = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+    let operator_pubkey = operator.pubkey();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(10_000_000)),
+        (operator_pubkey, system_account(10_000_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+    let rpc_client = test_validator.get_rpc_client();
+
+    // 10% of every transfer goes to the operator, and the participants pay
+    // for settling the channel.
+    let fees = ChannelFees::new(
+        FeePolicy::Percentage {
+            basis_points: 1_000,
+        },
+        operator_pubkey,
+    )
+    .with_shared_settlement_costs();
+    let mut paytube_channel = PayTubeChannel::new(
+        vec![
+            payer.insecure_clone(),
+            alice.insecure_clone(),
+            bob.insecure_clone(),
+        ],
+        test_validator.get_rpc_client(),
+    )
+    .with_fees(fees);
+
+    open_channel(
+        &mut paytube_channel,
+        &rpc_client,
+        &payer,
+        &[&alice, &bob],
+        None,
+        5_000_000,
+    );
+
+    let outcomes = paytube_channel
+        .process_paytube_transfers(&[
+            // Alice -> Bob 2_000_000, plus a 200_000 fee
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 2_000_000,
+                mint: None,
+                ..Default::default()
+            },
+            // Bob -> Alice 3_000_000, plus a 300_000 fee
+            PayTubeTransaction {
+                from: bob_pubkey,
+                to: alice_pubkey,
+                amount: 3_000_000,
+                mint: None,
+                ..Default::default()
+            },
+            // Alice -> Bob 5_500_000: Alice has it, but not the fee on top
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 5_500_000,
+                mint: None,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+    assert_eq!(
+        outcomes,
+        vec![
+            Ok(()),
+            Ok(()),
+            Err(TransactionError::InstructionError(
+                0,
+                InstructionError::InsufficientFunds
+            )),
+        ]
+    );
+    assert_eq!(
+        paytube_channel.balance(&alice_pubkey, None),
+        Some(5_800_000)
+    );
+    assert_eq!(paytube_channel.balance(&bob_pubkey, None), Some(3_700_000));
+    assert_eq!(
+        paytube_channel.balance(&operator_pubkey, None),
+        Some(500_000)
+    );
+
+    paytube_channel.close().unwrap();
+
+    // The single settlement signature is split between Alice and Bob.
+    // Alice:   5_000_000 + 5_800_000 - 2_500  = 10_797_500
+    // Bob:     5_000_000 + 3_700_000 - 2_500  = 8_697_500
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 10_797_500);
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 8_697_500);
+    assert_eq!(
+        rpc_client.get_balance(&operator_pubkey).unwrap(),
+        10_500_000
+    );
+}
+
+#[test]
+fn test_fees_outside_open_channel() {
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+    let operator = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+    let operator_pubkey = operator.pubkey();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(10_000_000)),
+        (operator_pubkey, system_account(10_000_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+    let rpc_client = test_validator.get_rpc_client();
+
+    // 10% of every transfer goes to the operator.
+    let fees = ChannelFees::new(
+        FeePolicy::Percentage {
+            basis_points: 1_000,
+        },
+        operator_pubkey,
+    );
+    let paytube_channel = PayTubeChannel::new(
+        vec![payer, alice.insecure_clone(), bob.insecure_clone()],
+        test_validator.get_rpc_client(),
+    )
+    .with_fees(fees);
+
+    let outcomes = paytube_channel
+        .process_paytube_transfers(&[
+            // Alice -> Bob 6_000_000, plus a 600_000 fee
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 6_000_000,
+                mint: None,
+                ..Default::default()
+            },
+            // Alice -> Bob 3_500_000: Alice has it, but not the fee on top
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 3_500_000,
+                mint: None,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+    assert_eq!(
+        outcomes,
+        vec![
+            Ok(()),
+            Err(TransactionError::InstructionError(
+                0,
+                InstructionError::InsufficientFunds
+            )),
+        ]
+    );
+
+    // The authority pays for settling.
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 3_400_000);
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 16_000_000);
+    assert_eq!(
+        rpc_client.get_balance(&operator_pubkey).unwrap(),
+        10_600_000
+    );
+}
@@ -0,0 +1,162 @@
+mod setup;
+
+use {
+    paytube_svm::{
+        clock::{ChainClock, Clock, ManualClock},
+        game_traits::{
+            derive_game_instance_id, GameConfig, GameEngineRegistry, GameFilter, GameStateManager,
+            GameStatus, RetentionPolicy,
+        },
+        games::{
+            rock_paper_scissors::{RPSChoice, RPS_PROGRAM_ID},
+            RPSEngine,
+        },
+    },
+    setup::{rps_config, rps_move, rps_wager_config},
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::pubkey::Pubkey,
+    std::sync::Arc,
+};
+
+#[test]
+fn test_list_archive_and_evict_games() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let carol = Pubkey::new_unique();
+    let clock = Arc::new(ManualClock::new(1_000));
+
+    let mut registry = GameEngineRegistry::new();
+    registry
+        .register_engine(Box::new(RPSEngine::new()))
+        .unwrap();
+    let mut manager = GameStateManager::with_retention_policy(
+        registry,
+        RetentionPolicy {
+            archive_on_finish: true,
+            max_history: Some(10),
+            idle_timeout_seconds: Some(60),
+        },
+    )
+    .with_clock(clock.clone());
+
+    let finished = manager.create_game(&rps_config(), &[alice, bob]).unwrap();
+    let idle = manager.create_game(&rps_config(), &[alice, carol]).unwrap();
+
+    let by_bob = GameFilter {
+        player: Some(bob),
+        ..Default::default()
+    };
+    assert_eq!(manager.list_games(&by_bob).len(), 1);
+    let by_type = GameFilter {
+        game_type_id: Some("rock_paper_scissors".to_string()),
+        status: Some(GameStatus::InProgress),
+        ..Default::default()
+    };
+    assert_eq!(manager.list_games(&by_type).len(), 2);
+
+    manager
+        .process_move(&rps_move(finished, alice, RPSChoice::Rock))
+        .unwrap();
+    manager
+        .process_move(&rps_move(finished, bob, RPSChoice::Scissors))
+        .unwrap();
+
+    // Finished games are archived straight away.
+    assert!(manager.get_game_state(&finished).is_none());
+    let archived = manager.get_archived_game(&finished).unwrap();
+    assert_eq!(archived.final_state.winner, Some(alice));
+    assert_eq!(manager.list_archived_games(&by_bob).len(), 1);
+    assert_eq!(manager.get_active_games().len(), 1);
+
+    let staked = manager
+        .create_game(&rps_wager_config(100), &[bob, carol])
+        .unwrap();
+    manager.commit_stake(&staked, bob, 100, None).unwrap();
+
+    // The engine follows the manager's clock, and the untouched game is
+    // evicted once idle for longer than the timeout.
+    assert_eq!(manager.get_game_state(&idle).unwrap().created_at, 1_000);
+    clock.advance(30);
+    assert!(manager.apply_retention_policy().unwrap().is_empty());
+    clock.advance(31);
+    assert_eq!(manager.apply_retention_policy().unwrap(), vec![idle]);
+
+    // Evicting or removing the staked game would orphan Bob's stake.
+    let active = manager.get_active_games();
+    assert_eq!(active.len(), 1);
+    assert_eq!(active[0].game_instance_id, staked);
+    assert!(manager.remove_game(&staked).is_err());
+}
+
+#[test]
+fn test_chain_clock_fails_without_chain() {
+    // Nothing listens here, and the local time isn't a stand-in.
+    let clock = ChainClock::new(RpcClient::new("http://127.0.0.1:1".to_string()));
+    assert!(clock.unix_timestamp().is_err());
+    assert!(clock.slot().is_err());
+}
+
+#[test]
+fn test_keep_unsettled_games_in_history() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    let mut registry = GameEngineRegistry::new();
+    registry
+        .register_engine(Box::new(RPSEngine::new()))
+        .unwrap();
+    let mut manager = GameStateManager::with_retention_policy(
+        registry,
+        RetentionPolicy {
+            archive_on_finish: true,
+            max_history: Some(1),
+            idle_timeout_seconds: None,
+        },
+    );
+    let mut play = |config: GameConfig| {
+        let game = manager.create_game(&config, &[alice, bob]).unwrap();
+        if config.wagering_config.is_some() {
+            manager.commit_stake(&game, alice, 100, None).unwrap();
+            manager.commit_stake(&game, bob, 100, None).unwrap();
+        }
+        manager
+            .process_move(&rps_move(game, alice, RPSChoice::Rock))
+            .unwrap();
+        manager
+            .process_move(&rps_move(game, bob, RPSChoice::Scissors))
+            .unwrap();
+        game
+    };
+    let wagered = play(rps_wager_config(100));
+    let unwagered = play(rps_config());
+
+    // The wagered game's payout is still owed, so it's kept over the newer
+    // game when the history is trimmed.
+    assert!(!manager.get_archived_game(&wagered).unwrap().is_settled());
+    assert!(manager.get_archived_game(&unwagered).is_none());
+}
+
+#[test]
+fn test_deterministic_game_ids() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    let mut registry = GameEngineRegistry::new();
+    registry
+        .register_engine(Box::new(RPSEngine::new()))
+        .unwrap();
+    let mut manager = GameStateManager::new(registry);
+
+    // Ids are the creator's game PDAs, numbered by a per-creator counter,
+    // which games that fail to be created don't move on.
+    let first = manager.create_game(&rps_config(), &[alice, bob]).unwrap();
+    assert!(manager.create_game(&rps_config(), &[alice]).is_err());
+    let second = manager.create_game(&rps_config(), &[alice, bob]).unwrap();
+    assert_eq!(first, derive_game_instance_id(&RPS_PROGRAM_ID, &alice, 0));
+    assert_eq!(second, derive_game_instance_id(&RPS_PROGRAM_ID, &alice, 1));
+
+    // Explicit game ids that are already taken are rejected.
+    let mut config = rps_config();
+    config.game_id = Some(1);
+    assert!(manager.create_game(&config, &[alice, bob]).is_err());
+}
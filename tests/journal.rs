@@ -0,0 +1,125 @@
+mod setup;
+
+use {
+    paytube_svm::{
+        journal::{SettlementJournal, SettlementStatus},
+        transaction::PayTubeTransaction,
+        PayTubeChannel,
+    },
+    setup::{open_channel, system_account, TestValidatorContext},
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        pubkey::Pubkey, signature::Keypair, signer::Signer, system_instruction,
+        transaction::Transaction,
+    },
+};
+
+/// Move all of `from`'s lamports, less the transfer's fee, to `to`.
+fn sweep(rpc_client: &RpcClient, from: &Keypair, to: &Pubkey) {
+    let lamports = rpc_client.get_balance(&from.pubkey()).unwrap() - 5_000;
+    let transaction = Transaction::new_signed_with_payer(
+        &[system_instruction::transfer(&from.pubkey(), to, lamports)],
+        Some(&from.pubkey()),
+        &[from],
+        rpc_client.get_latest_blockhash().unwrap(),
+    );
+    rpc_client
+        .send_and_confirm_transaction(&transaction)
+        .unwrap();
+}
+
+#[test]
+fn test_resume_settlement() {
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(10_000_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+    let rpc_client = test_validator.get_rpc_client();
+
+    let journal_path =
+        std::env::temp_dir().join(format!("paytube-journal-{}.json", Pubkey::new_unique()));
+    let keys = || {
+        vec![
+            payer.insecure_clone(),
+            alice.insecure_clone(),
+            bob.insecure_clone(),
+        ]
+    };
+    let mut paytube_channel =
+        PayTubeChannel::new(keys(), test_validator.get_rpc_client()).with_journal(&journal_path);
+
+    open_channel(
+        &mut paytube_channel,
+        &rpc_client,
+        &payer,
+        &[&alice, &bob],
+        None,
+        5_000_000,
+    );
+
+    paytube_channel
+        .process_paytube_transfers(&[PayTubeTransaction {
+            from: alice_pubkey,
+            to: bob_pubkey,
+            amount: 2_000_000,
+            mint: None,
+            ..Default::default()
+        }])
+        .unwrap();
+
+    // The channel can't pay the settlement's fees, so it fails on the base
+    // chain partway through.
+    let reserve = Keypair::new();
+    sweep(&rpc_client, &payer, &reserve.pubkey());
+    assert!(paytube_channel.close().is_err());
+    let unfinished = paytube_channel.unfinished_settlement().unwrap().unwrap();
+    assert_eq!(unfinished[0].status, SettlementStatus::Submitted);
+    assert!(!SettlementJournal::load(&journal_path)
+        .unwrap()
+        .is_complete());
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 5_000_000);
+    drop(paytube_channel);
+
+    // Once it can pay again, a restarted channel picks the settlement up
+    // from its journal.
+    sweep(&rpc_client, &reserve, &payer.pubkey());
+    let paytube_channel =
+        PayTubeChannel::new(keys(), test_validator.get_rpc_client()).with_journal(&journal_path);
+    let signatures = paytube_channel.resume_settlement().unwrap();
+    assert!(paytube_channel.unfinished_settlement().unwrap().is_none());
+
+    // Every transaction is recorded as confirmed.
+    let mut journal = SettlementJournal::load(&journal_path).unwrap();
+    assert!(journal.is_complete());
+    assert_eq!(journal.confirmed_signatures(), signatures);
+
+    // Pretend the channel crashed before hearing back from the base chain.
+    for index in 0..journal.entries().len() {
+        journal
+            .set_status(index, SettlementStatus::Submitted)
+            .unwrap();
+    }
+    drop(paytube_channel);
+
+    // A restarted channel finds the transactions already landed, and doesn't
+    // pay out twice.
+    let restarted =
+        PayTubeChannel::new(keys(), test_validator.get_rpc_client()).with_journal(&journal_path);
+    assert_eq!(restarted.resume_settlement().unwrap(), signatures);
+    assert!(SettlementJournal::load(&journal_path)
+        .unwrap()
+        .is_complete());
+
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 8_000_000);
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 12_000_000);
+
+    std::fs::remove_file(&journal_path).unwrap();
+}
@@ -0,0 +1,112 @@
+use {
+    borsh::BorshSerialize,
+    paytube_svm::{
+        game_traits::{
+            GameActionResult, GameConfig, GameEngine, GameEngineRegistry, GameInstanceId, GameMove,
+            GameStateManager, PlayerId,
+        },
+        games::{rock_paper_scissors::RPSChoice, MatchEngine, RPSEngine},
+    },
+    solana_sdk::{pubkey::Pubkey, signature::Signature},
+    std::collections::HashMap,
+};
+
+fn best_of_three() -> (GameStateManager, GameConfig) {
+    let engine = MatchEngine::new(Box::new(RPSEngine::new()), 3, 2).unwrap();
+    let config = GameConfig {
+        game_type_id: engine.game_type_id(),
+        max_players: engine.max_players(),
+        min_players: engine.min_players(),
+        timeout_seconds: 300,
+        stake_amount: 0,
+        custom_config: HashMap::new(),
+        game_id: None,
+        wagering_config: None,
+    };
+
+    let mut registry = GameEngineRegistry::new();
+    registry.register_engine(Box::new(engine)).unwrap();
+    (GameStateManager::new(registry), config)
+}
+
+fn play(
+    manager: &mut GameStateManager,
+    game_instance_id: GameInstanceId,
+    player_id: PlayerId,
+    choice: RPSChoice,
+) -> GameActionResult {
+    let move_id = manager
+        .get_game_state(&game_instance_id)
+        .unwrap()
+        .move_history
+        .len() as u64;
+    manager
+        .process_move(&GameMove {
+            game_instance_id,
+            player_id,
+            move_id,
+            move_data: choice.try_to_vec().unwrap(),
+            signature: Signature::default(),
+            timestamp: 0,
+        })
+        .unwrap()
+}
+
+#[test]
+fn test_best_of_three_replays_draws() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    let (mut manager, config) = best_of_three();
+    let game = manager.create_game(&config, &[alice, bob]).unwrap();
+
+    // Round 1: draw, replayed.
+    play(&mut manager, game, alice, RPSChoice::Rock);
+    play(&mut manager, game, bob, RPSChoice::Rock);
+    // Round 2: Alice wins.
+    play(&mut manager, game, alice, RPSChoice::Paper);
+    play(&mut manager, game, bob, RPSChoice::Rock);
+    assert!(!manager.get_game_state(&game).unwrap().is_finished);
+    // Round 3: Alice wins the match.
+    play(&mut manager, game, alice, RPSChoice::Scissors);
+    let result = play(&mut manager, game, bob, RPSChoice::Paper);
+
+    assert!(matches!(
+        result,
+        GameActionResult::GameEnded { winner: Some(winner) } if winner == alice
+    ));
+    let state = manager.get_game_state(&game).unwrap();
+    assert_eq!(state.move_history.len(), 6);
+    assert_eq!(state.winner, Some(alice));
+}
+
+#[test]
+fn test_best_of_three_draw_cap() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    let (mut manager, config) = best_of_three();
+    let game = manager.create_game(&config, &[alice, bob]).unwrap();
+
+    // Three draws exceed the cap of two replays, with no round wins.
+    let mut result = GameActionResult::Success;
+    for _ in 0..3 {
+        play(&mut manager, game, alice, RPSChoice::Rock);
+        result = play(&mut manager, game, bob, RPSChoice::Rock);
+    }
+
+    assert!(matches!(
+        result,
+        GameActionResult::GameEnded { winner: None }
+    ));
+    assert!(manager.get_game_state(&game).unwrap().is_finished);
+}
+
+#[test]
+fn test_best_of_must_be_odd() {
+    for best_of in [0, 2, 4] {
+        assert!(MatchEngine::new(Box::new(RPSEngine::new()), best_of, 2).is_err());
+    }
+    let engine = MatchEngine::new(Box::new(RPSEngine::new()), 5, 2).unwrap();
+    assert_eq!(engine.wins_required(), 3);
+}
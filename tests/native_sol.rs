@@ -8,6 +8,7 @@ use {
         pubkey::Pubkey,
         signature::Keypair,
         signer::Signer,
+        transaction::TransactionError,
     },
     std::fs,
 };
@@ -60,36 +61,42 @@ fn test_native_sol() {
 
     let paytube_channel = PayTubeChannel::new(vec![payer, alice, bob, will], rpc_client);
 
-    paytube_channel.process_paytube_transfers(&[
-        // Alice -> Bob 2_000_000
-        PayTubeTransaction {
-            from: alice_pubkey,
-            to: bob_pubkey,
-            amount: 2_000_000,
-            mint: None,
-        },
-        // Bob -> Will 5_000_000
-        PayTubeTransaction {
-            from: bob_pubkey,
-            to: will_pubkey,
-            amount: 5_000_000,
-            mint: None,
-        },
-        // Alice -> Bob 2_000_000
-        PayTubeTransaction {
-            from: alice_pubkey,
-            to: bob_pubkey,
-            amount: 2_000_000,
-            mint: None,
-        },
-        // Will -> Alice 1_000_000
-        PayTubeTransaction {
-            from: will_pubkey,
-            to: alice_pubkey,
-            amount: 1_000_000,
-            mint: None,
-        },
-    ]);
+    paytube_channel
+        .process_paytube_transfers(&[
+            // Alice -> Bob 2_000_000
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 2_000_000,
+                mint: None,
+                ..Default::default()
+            },
+            // Bob -> Will 5_000_000
+            PayTubeTransaction {
+                from: bob_pubkey,
+                to: will_pubkey,
+                amount: 5_000_000,
+                mint: None,
+                ..Default::default()
+            },
+            // Alice -> Bob 2_000_000
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 2_000_000,
+                mint: None,
+                ..Default::default()
+            },
+            // Will -> Alice 1_000_000
+            PayTubeTransaction {
+                from: will_pubkey,
+                to: alice_pubkey,
+                amount: 1_000_000,
+                mint: None,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
 
     // Ledger:
     // Alice:   10_000_000 - 2_000_000 - 2_000_000 + 1_000_000  = 7_000_000
@@ -116,3 +123,60 @@ pub fn program_account(program_id: Pubkey, program_path: &str) -> (Pubkey, Accou
 
     (program_id, AccountSharedData::from(account))
 }
+
+#[test]
+fn test_unfunded_fee_payers_rejected() {
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+    let carol = Keypair::new();
+    let dave = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+    let carol_pubkey = carol.pubkey();
+    let dave_pubkey = dave.pubkey();
+
+    // Carol has no account, and Dave can't pay the fee of a transfer.
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(10_000_000)),
+        (dave_pubkey, system_account(1_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+
+    let paytube_channel = PayTubeChannel::new(
+        vec![payer, alice, bob, carol, dave],
+        test_validator.get_rpc_client(),
+    );
+
+    // Senders pay the fees of their transfers in the channel.
+    let transfer = |from, amount| PayTubeTransaction {
+        from,
+        to: bob_pubkey,
+        amount,
+        mint: None,
+        ..Default::default()
+    };
+    let outcomes = paytube_channel
+        .process_paytube_transfers(&[
+            transfer(carol_pubkey, 1_000_000),
+            transfer(dave_pubkey, 500),
+            transfer(alice_pubkey, 1_000_000),
+        ])
+        .unwrap();
+    assert_eq!(
+        outcomes,
+        vec![
+            Err(TransactionError::AccountNotFound),
+            Err(TransactionError::InsufficientFundsForFee),
+            Ok(()),
+        ]
+    );
+
+    // Only Alice's transfer is settled.
+    let rpc_client = test_validator.get_rpc_client();
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 9_000_000);
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 11_000_000);
+    assert_eq!(rpc_client.get_balance(&dave_pubkey).unwrap(), 1_000);
+}
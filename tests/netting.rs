@@ -0,0 +1,83 @@
+mod setup;
+
+use {
+    paytube_svm::{transaction::PayTubeTransaction, NettingMode, PayTubeChannel},
+    setup::{system_account, TestValidatorContext},
+    solana_sdk::{signature::Keypair, signer::Signer},
+};
+
+#[test]
+fn test_global_netting() {
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+    let will = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+    let will_pubkey = will.pubkey();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(10_000_000)),
+        (will_pubkey, system_account(10_000_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+    let rpc_client = test_validator.get_rpc_client();
+
+    let paytube_channel = PayTubeChannel::new(
+        vec![payer, alice, bob, will],
+        test_validator.get_rpc_client(),
+    )
+    .with_netting(NettingMode::Global);
+
+    paytube_channel
+        .process_paytube_transfers(&[
+            // Alice -> Bob 3_000_000
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 3_000_000,
+                mint: None,
+                ..Default::default()
+            },
+            // Bob -> Will 3_000_000
+            PayTubeTransaction {
+                from: bob_pubkey,
+                to: will_pubkey,
+                amount: 3_000_000,
+                mint: None,
+                ..Default::default()
+            },
+            // Will -> Alice 1_000_000
+            PayTubeTransaction {
+                from: will_pubkey,
+                to: alice_pubkey,
+                amount: 1_000_000,
+                mint: None,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+    // Ledger:
+    // Alice:   10_000_000 - 3_000_000 + 1_000_000  = 8_000_000
+    // Bob:     10_000_000 + 3_000_000 - 3_000_000  = 10_000_000
+    // Will:    10_000_000 + 3_000_000 - 1_000_000  = 12_000_000
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 8_000_000);
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 10_000_000);
+    assert_eq!(rpc_client.get_balance(&will_pubkey).unwrap(), 12_000_000);
+
+    // Alice paid Will directly, so Bob never appeared on the base chain.
+    assert_eq!(
+        rpc_client
+            .get_signatures_for_address(&alice_pubkey)
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(rpc_client
+        .get_signatures_for_address(&bob_pubkey)
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,107 @@
+mod setup;
+
+use {
+    paytube_svm::{signing::CallbackSigner, transaction::PayTubeTransaction, PayTubeChannel},
+    setup::{
+        get_token_account_balance, mint_account, open_channel, system_account, token_account,
+        TestValidatorContext,
+    },
+    solana_sdk::{
+        program_option::COption, program_pack::Pack, pubkey::Pubkey, signature::Keypair,
+        signer::Signer,
+    },
+    spl_associated_token_account::get_associated_token_address,
+    spl_token::state::Account as TokenAccount,
+};
+
+#[test]
+fn test_participants_sign_settlement() {
+    let mint = Pubkey::new_unique();
+
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let alice_token_account_pubkey = get_associated_token_address(&alice_pubkey, &mint);
+
+    let bob_pubkey = bob.pubkey();
+    let bob_token_account_pubkey = get_associated_token_address(&bob_pubkey, &mint);
+
+    let accounts = vec![
+        (mint, mint_account()),
+        (alice_pubkey, system_account(10_000_000)),
+        (
+            alice_token_account_pubkey,
+            token_account(&alice_pubkey, &mint, 10),
+        ),
+        (bob_pubkey, system_account(10_000_000)),
+        (
+            bob_token_account_pubkey,
+            token_account(&bob_pubkey, &mint, 10),
+        ),
+    ];
+
+    let context = TestValidatorContext::start_with_accounts(accounts);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+    let rpc_client = test_validator.get_rpc_client();
+
+    // The channel only holds its own key.
+    let mut paytube_channel = PayTubeChannel::with_signers(
+        payer.insecure_clone(),
+        vec![],
+        test_validator.get_rpc_client(),
+    );
+
+    open_channel(
+        &mut paytube_channel,
+        &rpc_client,
+        &payer,
+        &[&alice, &bob],
+        Some(mint),
+        5,
+    );
+
+    // Alice -> Bob 3
+    let outcomes = paytube_channel
+        .process_paytube_transfers(&[PayTubeTransaction {
+            from: alice_pubkey,
+            to: bob_pubkey,
+            amount: 3,
+            mint: Some(mint),
+            ..Default::default()
+        }])
+        .unwrap();
+    assert_eq!(outcomes, vec![Ok(())]);
+
+    // Revoking the delegations needs the participants' signatures.
+    let mut pending = paytube_channel.prepare_close().unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].missing_signers().len(), 2);
+    assert!(paytube_channel.submit_settlement(&pending).is_err());
+    // Nothing was sent, so there's no settlement to resume.
+    assert_eq!(paytube_channel.unfinished_settlement().unwrap(), None);
+
+    // Alice signs the message herself, Bob through a callback.
+    let signature = alice.sign_message(&pending[0].message_data());
+    pending[0].add_signature(&alice_pubkey, signature).unwrap();
+    let bob_signer =
+        CallbackSigner::new(bob_pubkey, |message: &[u8]| Ok(bob.sign_message(message)));
+    assert!(pending[0].sign(&bob_signer).unwrap());
+    assert!(pending[0].is_complete());
+
+    paytube_channel.submit_settlement(&pending).unwrap();
+
+    // Ledger:
+    // Alice:   10 - 3  = 7
+    // Bob:     10 + 3  = 13
+    for (token_account_pubkey, balance) in [
+        (alice_token_account_pubkey, 7),
+        (bob_token_account_pubkey, 13),
+    ] {
+        let account = rpc_client.get_account(&token_account_pubkey).unwrap();
+        assert_eq!(get_token_account_balance(account.clone()), balance);
+        let state = TokenAccount::unpack(&account.data).unwrap();
+        assert_eq!(state.delegate, COption::None);
+    }
+}
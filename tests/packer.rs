@@ -0,0 +1,96 @@
+use {
+    paytube_svm::{
+        packer::{transaction_size, SettlementPacker},
+        signing::PendingSettlement,
+    },
+    solana_sdk::{
+        hash::Hash,
+        instruction::Instruction,
+        message::{AddressLookupTableAccount, VersionedMessage},
+        packet::PACKET_DATA_SIZE,
+        pubkey::Pubkey,
+        signature::Keypair,
+        signer::Signer,
+        system_instruction,
+    },
+};
+
+#[test]
+fn test_pack_settlement() {
+    let payer = Keypair::new();
+    let senders: Vec<Keypair> = (0..30).map(|_| Keypair::new()).collect();
+    let recipients: Vec<Pubkey> = (0..30).map(|_| Pubkey::new_unique()).collect();
+
+    let instructions: Vec<_> = senders
+        .iter()
+        .zip(&recipients)
+        .map(|(sender, recipient)| system_instruction::transfer(&sender.pubkey(), recipient, 1))
+        .collect();
+
+    // Ten transfers from different senders no longer fit in one transaction.
+    let legacy = SettlementPacker::default()
+        .pack(&instructions, &payer.pubkey(), Hash::default())
+        .unwrap();
+    assert!(legacy.len() > 3);
+    assert!(legacy
+        .iter()
+        .all(|message| matches!(message, VersionedMessage::Legacy(_))));
+
+    let mut packed = 0;
+    for message in &legacy {
+        assert!(transaction_size(message) <= PACKET_DATA_SIZE);
+        packed += message.instructions().len();
+
+        // Only the payer and the senders of its own transfers sign.
+        let mut pending = PendingSettlement::from_message(message.clone());
+        assert_eq!(pending.signers().len(), message.instructions().len() + 1);
+        assert!(pending.sign(&payer).unwrap());
+        for sender in &senders {
+            pending.sign(sender).unwrap();
+        }
+        assert!(pending.is_complete());
+    }
+    assert_eq!(packed, instructions.len());
+
+    // Looking up the recipients saves 31 bytes per transfer.
+    let lookup_table = AddressLookupTableAccount {
+        key: Pubkey::new_unique(),
+        addresses: recipients,
+    };
+    let versioned = SettlementPacker::with_lookup_tables(vec![lookup_table])
+        .pack(&instructions, &payer.pubkey(), Hash::default())
+        .unwrap();
+    assert!(versioned.len() < legacy.len());
+    assert!(versioned
+        .iter()
+        .all(|message| matches!(message, VersionedMessage::V0(_))
+            && transaction_size(message) <= PACKET_DATA_SIZE));
+    assert_eq!(
+        versioned
+            .iter()
+            .map(|message| message.instructions().len())
+            .sum::<usize>(),
+        instructions.len()
+    );
+}
+
+#[test]
+fn test_pack_respects_compute_limit() {
+    let payer = Pubkey::new_unique();
+    let program_id = Pubkey::new_unique();
+    let instructions: Vec<_> = (0..10)
+        .map(|_| Instruction::new_with_bytes(program_id, &[], vec![]))
+        .collect();
+
+    // Unknown programs are budgeted the default 200k compute units each.
+    let messages = SettlementPacker::default()
+        .pack(&instructions, &payer, Hash::default())
+        .unwrap();
+    assert_eq!(
+        messages
+            .iter()
+            .map(|message| message.instructions().len())
+            .collect::<Vec<_>>(),
+        vec![7, 3]
+    );
+}
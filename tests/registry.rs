@@ -0,0 +1,42 @@
+use paytube_svm::{
+    game_traits::{GameEngine, GameEngineRegistry},
+    games::{register_builtin_games, RPSEngine},
+};
+
+#[test]
+fn test_registry_discovery() {
+    let mut registry = GameEngineRegistry::new();
+    register_builtin_games(&mut registry).unwrap();
+
+    let engines = registry.list_engines();
+    assert_eq!(engines.len(), 2);
+    assert_eq!(
+        engines[0].versioned_game_type_id,
+        "best_of_3_rock_paper_scissors@1"
+    );
+    assert_eq!(engines[1].versioned_game_type_id, "rock_paper_scissors@1");
+    assert_eq!(engines[1].min_players, 2);
+    assert_eq!(engines[1].max_players, 2);
+
+    // Duplicate registrations are rejected rather than overwritten.
+    assert!(registry
+        .register_engine(Box::new(RPSEngine::new()))
+        .is_err());
+
+    // Unversioned ids resolve to the latest registered version.
+    let engine = registry
+        .get_engine(&"rock_paper_scissors".to_string())
+        .unwrap();
+    assert_eq!(engine.versioned_game_type_id(), "rock_paper_scissors@1");
+
+    assert_eq!(
+        registry
+            .unregister_engine(&"rock_paper_scissors".to_string())
+            .len(),
+        1
+    );
+    assert!(registry
+        .get_engine(&"rock_paper_scissors@1".to_string())
+        .is_none());
+    assert_eq!(registry.list_engines().len(), 1);
+}
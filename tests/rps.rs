@@ -1,7 +1,8 @@
 mod setup;
 
 use paytube_svm::{
-transaction_two::{Choice, RpsTransaction},
+    game_traits::derive_game_instance_id,
+    transaction_two::{Choice, RpsTransaction},
     PlayChannel,
 };
 use setup::{system_account, TestValidatorContext};
@@ -29,17 +30,13 @@ fn test_rps() {
         .parse()
         .unwrap();
 
+    let program_path = std::env::current_dir()
+        .unwrap()
+        .join("rock_paper_scissors.so");
     let accounts = vec![
         (uba_pubkey, system_account(10_000_000)),
         (clem_pubkey, system_account(10_000_000)),
-        (program_account(
-            program_id,
-            std::env::current_dir()
-                .unwrap()
-                .join("rock_paper_scissors.so")
-                .to_str()
-                .unwrap(),
-        )),
+        (program_account(program_id, program_path.to_str().unwrap())),
     ];
 
     let context = TestValidatorContext::start_with_accounts(accounts);
@@ -52,42 +49,46 @@ fn test_rps() {
     let play_channel = PlayChannel::new(
         vec![payer, uba.insecure_clone(), clem.insecure_clone()],
         rpc_client,
-    );
-    let game_pda = Pubkey::find_program_address(
-        &[b"game", &uba_pubkey.to_bytes(), &2u64.to_le_bytes()],
-        &program_id,
-    );
-    println!("{}", game_pda.0);
+    )
+    .with_program(program_id, read_file(&program_path));
+    let game_id = 2;
+    let game_pda = derive_game_instance_id(&program_id, &uba_pubkey, game_id);
+    println!("{}", game_pda);
 
-    play_channel.process_plays(&[
-        //Initialize Game Play
-        RpsTransaction {
-            game: game_pda.0,
-            player: uba_pubkey,
-            player_two: Some(clem_pubkey),
-            choice: Choice::Paper,
-            program_id,
-            first_tx: true
-        },
-        //Make first move
-        RpsTransaction {
-            game: game_pda.0,
-            player: uba_pubkey,
-            player_two: None,
-            choice: Choice::Paper,
-            program_id,
-            first_tx: false
-        },
-        //Make second move
-        RpsTransaction {
-            game: game_pda.0,
-            player: clem_pubkey,
-            player_two: None,
-            choice: Choice::Paper,
-            program_id,
-            first_tx: false
-        },
-    ]);
+    play_channel
+        .process_plays(&[
+            //Initialize Game Play
+            RpsTransaction {
+                game: game_pda,
+                player: uba_pubkey,
+                player_two: Some(clem_pubkey),
+                choice: Choice::Paper,
+                program_id,
+                first_tx: true,
+                game_id,
+            },
+            //Make first move
+            RpsTransaction {
+                game: game_pda,
+                player: uba_pubkey,
+                player_two: None,
+                choice: Choice::Paper,
+                program_id,
+                first_tx: false,
+                game_id,
+            },
+            //Make second move
+            RpsTransaction {
+                game: game_pda,
+                player: clem_pubkey,
+                player_two: None,
+                choice: Choice::Paper,
+                program_id,
+                first_tx: false,
+                game_id,
+            },
+        ])
+        .unwrap();
 }
 
 pub fn program_account(program_id: Pubkey, program_path: &str) -> (Pubkey, AccountSharedData) {
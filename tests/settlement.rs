@@ -0,0 +1,315 @@
+mod setup;
+
+use {
+    paytube_svm::{
+        escrow::StakeEscrow,
+        events::ChannelEvent,
+        game_channel::{PlayChannel, SettlementConfig},
+        game_traits::GameEngineRegistry,
+        games::{rock_paper_scissors::RPSChoice, RPSEngine},
+        token::MintInfo,
+    },
+    setup::{mint_account, rps_move, system_account, token_account, TestValidatorContext},
+    solana_sdk::{
+        instruction::Instruction, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey,
+        signature::Keypair, signer::Signer, system_instruction, transaction::Transaction,
+    },
+    std::time::Duration,
+};
+
+#[test]
+fn test_auto_settle_wagered_game() {
+    let authority = Keypair::new();
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice.pubkey(), system_account(2 * LAMPORTS_PER_SOL)),
+        (bob.pubkey(), system_account(2 * LAMPORTS_PER_SOL)),
+    ]);
+    let rpc_client = context.test_validator.get_rpc_client();
+    let escrow = StakeEscrow::sol(authority.pubkey());
+
+    let mut registry = GameEngineRegistry::new();
+    registry
+        .register_engine(Box::new(RPSEngine::new()))
+        .unwrap();
+    let mut channel = PlayChannel::with_registry(
+        vec![context.payer.insecure_clone(), authority.insecure_clone()],
+        context.test_validator.get_rpc_client(),
+        registry,
+    )
+    .with_escrow(escrow.clone())
+    .with_auto_settlement(SettlementConfig {
+        retry_delay: Duration::from_millis(100),
+        ..Default::default()
+    });
+    let events = channel.subscribe();
+
+    let game = channel
+        .create_game(
+            &"rock_paper_scissors".to_string(),
+            &[alice.pubkey(), bob.pubkey()],
+            LAMPORTS_PER_SOL,
+            None,
+        )
+        .unwrap();
+    assert!(matches!(
+        events.try_recv().unwrap(),
+        ChannelEvent::GameCreated { .. }
+    ));
+
+    // Stakes must be escrowed before they can be committed.
+    assert!(channel
+        .commit_stake(&game, alice.pubkey(), LAMPORTS_PER_SOL)
+        .is_err());
+    for player in [&alice, &bob] {
+        let instructions = escrow
+            .deposit_instructions(&game, &player.pubkey(), LAMPORTS_PER_SOL)
+            .unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&context.payer.pubkey()),
+            &[&context.payer, player],
+            rpc_client.get_latest_blockhash().unwrap(),
+        );
+        rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .unwrap();
+        channel
+            .commit_stake(&game, player.pubkey(), LAMPORTS_PER_SOL)
+            .unwrap();
+    }
+
+    channel
+        .process_game_move(&rps_move(game, alice.pubkey(), RPSChoice::Paper))
+        .unwrap();
+    channel
+        .process_game_move(&rps_move(game, bob.pubkey(), RPSChoice::Rock))
+        .unwrap();
+
+    // The whole pot went to the winner and the escrow is empty.
+    assert_eq!(
+        rpc_client.get_balance(&alice.pubkey()).unwrap(),
+        3 * LAMPORTS_PER_SOL
+    );
+    assert_eq!(
+        rpc_client.get_balance(&bob.pubkey()).unwrap(),
+        LAMPORTS_PER_SOL
+    );
+    for player in [&alice, &bob] {
+        let deposit = escrow.deposit_address(&game, &player.pubkey());
+        assert_eq!(rpc_client.get_balance(&deposit).unwrap(), 0);
+    }
+
+    let archived = channel.game_manager.get_archived_game(&game).unwrap();
+    assert_eq!(archived.settlement_signatures.len(), 1);
+    let signature = archived.settlement_signatures[0];
+    assert!(events.try_iter().any(|event| event
+        == ChannelEvent::SettlementConfirmed {
+            game_instance_id: game,
+            signature,
+        }));
+
+    // Settling twice would pay out twice.
+    assert!(channel
+        .settle_game(&game, &SettlementConfig::default())
+        .is_err());
+}
+
+#[test]
+fn test_resume_partial_settlement() {
+    let authority = Keypair::new();
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice.pubkey(), system_account(2 * LAMPORTS_PER_SOL)),
+        (bob.pubkey(), system_account(2 * LAMPORTS_PER_SOL)),
+    ]);
+    let rpc_client = context.test_validator.get_rpc_client();
+    let escrow = StakeEscrow::sol(authority.pubkey());
+    let send = |instructions: &[Instruction], signer: &Keypair| {
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&context.payer.pubkey()),
+            &[&context.payer, signer],
+            rpc_client.get_latest_blockhash().unwrap(),
+        );
+        rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .unwrap();
+    };
+
+    let mut registry = GameEngineRegistry::new();
+    registry
+        .register_engine(Box::new(RPSEngine::new()))
+        .unwrap();
+    let mut channel = PlayChannel::with_registry(
+        vec![context.payer.insecure_clone(), authority.insecure_clone()],
+        context.test_validator.get_rpc_client(),
+        registry,
+    )
+    .with_escrow(escrow.clone())
+    .with_auto_settlement(SettlementConfig {
+        retry_delay: Duration::from_millis(100),
+        ..Default::default()
+    });
+    let events = channel.subscribe();
+
+    let game = channel
+        .create_game(
+            &"rock_paper_scissors".to_string(),
+            &[alice.pubkey(), bob.pubkey()],
+            LAMPORTS_PER_SOL,
+            None,
+        )
+        .unwrap();
+    for player in [&alice, &bob] {
+        send(
+            &escrow
+                .deposit_instructions(&game, &player.pubkey(), LAMPORTS_PER_SOL)
+                .unwrap(),
+            player,
+        );
+        channel
+            .commit_stake(&game, player.pubkey(), LAMPORTS_PER_SOL)
+            .unwrap();
+    }
+
+    // Bob's deposit is emptied behind the channel's back, so paying back
+    // his stake fails while Alice's goes through.
+    send(
+        &[escrow
+            .transfer_instruction(&game, &bob.pubkey(), &authority.pubkey(), LAMPORTS_PER_SOL)
+            .unwrap()],
+        &authority,
+    );
+
+    channel
+        .process_game_move(&rps_move(game, alice.pubkey(), RPSChoice::Rock))
+        .unwrap();
+    // A draw pays every stake back.
+    channel
+        .process_game_move(&rps_move(game, bob.pubkey(), RPSChoice::Rock))
+        .unwrap();
+    assert!(events
+        .try_iter()
+        .any(|event| matches!(event, ChannelEvent::SettlementFailed { .. })));
+
+    assert_eq!(
+        rpc_client.get_balance(&alice.pubkey()).unwrap(),
+        2 * LAMPORTS_PER_SOL
+    );
+    assert_eq!(
+        rpc_client.get_balance(&bob.pubkey()).unwrap(),
+        LAMPORTS_PER_SOL
+    );
+    let archived = channel.game_manager.get_archived_game(&game).unwrap();
+    assert_eq!(archived.settlement_signatures.len(), 1);
+
+    // Once the deposit is back, settling again only pays out Bob.
+    let bob_deposit = escrow.deposit_address(&game, &bob.pubkey());
+    send(
+        &[system_instruction::transfer(
+            &authority.pubkey(),
+            &bob_deposit,
+            LAMPORTS_PER_SOL,
+        )],
+        &authority,
+    );
+    let signatures = channel
+        .settle_game(&game, &SettlementConfig::default())
+        .unwrap();
+    assert_eq!(signatures.len(), 1);
+
+    assert_eq!(
+        rpc_client.get_balance(&alice.pubkey()).unwrap(),
+        2 * LAMPORTS_PER_SOL
+    );
+    assert_eq!(
+        rpc_client.get_balance(&bob.pubkey()).unwrap(),
+        2 * LAMPORTS_PER_SOL
+    );
+    let archived = channel.game_manager.get_archived_game(&game).unwrap();
+    assert!(archived.is_settled());
+    assert!(channel
+        .settle_game(&game, &SettlementConfig::default())
+        .is_err());
+}
+
+#[test]
+fn test_token_stakes_back_unsettled_games() {
+    let authority = Keypair::new();
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+    let mint = Pubkey::new_unique();
+    let mint_info = MintInfo::from_account(&mint, &mint_account()).unwrap();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (mint, mint_account()),
+        (alice.pubkey(), system_account(LAMPORTS_PER_SOL)),
+        (
+            mint_info.token_account(&alice.pubkey()),
+            token_account(&alice.pubkey(), &mint, 10),
+        ),
+        (bob.pubkey(), system_account(LAMPORTS_PER_SOL)),
+        (
+            mint_info.token_account(&bob.pubkey()),
+            token_account(&bob.pubkey(), &mint, 10),
+        ),
+    ]);
+    let rpc_client = context.test_validator.get_rpc_client();
+    let escrow = StakeEscrow::token(authority.pubkey(), mint_info);
+    let approve = |game, player: &Keypair, amount| {
+        let transaction = Transaction::new_signed_with_payer(
+            &escrow
+                .deposit_instructions(&game, &player.pubkey(), amount)
+                .unwrap(),
+            Some(&context.payer.pubkey()),
+            &[&context.payer, player],
+            rpc_client.get_latest_blockhash().unwrap(),
+        );
+        rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .unwrap();
+    };
+
+    let mut registry = GameEngineRegistry::new();
+    registry
+        .register_engine(Box::new(RPSEngine::new()))
+        .unwrap();
+    let mut channel = PlayChannel::with_registry(
+        vec![context.payer.insecure_clone(), authority.insecure_clone()],
+        context.test_validator.get_rpc_client(),
+        registry,
+    )
+    .with_escrow(escrow.clone());
+
+    let players = [alice.pubkey(), bob.pubkey()];
+    let first = channel
+        .create_game(&"rock_paper_scissors".to_string(), &players, 5, None)
+        .unwrap();
+    for player in [&alice, &bob] {
+        approve(first, player, 5);
+        channel.commit_stake(&first, player.pubkey(), 5).unwrap();
+    }
+    channel
+        .process_game_move(&rps_move(first, alice.pubkey(), RPSChoice::Paper))
+        .unwrap();
+    channel
+        .process_game_move(&rps_move(first, bob.pubkey(), RPSChoice::Rock))
+        .unwrap();
+
+    // The first game is over but not paid out, so its stakes still hold
+    // the delegation, whether or not it's archived.
+    let second = channel
+        .create_game(&"rock_paper_scissors".to_string(), &players, 5, None)
+        .unwrap();
+    assert!(channel.commit_stake(&second, alice.pubkey(), 5).is_err());
+    channel.game_manager.archive_game(&first).unwrap();
+    assert!(channel.commit_stake(&second, alice.pubkey(), 5).is_err());
+
+    approve(second, &alice, 10);
+    channel.commit_stake(&second, alice.pubkey(), 5).unwrap();
+}
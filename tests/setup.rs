@@ -1,16 +1,31 @@
 #![allow(unused)]
 
 use {
+    borsh::BorshSerialize,
+    paytube_svm::{
+        channel::ChannelDeposit,
+        game_traits::{GameConfig, GameMove, WageringConfig, WageringType},
+        games::rock_paper_scissors::RPSChoice,
+        PayTubeChannel,
+    },
+    solana_client::rpc_client::RpcClient,
     solana_sdk::{
         account::{Account, AccountSharedData, ReadableAccount},
         epoch_schedule::EpochSchedule,
         program_pack::Pack,
         pubkey::Pubkey,
-        signature::Keypair,
+        signature::{Keypair, Signature},
+        signer::Signer,
         system_program,
+        transaction::Transaction,
     },
     solana_test_validator::{TestValidator, TestValidatorGenesis},
     spl_token::state::{Account as TokenAccount, Mint},
+    spl_token_2022::extension::{
+        transfer_fee::{TransferFeeAmount, TransferFeeConfig},
+        BaseStateWithExtensionsMut, ExtensionType, StateWithExtensionsMut,
+    },
+    std::collections::HashMap,
 };
 
 const SLOTS_PER_EPOCH: u64 = 50;
@@ -92,3 +107,132 @@ pub fn token_account(owner: &Pubkey, mint: &Pubkey, amount: u64) -> AccountShare
     account.set_data_from_slice(&data);
     account
 }
+
+pub fn token_2022_mint_account(decimals: u8, transfer_fee_basis_points: u16) -> AccountSharedData {
+    let len = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[
+        ExtensionType::TransferFeeConfig,
+    ])
+    .unwrap();
+    let mut data = vec![0; len];
+    let mut state =
+        StateWithExtensionsMut::<spl_token_2022::state::Mint>::unpack_uninitialized(&mut data)
+            .unwrap();
+    let config = state.init_extension::<TransferFeeConfig>(true).unwrap();
+    for transfer_fee in [
+        &mut config.older_transfer_fee,
+        &mut config.newer_transfer_fee,
+    ] {
+        transfer_fee.maximum_fee = u64::MAX.into();
+        transfer_fee.transfer_fee_basis_points = transfer_fee_basis_points.into();
+    }
+    state.base = spl_token_2022::state::Mint {
+        supply: 100_000_000,
+        decimals,
+        is_initialized: true,
+        ..Default::default()
+    };
+    state.pack_base();
+    state.init_account_type().unwrap();
+
+    let mut account = AccountSharedData::new(100_000_000, data.len(), &spl_token_2022::id());
+    account.set_data_from_slice(&data);
+    account
+}
+
+pub fn token_2022_account(owner: &Pubkey, mint: &Pubkey, amount: u64) -> AccountSharedData {
+    let len = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(&[
+        ExtensionType::TransferFeeAmount,
+    ])
+    .unwrap();
+    let mut data = vec![0; len];
+    let mut state =
+        StateWithExtensionsMut::<spl_token_2022::state::Account>::unpack_uninitialized(&mut data)
+            .unwrap();
+    state.init_extension::<TransferFeeAmount>(true).unwrap();
+    state.base = spl_token_2022::state::Account {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: spl_token_2022::state::AccountState::Initialized,
+        ..Default::default()
+    };
+    state.pack_base();
+    state.init_account_type().unwrap();
+
+    let mut account = AccountSharedData::new(100_000_000, data.len(), &spl_token_2022::id());
+    account.set_data_from_slice(&data);
+    account
+}
+
+/// Lock up a deposit of `amount` from each of `owners`, paid for by `payer`,
+/// then open `channel` between them.
+pub fn open_channel(
+    channel: &mut PayTubeChannel,
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    owners: &[&Keypair],
+    mint: Option<Pubkey>,
+    amount: u64,
+) {
+    let deposits: Vec<ChannelDeposit> = owners
+        .iter()
+        .map(|owner| ChannelDeposit {
+            owner: owner.pubkey(),
+            mint,
+            amount,
+        })
+        .collect();
+    for (deposit, owner) in deposits.iter().zip(owners) {
+        let transaction = Transaction::new_signed_with_payer(
+            &channel.deposit_instructions(deposit).unwrap(),
+            Some(&payer.pubkey()),
+            &[payer, *owner],
+            rpc_client.get_latest_blockhash().unwrap(),
+        );
+        rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .unwrap();
+    }
+    let participants: Vec<Pubkey> = deposits.iter().map(|deposit| deposit.owner).collect();
+    channel.open(&participants, &deposits).unwrap();
+}
+
+/// A game of Rock-Paper-Scissors without stakes.
+pub fn rps_config() -> GameConfig {
+    GameConfig {
+        game_type_id: "rock_paper_scissors".to_string(),
+        max_players: 2,
+        min_players: 2,
+        timeout_seconds: 300,
+        stake_amount: 0,
+        custom_config: HashMap::new(),
+        game_id: None,
+        wagering_config: None,
+    }
+}
+
+/// A game of Rock-Paper-Scissors where both players stake `stake`, and the
+/// winner takes it all.
+pub fn rps_wager_config(stake: u64) -> GameConfig {
+    GameConfig {
+        stake_amount: stake,
+        wagering_config: Some(WageringConfig {
+            wagering_type: WageringType::WinnerTakesAll,
+            min_stake: stake,
+            max_stake: None,
+            equal_stakes: true,
+        }),
+        ..rps_config()
+    }
+}
+
+pub fn rps_move(game_instance_id: Pubkey, player_id: Pubkey, choice: RPSChoice) -> GameMove {
+    GameMove {
+        game_instance_id,
+        player_id,
+        move_id: 0,
+        move_data: choice.try_to_vec().unwrap(),
+        signature: Signature::default(),
+        timestamp: 0,
+    }
+}
@@ -0,0 +1,174 @@
+mod setup;
+
+use {
+    paytube_svm::{
+        escrow::StakeEscrow,
+        game_channel::PlayChannel,
+        game_traits::GameEngineRegistry,
+        games::{rock_paper_scissors::RPSChoice, RPSEngine},
+        transaction::PayTubeTransaction,
+        PayTubeChannel,
+    },
+    setup::{open_channel, rps_move, system_account, TestValidatorContext},
+    solana_sdk::{
+        native_token::LAMPORTS_PER_SOL, signature::Keypair, signer::Signer,
+        transaction::Transaction,
+    },
+};
+
+#[test]
+fn test_simulate_close() {
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(10_000_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+    let rpc_client = test_validator.get_rpc_client();
+
+    let mut paytube_channel = PayTubeChannel::new(
+        vec![
+            payer.insecure_clone(),
+            alice.insecure_clone(),
+            bob.insecure_clone(),
+        ],
+        test_validator.get_rpc_client(),
+    );
+
+    open_channel(
+        &mut paytube_channel,
+        &rpc_client,
+        &payer,
+        &[&alice, &bob],
+        None,
+        5_000_000,
+    );
+
+    paytube_channel
+        .process_paytube_transfers(&[PayTubeTransaction {
+            from: alice_pubkey,
+            to: bob_pubkey,
+            amount: 2_000_000,
+            mint: None,
+            ..Default::default()
+        }])
+        .unwrap();
+
+    let pending = paytube_channel.plan_close().unwrap();
+    let reports = paytube_channel.simulate_settlement(&pending).unwrap();
+    assert_eq!(reports.len(), pending.len());
+    assert!(reports.iter().all(|report| report.is_success()));
+    assert!(reports.iter().all(|report| report.compute_units.is_some()));
+
+    // The simulated payouts.
+    let post_balance = |address| {
+        reports
+            .iter()
+            .flat_map(|report| &report.post_balances)
+            .find(|balance| balance.address == address)
+            .unwrap()
+            .lamports
+    };
+    assert_eq!(post_balance(alice_pubkey), 8_000_000);
+    assert_eq!(post_balance(bob_pubkey), 12_000_000);
+
+    // Nothing was sent, and the channel is still open.
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 5_000_000);
+    assert_eq!(
+        paytube_channel.balance(&alice_pubkey, None),
+        Some(3_000_000)
+    );
+
+    paytube_channel.close().unwrap();
+    assert_eq!(rpc_client.get_balance(&alice_pubkey).unwrap(), 8_000_000);
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 12_000_000);
+}
+
+#[test]
+fn test_simulate_game_settlement() {
+    let authority = Keypair::new();
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice.pubkey(), system_account(2 * LAMPORTS_PER_SOL)),
+        (bob.pubkey(), system_account(2 * LAMPORTS_PER_SOL)),
+    ]);
+    let rpc_client = context.test_validator.get_rpc_client();
+    let escrow = StakeEscrow::sol(authority.pubkey());
+
+    let mut registry = GameEngineRegistry::new();
+    registry
+        .register_engine(Box::new(RPSEngine::new()))
+        .unwrap();
+    let mut channel = PlayChannel::with_registry(
+        vec![context.payer.insecure_clone(), authority.insecure_clone()],
+        context.test_validator.get_rpc_client(),
+        registry,
+    )
+    .with_escrow(escrow.clone());
+
+    let game = channel
+        .create_game(
+            &"rock_paper_scissors".to_string(),
+            &[alice.pubkey(), bob.pubkey()],
+            LAMPORTS_PER_SOL,
+            None,
+        )
+        .unwrap();
+    for player in [&alice, &bob] {
+        let instructions = escrow
+            .deposit_instructions(&game, &player.pubkey(), LAMPORTS_PER_SOL)
+            .unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&context.payer.pubkey()),
+            &[&context.payer, player],
+            rpc_client.get_latest_blockhash().unwrap(),
+        );
+        rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .unwrap();
+        channel
+            .commit_stake(&game, player.pubkey(), LAMPORTS_PER_SOL)
+            .unwrap();
+    }
+
+    channel
+        .process_game_move(&rps_move(game, alice.pubkey(), RPSChoice::Paper))
+        .unwrap();
+    channel
+        .process_game_move(&rps_move(game, bob.pubkey(), RPSChoice::Rock))
+        .unwrap();
+
+    // Only archived games are settled.
+    assert!(channel.simulate_settlement(&game).is_err());
+    channel.game_manager.archive_game(&game).unwrap();
+
+    let reports = channel.simulate_settlement(&game).unwrap();
+    assert_eq!(reports.len(), channel.plan_settlement(&game).unwrap().len());
+    assert!(reports.iter().all(|report| report.is_success()));
+
+    // The simulated payout of the whole pot to the winner.
+    let post_balance = reports
+        .iter()
+        .flat_map(|report| &report.post_balances)
+        .find(|balance| balance.address == alice.pubkey())
+        .unwrap()
+        .lamports;
+    assert_eq!(post_balance, 3 * LAMPORTS_PER_SOL);
+
+    // Nothing was sent.
+    assert_eq!(
+        rpc_client.get_balance(&alice.pubkey()).unwrap(),
+        LAMPORTS_PER_SOL
+    );
+    let deposit = escrow.deposit_address(&game, &bob.pubkey());
+    assert_eq!(rpc_client.get_balance(&deposit).unwrap(), LAMPORTS_PER_SOL);
+}
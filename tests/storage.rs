@@ -0,0 +1,159 @@
+mod setup;
+
+use {
+    paytube_svm::{
+        clock::ManualClock,
+        game_traits::{
+            derive_game_instance_id, GameEngineRegistry, GameMove, GameStateManager,
+            RetentionPolicy,
+        },
+        games::{
+            rock_paper_scissors::{RPSChoice, RPS_PROGRAM_ID},
+            RPSEngine,
+        },
+        storage::FileStorage,
+    },
+    setup::{rps_config, rps_move},
+    solana_sdk::{
+        account::AccountSharedData, pubkey::Pubkey, signature::Signature, system_program,
+    },
+    std::{fs::OpenOptions, io::Write, sync::Arc},
+};
+
+fn registry() -> GameEngineRegistry {
+    let mut registry = GameEngineRegistry::new();
+    registry
+        .register_engine(Box::new(RPSEngine::new()))
+        .unwrap();
+    registry
+}
+
+#[test]
+fn test_recover_game_state_from_disk() {
+    let dir = std::env::temp_dir().join(format!("playchannel-{}", Pubkey::new_unique()));
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let signature = Signature::from([7u8; 64]);
+
+    let config = rps_config();
+
+    let game = {
+        let mut manager = GameStateManager::new(registry());
+        manager
+            .set_storage(Box::new(FileStorage::open(&dir).unwrap()))
+            .unwrap();
+        let game = manager.create_game(&config, &[alice, bob]).unwrap();
+        manager
+            .process_move(&GameMove {
+                signature,
+                ..rps_move(game, alice, RPSChoice::Rock)
+            })
+            .unwrap();
+        manager
+            .record_accounts(&[(
+                alice,
+                AccountSharedData::new(1_000, 0, &system_program::id()),
+            )])
+            .unwrap();
+        game
+    };
+
+    // Simulate a crash in the middle of appending a record.
+    OpenOptions::new()
+        .append(true)
+        .open(dir.join("log.bin"))
+        .unwrap()
+        .write_all(&[42, 0, 0, 0, 1])
+        .unwrap();
+
+    let mut manager = GameStateManager::recover(
+        registry(),
+        RetentionPolicy::default(),
+        Box::new(FileStorage::open(&dir).unwrap()),
+    )
+    .unwrap();
+
+    let state = manager.get_game_state(&game).unwrap();
+    assert_eq!(state.players, vec![alice, bob]);
+    assert_eq!(state.move_history.len(), 1);
+    assert_eq!(state.move_history[0].signature, signature);
+    assert!(manager.accounts().contains_key(&alice));
+
+    // Records appended after the torn one survive the next restart.
+    let second_game = manager.create_game(&config, &[bob, alice]).unwrap();
+    drop(manager);
+
+    let manager = GameStateManager::recover(
+        registry(),
+        RetentionPolicy::default(),
+        Box::new(FileStorage::open(&dir).unwrap()),
+    )
+    .unwrap();
+    assert_eq!(manager.get_game_state(&game).unwrap().move_history.len(), 1);
+    assert!(manager.get_game_state(&second_game).is_some());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_game_ids_survive_restart() {
+    let dir = std::env::temp_dir().join(format!("playchannel-{}", Pubkey::new_unique()));
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let retention_policy = RetentionPolicy {
+        idle_timeout_seconds: Some(60),
+        ..Default::default()
+    };
+    let recover = || {
+        GameStateManager::recover(
+            registry(),
+            retention_policy.clone(),
+            Box::new(FileStorage::open(&dir).unwrap()),
+        )
+        .unwrap()
+        .with_clock(clock.clone())
+    };
+
+    let mut manager = GameStateManager::with_retention_policy(registry(), retention_policy.clone())
+        .with_clock(clock.clone());
+    manager
+        .set_storage(Box::new(FileStorage::open(&dir).unwrap()))
+        .unwrap();
+    let first = manager.create_game(&rps_config(), &[alice, bob]).unwrap();
+    clock.advance(61);
+    assert_eq!(manager.apply_retention_policy().unwrap(), vec![first]);
+    drop(manager);
+
+    // The evicted game's id isn't handed out again, whether the counter is
+    // replayed from the log or read from a checkpoint.
+    let mut manager = recover();
+    let second = manager.create_game(&rps_config(), &[alice, bob]).unwrap();
+    assert_eq!(second, derive_game_instance_id(&RPS_PROGRAM_ID, &alice, 1));
+    manager.checkpoint().unwrap();
+    drop(manager);
+
+    let mut manager = recover();
+    let third = manager.create_game(&rps_config(), &[alice, bob]).unwrap();
+    assert_eq!(third, derive_game_instance_id(&RPS_PROGRAM_ID, &alice, 2));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_reject_unreadable_log_record() {
+    let dir = std::env::temp_dir().join(format!("playchannel-{}", Pubkey::new_unique()));
+    FileStorage::open(&dir).unwrap();
+
+    // A complete record in an encoding version this build doesn't know.
+    OpenOptions::new()
+        .append(true)
+        .open(dir.join("log.bin"))
+        .unwrap()
+        .write_all(&[2, 0, 0, 0, 255, 0])
+        .unwrap();
+
+    assert!(FileStorage::open(&dir).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,152 @@
+mod setup;
+
+use {
+    paytube_svm::{
+        game_traits::{derive_game_instance_id, GameActionResult, GameConfig, GameEngine},
+        games::{
+            rock_paper_scissors::{RPSChoice, RPS_PROGRAM_ID},
+            RPSProgram, SvmGameEngine,
+        },
+    },
+    setup::{rps_move, system_account, TestValidatorContext},
+    solana_sdk::{
+        account::{Account, AccountSharedData},
+        bpf_loader,
+        pubkey::Pubkey,
+    },
+    std::collections::HashMap,
+};
+
+fn rps_engine(players: &[Pubkey]) -> (TestValidatorContext, SvmGameEngine) {
+    let elf = std::fs::read("rock_paper_scissors.so").unwrap();
+    let program = AccountSharedData::from(Account {
+        lamports: 1_000_000_000,
+        data: elf.clone(),
+        owner: bpf_loader::id(),
+        executable: true,
+        rent_epoch: 0,
+    });
+
+    let mut accounts = vec![(RPS_PROGRAM_ID, program)];
+    accounts.extend(
+        players
+            .iter()
+            .map(|player| (*player, system_account(10_000_000))),
+    );
+    let context = TestValidatorContext::start_with_accounts(accounts);
+    let rpc_client = context.test_validator.get_rpc_client();
+
+    let engine = SvmGameEngine::new(Box::new(RPSProgram::new()), elf, rpc_client).unwrap();
+    (context, engine)
+}
+
+fn config(engine: &SvmGameEngine, game_id: u64) -> GameConfig {
+    GameConfig {
+        game_type_id: engine.game_type_id(),
+        max_players: engine.max_players(),
+        min_players: engine.min_players(),
+        timeout_seconds: 300,
+        stake_amount: 0,
+        custom_config: HashMap::new(),
+        game_id: Some(game_id),
+        wagering_config: None,
+    }
+}
+
+#[test]
+fn test_svm_rps_game() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let (_context, engine) = rps_engine(&[alice, bob]);
+
+    let state = engine
+        .create_game(&config(&engine, 0), &[alice, bob])
+        .unwrap();
+    assert_eq!(
+        state.game_instance_id,
+        derive_game_instance_id(&RPS_PROGRAM_ID, &alice, 0)
+    );
+    assert!(engine
+        .take_modified_accounts()
+        .iter()
+        .any(|(pubkey, _)| *pubkey == state.game_instance_id));
+
+    let game = state.game_instance_id;
+    let first = rps_move(game, alice, RPSChoice::Paper);
+    assert_eq!(
+        engine.validate_move(&state, &first),
+        GameActionResult::Success
+    );
+    let state = engine.apply_move(&state, &first).unwrap();
+    assert!(!state.is_finished);
+
+    // The program only takes one choice per player, from players, and only
+    // choices.
+    assert!(matches!(
+        engine.validate_move(&state, &first),
+        GameActionResult::Failure(_)
+    ));
+    assert!(matches!(
+        engine.validate_move(
+            &state,
+            &rps_move(game, Pubkey::new_unique(), RPSChoice::Rock)
+        ),
+        GameActionResult::Failure(_)
+    ));
+    let mut not_a_choice = rps_move(game, bob, RPSChoice::Rock);
+    not_a_choice.move_data = vec![7];
+    assert!(matches!(
+        engine.validate_move(&state, &not_a_choice),
+        GameActionResult::Failure(_)
+    ));
+
+    let second = rps_move(game, bob, RPSChoice::Rock);
+    assert_eq!(
+        engine.validate_move(&state, &second),
+        GameActionResult::Success
+    );
+    let state = engine.apply_move(&state, &second).unwrap();
+    assert!(state.is_finished);
+    assert_eq!(engine.check_game_end(&state), Some(alice));
+    assert_eq!(state.move_history, vec![first, second]);
+}
+
+#[test]
+fn test_svm_rps_apply_other_move_than_validated() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let (_context, engine) = rps_engine(&[alice, bob]);
+
+    let state = engine
+        .create_game(&config(&engine, 1), &[alice, bob])
+        .unwrap();
+    let game = state.game_instance_id;
+    let state = engine
+        .apply_move(&state, &rps_move(game, alice, RPSChoice::Paper))
+        .unwrap();
+
+    // Validating one move and applying another executes the applied one.
+    assert_eq!(
+        engine.validate_move(&state, &rps_move(game, bob, RPSChoice::Rock)),
+        GameActionResult::Success
+    );
+    let state = engine
+        .apply_move(&state, &rps_move(game, bob, RPSChoice::Scissors))
+        .unwrap();
+    assert!(state.is_finished);
+    assert_eq!(engine.check_game_end(&state), Some(bob));
+
+    // A draw finishes without a winner.
+    let state = engine
+        .create_game(&config(&engine, 2), &[alice, bob])
+        .unwrap();
+    let game = state.game_instance_id;
+    let state = engine
+        .apply_move(&state, &rps_move(game, alice, RPSChoice::Rock))
+        .unwrap();
+    let state = engine
+        .apply_move(&state, &rps_move(game, bob, RPSChoice::Rock))
+        .unwrap();
+    assert!(state.is_finished);
+    assert_eq!(engine.check_game_end(&state), None);
+}
@@ -0,0 +1,98 @@
+mod setup;
+
+use {
+    paytube_svm::{transaction::PayTubeTransaction, PayTubeChannel},
+    setup::{system_account, token_2022_account, token_2022_mint_account, TestValidatorContext},
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer},
+    spl_associated_token_account::get_associated_token_address_with_program_id,
+    spl_token_2022::{
+        extension::{
+            transfer_fee::TransferFeeAmount, BaseStateWithExtensions, StateWithExtensions,
+        },
+        state::Account as TokenAccount,
+    },
+};
+
+/// A token account's balance, and the transfer fees withheld in it.
+fn get_balance_and_withheld(rpc_client: &RpcClient, token_account: &Pubkey) -> (u64, u64) {
+    let account = rpc_client.get_account(token_account).unwrap();
+    let state = StateWithExtensions::<TokenAccount>::unpack(&account.data).unwrap();
+    let withheld = state
+        .get_extension::<TransferFeeAmount>()
+        .unwrap()
+        .withheld_amount;
+    (state.base.amount, withheld.into())
+}
+
+#[test]
+fn test_token_2022_transfer_fees() {
+    let mint = Pubkey::new_unique();
+
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let alice_token_account_pubkey =
+        get_associated_token_address_with_program_id(&alice_pubkey, &mint, &spl_token_2022::id());
+
+    let bob_pubkey = bob.pubkey();
+    let bob_token_account_pubkey =
+        get_associated_token_address_with_program_id(&bob_pubkey, &mint, &spl_token_2022::id());
+
+    // A 1% transfer fee.
+    let accounts = vec![
+        (mint, token_2022_mint_account(2, 100)),
+        (alice_pubkey, system_account(10_000_000)),
+        (
+            alice_token_account_pubkey,
+            token_2022_account(&alice_pubkey, &mint, 10_000),
+        ),
+        (bob_pubkey, system_account(10_000_000)),
+        (
+            bob_token_account_pubkey,
+            token_2022_account(&bob_pubkey, &mint, 10_000),
+        ),
+    ];
+
+    let context = TestValidatorContext::start_with_accounts(accounts);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+
+    let rpc_client = test_validator.get_rpc_client();
+
+    let paytube_channel = PayTubeChannel::new(vec![payer, alice, bob], rpc_client);
+
+    paytube_channel
+        .process_paytube_transfers(&[
+            // Alice -> Bob 600
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 600,
+                mint: Some(mint),
+                ..Default::default()
+            },
+            // Alice -> Bob 400
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 400,
+                mint: Some(mint),
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+    // Bob receives what was sent less the 1% fee, 594 + 396 = 990, and the
+    // settlement sends 1_000 of Alice's tokens so that exactly that arrives.
+    let rpc_client = test_validator.get_rpc_client();
+    assert_eq!(
+        get_balance_and_withheld(&rpc_client, &alice_token_account_pubkey),
+        (9_000, 0)
+    );
+    assert_eq!(
+        get_balance_and_withheld(&rpc_client, &bob_token_account_pubkey),
+        (10_990, 10)
+    );
+}
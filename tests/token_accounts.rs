@@ -0,0 +1,212 @@
+mod setup;
+
+use {
+    paytube_svm::{transaction::PayTubeTransaction, PayTubeChannel},
+    setup::{
+        get_token_account_balance, mint_account, system_account, token_account,
+        TestValidatorContext,
+    },
+    solana_sdk::{
+        instruction::InstructionError, pubkey::Pubkey, signature::Keypair, signer::Signer,
+        transaction::TransactionError,
+    },
+    spl_associated_token_account::get_associated_token_address,
+};
+
+#[test]
+fn test_create_recipient_token_account() {
+    let mint = Pubkey::new_unique();
+
+    let alice = Keypair::new();
+    let carol = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let alice_token_account_pubkey = get_associated_token_address(&alice_pubkey, &mint);
+
+    // Carol doesn't hold any of the mint's tokens yet.
+    let carol_pubkey = carol.pubkey();
+    let carol_token_account_pubkey = get_associated_token_address(&carol_pubkey, &mint);
+
+    let accounts = vec![
+        (mint, mint_account()),
+        (alice_pubkey, system_account(10_000_000)),
+        (
+            alice_token_account_pubkey,
+            token_account(&alice_pubkey, &mint, 10),
+        ),
+        (carol_pubkey, system_account(10_000_000)),
+    ];
+
+    let context = TestValidatorContext::start_with_accounts(accounts);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+
+    let rpc_client = test_validator.get_rpc_client();
+    assert!(rpc_client.get_account(&carol_token_account_pubkey).is_err());
+
+    let paytube_channel = PayTubeChannel::new(vec![payer, alice, carol], rpc_client);
+
+    let results = paytube_channel
+        .process_paytube_transfers(&[PayTubeTransaction {
+            from: alice_pubkey,
+            to: carol_pubkey,
+            amount: 4,
+            mint: Some(mint),
+            ..Default::default()
+        }])
+        .unwrap();
+    assert_eq!(results, vec![Ok(())]);
+
+    // The settlement created Carol's token account, at the authority's
+    // expense, before paying into it.
+    let rpc_client = test_validator.get_rpc_client();
+    assert_eq!(
+        get_token_account_balance(rpc_client.get_account(&alice_token_account_pubkey).unwrap()),
+        6
+    );
+    assert_eq!(
+        get_token_account_balance(rpc_client.get_account(&carol_token_account_pubkey).unwrap()),
+        4
+    );
+    assert_eq!(rpc_client.get_balance(&carol_pubkey).unwrap(), 10_000_000);
+}
+
+#[test]
+fn test_transfer_to_other_token_account() {
+    let mint = Pubkey::new_unique();
+
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let alice_token_account_pubkey = get_associated_token_address(&alice_pubkey, &mint);
+
+    // Bob holds the mint's tokens in an account other than the associated
+    // token account.
+    let bob_pubkey = bob.pubkey();
+    let bob_token_account_pubkey = Pubkey::new_unique();
+
+    let accounts = vec![
+        (mint, mint_account()),
+        (alice_pubkey, system_account(10_000_000)),
+        (
+            alice_token_account_pubkey,
+            token_account(&alice_pubkey, &mint, 10),
+        ),
+        (bob_pubkey, system_account(10_000_000)),
+        (
+            bob_token_account_pubkey,
+            token_account(&bob_pubkey, &mint, 10),
+        ),
+    ];
+
+    let context = TestValidatorContext::start_with_accounts(accounts);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+
+    let rpc_client = test_validator.get_rpc_client();
+
+    let paytube_channel = PayTubeChannel::new(vec![payer, alice, bob], rpc_client);
+
+    let results = paytube_channel
+        .process_paytube_transfers(&[
+            // Alice -> Bob 3
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 3,
+                mint: Some(mint),
+                to_token_account: Some(bob_token_account_pubkey),
+                ..Default::default()
+            },
+            // Alice -> Bob 1, into an account Bob doesn't hold.
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 1,
+                mint: Some(mint),
+                to_token_account: Some(alice_token_account_pubkey),
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+    assert_eq!(
+        results,
+        vec![
+            Ok(()),
+            Err(TransactionError::InstructionError(
+                0,
+                InstructionError::IllegalOwner
+            )),
+        ]
+    );
+
+    let rpc_client = test_validator.get_rpc_client();
+    assert_eq!(
+        get_token_account_balance(rpc_client.get_account(&alice_token_account_pubkey).unwrap()),
+        7
+    );
+    assert_eq!(
+        get_token_account_balance(rpc_client.get_account(&bob_token_account_pubkey).unwrap()),
+        13
+    );
+    // No associated token account was created for Bob.
+    assert!(rpc_client
+        .get_account(&get_associated_token_address(&bob_pubkey, &mint))
+        .is_err());
+}
+
+#[test]
+fn test_reject_unknown_mint() {
+    let unknown_mint = Pubkey::new_unique();
+
+    let alice = Keypair::new();
+    let bob = Keypair::new();
+
+    let alice_pubkey = alice.pubkey();
+    let bob_pubkey = bob.pubkey();
+
+    let context = TestValidatorContext::start_with_accounts(vec![
+        (alice_pubkey, system_account(10_000_000)),
+        (bob_pubkey, system_account(10_000_000)),
+    ]);
+    let test_validator = &context.test_validator;
+    let payer = context.payer.insecure_clone();
+
+    let rpc_client = test_validator.get_rpc_client();
+
+    let paytube_channel = PayTubeChannel::new(vec![payer, alice, bob], rpc_client);
+
+    // Only the transfer of tokens nobody can find the mint of fails.
+    let results = paytube_channel
+        .process_paytube_transfers(&[
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 3,
+                mint: Some(unknown_mint),
+                ..Default::default()
+            },
+            PayTubeTransaction {
+                from: alice_pubkey,
+                to: bob_pubkey,
+                amount: 1_000_000,
+                mint: None,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+    assert_eq!(
+        results,
+        vec![
+            Err(TransactionError::InstructionError(
+                0,
+                InstructionError::InvalidAccountData
+            )),
+            Ok(()),
+        ]
+    );
+
+    let rpc_client = test_validator.get_rpc_client();
+    assert_eq!(rpc_client.get_balance(&bob_pubkey).unwrap(), 11_000_000);
+}
@@ -0,0 +1,231 @@
+use {
+    paytube_svm::{
+        game_traits::{GameActionResult, GameConfig, GameEngine, GameEngineRegistry, GameMove},
+        games::{load_wasm_games, WasmGameEngine, WasmGameManifest},
+    },
+    solana_sdk::{pubkey::Pubkey, signature::Signature},
+    std::collections::HashMap,
+};
+
+/// A game where moves are a single non-zero byte, and the first player wins
+/// once two moves have been made. The state is the number of moves so far.
+const COUNTER_GAME: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+  (func $alloc (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $heap))
+    (global.set $heap (i32.add (global.get $heap) (local.get $len)))
+    (local.get $ptr))
+  (func $state (param $count i32) (result i64)
+    (local $ptr i32)
+    (local.set $ptr (call $alloc (i32.const 1)))
+    (i32.store8 (local.get $ptr) (local.get $count))
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+      (i64.const 1)))
+  (func (export "create_game") (param i32 i32) (result i64)
+    (call $state (i32.const 0)))
+  (func (export "validate_move")
+    (param $state i32) (param $state_len i32) (param $move i32) (param $move_len i32)
+    (result i32)
+    ;; A move of 255 never finishes.
+    (if (i32.eq (i32.load8_u offset=32 (local.get $move)) (i32.const 255))
+      (then (loop $spin (br $spin))))
+    (i32.eqz
+      (i32.and
+        (i32.eq (local.get $move_len) (i32.const 33))
+        (i32.ne (i32.load8_u offset=32 (local.get $move)) (i32.const 0)))))
+  (func (export "apply_move")
+    (param $state i32) (param $state_len i32) (param $move i32) (param $move_len i32)
+    (result i64)
+    (call $state (i32.add (i32.load8_u (local.get $state)) (i32.const 1))))
+  (func (export "check_game_end") (param $state i32) (param $state_len i32) (result i32)
+    (if (result i32) (i32.ge_u (i32.load8_u (local.get $state)) (i32.const 2))
+      (then (i32.const 0))
+      (else (i32.const -1)))))
+"#;
+
+/// Grows its memory by 64 MiB when a game is created.
+const GREEDY_GAME: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32)
+    (i32.const 1024))
+  (func (export "create_game") (param i32 i32) (result i64)
+    (drop (memory.grow (i32.const 1024)))
+    (i64.const 1)))
+"#;
+
+/// Exports memory, but none of the game functions.
+const EMPTY_GAME: &str = r#"
+(module
+  (memory (export "memory") 1))
+"#;
+
+/// Asks the host for a function.
+const IMPORTING_GAME: &str = r#"
+(module
+  (import "env" "random" (func $random (result i32)))
+  (memory (export "memory") 1))
+"#;
+
+fn manifest(game_type_id: &str) -> WasmGameManifest {
+    WasmGameManifest {
+        game_type_id: game_type_id.to_string(),
+        version: 1,
+        display_name: "Counter".to_string(),
+        description: "First to two moves".to_string(),
+        min_players: 2,
+        max_players: 2,
+    }
+}
+
+fn engine(wat: &str) -> Result<WasmGameEngine, String> {
+    WasmGameEngine::new(manifest("counter"), &wat::parse_str(wat).unwrap())
+}
+
+fn config(engine: &WasmGameEngine) -> GameConfig {
+    GameConfig {
+        game_type_id: engine.game_type_id(),
+        max_players: engine.max_players(),
+        min_players: engine.min_players(),
+        timeout_seconds: 300,
+        stake_amount: 0,
+        custom_config: HashMap::new(),
+        game_id: Some(0),
+        wagering_config: None,
+    }
+}
+
+fn counter_move(player_id: Pubkey, move_id: u64, value: u8) -> GameMove {
+    GameMove {
+        game_instance_id: Pubkey::default(),
+        player_id,
+        move_id,
+        move_data: vec![value],
+        signature: Signature::default(),
+        timestamp: 0,
+    }
+}
+
+#[test]
+fn test_wasm_game() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    let engine = engine(COUNTER_GAME).unwrap();
+    let state = engine.create_game(&config(&engine), &[alice, bob]).unwrap();
+    assert_eq!(state.state_data, vec![0]);
+
+    // The same creator and game id always make the same game, and another
+    // game id a different one.
+    let again = engine.create_game(&config(&engine), &[alice, bob]).unwrap();
+    assert_eq!(again.game_instance_id, state.game_instance_id);
+    let other_config = GameConfig {
+        game_id: Some(1),
+        ..config(&engine)
+    };
+    let other = engine.create_game(&other_config, &[alice, bob]).unwrap();
+    assert_ne!(other.game_instance_id, state.game_instance_id);
+
+    // Zero isn't a move, and only players can move.
+    assert!(matches!(
+        engine.validate_move(&state, &counter_move(alice, 0, 0)),
+        GameActionResult::Failure(_)
+    ));
+    assert!(matches!(
+        engine.validate_move(&state, &counter_move(Pubkey::new_unique(), 0, 1)),
+        GameActionResult::Failure(_)
+    ));
+
+    let first = counter_move(alice, 0, 1);
+    assert_eq!(
+        engine.validate_move(&state, &first),
+        GameActionResult::Success
+    );
+    let state = engine.apply_move(&state, &first).unwrap();
+    assert_eq!(state.state_data, vec![1]);
+    assert!(!state.is_finished);
+
+    let state = engine.apply_move(&state, &counter_move(bob, 1, 7)).unwrap();
+    assert_eq!(state.state_data, vec![2]);
+    assert!(state.is_finished);
+    assert_eq!(engine.check_game_end(&state), Some(alice));
+}
+
+#[test]
+fn test_wasm_game_out_of_fuel() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    let engine = engine(COUNTER_GAME).unwrap().with_fuel(100_000);
+    let state = engine.create_game(&config(&engine), &[alice, bob]).unwrap();
+
+    // The module spins until its fuel runs out, instead of hanging the
+    // channel.
+    assert!(matches!(
+        engine.validate_move(&state, &counter_move(alice, 0, 255)),
+        GameActionResult::Failure(_)
+    ));
+    // Every call gets fresh fuel.
+    assert_eq!(
+        engine.validate_move(&state, &counter_move(alice, 0, 1)),
+        GameActionResult::Success
+    );
+}
+
+#[test]
+fn test_wasm_game_memory_limit() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    let engine = engine(GREEDY_GAME).unwrap();
+    assert!(engine.create_game(&config(&engine), &[alice, bob]).is_err());
+
+    let engine = engine.with_memory_limit(128 * 1024 * 1024);
+    assert!(engine.create_game(&config(&engine), &[alice, bob]).is_ok());
+}
+
+#[test]
+fn test_wasm_game_bad_exports() {
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+
+    assert!(engine(IMPORTING_GAME).is_err());
+
+    let engine = engine(EMPTY_GAME).unwrap();
+    let error = engine
+        .create_game(&config(&engine), &[alice, bob])
+        .unwrap_err();
+    assert!(error.contains("alloc"), "{}", error);
+}
+
+#[test]
+fn test_load_wasm_games_all_or_nothing() {
+    let dir = std::env::temp_dir().join(format!("wasm-games-{}", Pubkey::new_unique()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let wasm = wat::parse_str(COUNTER_GAME).unwrap();
+    std::fs::write(dir.join("counter.wasm"), &wasm).unwrap();
+    std::fs::write(dir.join("counter_copy.wasm"), &wasm).unwrap();
+
+    // Both files register the same game type and version.
+    let manifests = HashMap::from([
+        ("counter".to_string(), manifest("counter")),
+        ("counter_copy".to_string(), manifest("counter")),
+    ]);
+    let mut registry = GameEngineRegistry::new();
+    assert!(load_wasm_games(&mut registry, &dir, &manifests).is_err());
+    assert!(registry.list_engines().is_empty());
+
+    let manifests = HashMap::from([
+        ("counter".to_string(), manifest("counter")),
+        ("counter_copy".to_string(), manifest("counter_copy")),
+    ]);
+    let loaded = load_wasm_games(&mut registry, &dir, &manifests).unwrap();
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(registry.list_engines().len(), 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}